@@ -0,0 +1,129 @@
+use std::fmt::Display;
+
+use http_body_util::{combinators::BoxBody, BodyExt, Full};
+use hyper::{body::Bytes, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+
+/// Errors reported to clients of the Jaem services. Every variant maps to one HTTP status code
+/// and a stable error code that clients can match on, while the message is meant for humans and
+/// may change.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApiError {
+    /// The request is malformed or contains invalid values.
+    InvalidRequest(String),
+    /// The client is not allowed to perform the request, e.g. because a signature is invalid.
+    Forbidden(String),
+    /// The requested resource does not exist.
+    NotFound(String),
+    /// The request conflicts with the current state, e.g. because a user already exists.
+    Conflict(String),
+    /// A precondition like `If-Match` does not hold, because the resource changed in the meantime.
+    PreconditionFailed(String),
+    /// The request body exceeds the given number of bytes.
+    PayloadTooLarge { max_size: usize },
+    /// The client sent too many requests and may retry after the given number of seconds.
+    RateLimited { retry_after: Option<u64> },
+    /// The request could not be processed because of a problem on the server.
+    Internal(String),
+}
+
+/// The JSON body of every error response, e.g.
+/// `{"code": "not_found", "message": "User not found"}`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErrorBody {
+    pub code: String,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::Conflict(_) => StatusCode::CONFLICT,
+            Self::PreconditionFailed(_) => StatusCode::PRECONDITION_FAILED,
+            Self::PayloadTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// The stable error code sent to clients.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::InvalidRequest(_) => "invalid_request",
+            Self::Forbidden(_) => "forbidden",
+            Self::NotFound(_) => "not_found",
+            Self::Conflict(_) => "conflict",
+            Self::PreconditionFailed(_) => "precondition_failed",
+            Self::PayloadTooLarge { .. } => "payload_too_large",
+            Self::RateLimited { .. } => "rate_limited",
+            Self::Internal(_) => "internal_error",
+        }
+    }
+
+    pub fn body(&self) -> ErrorBody {
+        ErrorBody {
+            code: self.code().to_string(),
+            message: self.to_string(),
+        }
+    }
+
+    /// Turns the error into a response with a JSON body.
+    pub fn into_response(self) -> Response<BoxBody<Bytes, hyper::Error>> {
+        let json = serde_json::to_string(&self.body()).unwrap();
+        let mut response = Response::builder()
+            .status(self.status())
+            .header("Content-Type", "application/json");
+        if let Self::RateLimited {
+            retry_after: Some(seconds),
+        } = self
+        {
+            response = response.header("Retry-After", seconds);
+        }
+        response
+            .body(
+                Full::new(Bytes::from(json))
+                    .map_err(|never| match never {})
+                    .boxed(),
+            )
+            .unwrap()
+    }
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidRequest(message)
+            | Self::Forbidden(message)
+            | Self::NotFound(message)
+            | Self::Conflict(message)
+            | Self::PreconditionFailed(message)
+            | Self::Internal(message) => write!(f, "{}", message),
+            Self::PayloadTooLarge { max_size } => write!(
+                f,
+                "Request body exceeds the maximum size of {} bytes",
+                max_size
+            ),
+            Self::RateLimited { .. } => write!(f, "Too many requests"),
+        }
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+/// Errors raised with `anyhow` keep their status if they were created from an `ApiError`. Any
+/// other error, e.g. from I/O, is a problem on the server: it is logged and reported with a
+/// generic message, so that details like file paths are not sent to clients.
+impl From<anyhow::Error> for ApiError {
+    fn from(err: anyhow::Error) -> Self {
+        match err.downcast::<ApiError>() {
+            Ok(err) => err,
+            Err(err) => {
+                tracing::error!(error = format!("{:#}", err), "Request failed");
+                Self::Internal("Internal server error".to_string())
+            }
+        }
+    }
+}
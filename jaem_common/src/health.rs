@@ -0,0 +1,106 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    future::Future,
+    path::Path,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use http_body_util::{combinators::BoxBody, BodyExt, Full};
+use hyper::{body::Bytes, Response, StatusCode};
+use serde::Serialize;
+use serde_json::json;
+use uuid::Uuid;
+
+/// Answers `/healthz`: the process is alive and handles requests.
+pub fn healthz() -> Response<BoxBody<Bytes, hyper::Error>> {
+    json_response(StatusCode::OK, json!({"status": "ok"}))
+}
+
+/// How long the outcome of `/readyz` is reused, so that frequent probes do not touch the disk.
+pub const READINESS_TTL: Duration = Duration::from_secs(5);
+
+/// The checks of `/readyz`. The service is ready if all of them passed.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct Readiness {
+    checks: BTreeMap<&'static str, String>,
+}
+
+impl Readiness {
+    /// Records the result of a check. Failures carry the reason.
+    pub fn check(&mut self, name: &'static str, result: Result<(), String>) {
+        let outcome = match result {
+            Ok(()) => "ok".to_string(),
+            Err(reason) => reason,
+        };
+        // a check that runs several times, e.g. for several directories, keeps its first failure
+        let current = self.checks.entry(name).or_insert_with(|| "ok".to_string());
+        if current == "ok" {
+            *current = outcome;
+        }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.checks.values().all(|outcome| outcome == "ok")
+    }
+
+    /// Answers `200 OK` if the service is ready and `503 Service Unavailable` otherwise, with the
+    /// outcome of every check, e.g.
+    /// `{"status": "not_ready", "checks": {"storage": "ok", "sweeper": "last run 95 seconds ago"}}`.
+    pub fn into_response(self) -> Response<BoxBody<Bytes, hyper::Error>> {
+        let (status, text) = match self.is_ready() {
+            true => (StatusCode::OK, "ready"),
+            false => (StatusCode::SERVICE_UNAVAILABLE, "not_ready"),
+        };
+        json_response(status, json!({"status": text, "checks": self.checks}))
+    }
+}
+
+/// The outcome of the last readiness check, shared by the requests of a service.
+#[derive(Debug, Default)]
+pub struct ReadinessCache {
+    cached: Mutex<Option<(Instant, Readiness)>>,
+}
+
+impl ReadinessCache {
+    /// Returns the outcome of the last check if it is younger than `READINESS_TTL`, and runs the
+    /// check otherwise.
+    pub async fn get_or_check(&self, check: impl Future<Output = Readiness>) -> Readiness {
+        if let Some((checked_at, readiness)) = &*self.cached.lock().unwrap() {
+            if checked_at.elapsed() < READINESS_TTL {
+                return readiness.clone();
+            }
+        }
+        let readiness = check.await;
+        *self.cached.lock().unwrap() = Some((Instant::now(), readiness.clone()));
+        readiness
+    }
+}
+
+/// Checks that files can be created in the directory by creating and removing one. The path and
+/// the error are only logged, since the outcome is public.
+pub fn check_writable(directory: &Path) -> Result<(), String> {
+    let probe = directory.join(format!(".readyz-{}", Uuid::new_v4()));
+    fs::write(&probe, b"")
+        .and_then(|_| fs::remove_file(&probe))
+        .map_err(|err| {
+            tracing::warn!(directory = %directory.display(), "Storage is not writable: {}", err);
+            "not writable".to_string()
+        })
+}
+
+fn json_response(
+    status: StatusCode,
+    body: serde_json::Value,
+) -> Response<BoxBody<Bytes, hyper::Error>> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(
+            Full::new(Bytes::from(body.to_string()))
+                .map_err(|never| match never {})
+                .boxed(),
+        )
+        .unwrap()
+}
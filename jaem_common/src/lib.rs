@@ -0,0 +1,6 @@
+pub mod error;
+pub mod health;
+pub mod logging;
+pub mod metrics;
+pub mod shutdown;
+pub mod tls;
@@ -0,0 +1,150 @@
+use std::{
+    fmt::{Debug, Display},
+    io,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use hyper::{header::HeaderValue, Request, Response};
+use jaem_config::{reload::LiveConfig, LogFormat, LoggingConfig};
+use tracing::{field::Empty, Span};
+use tracing_subscriber::{
+    fmt, layer::SubscriberExt, reload, util::SubscriberInitExt, EnvFilter, Registry,
+};
+use uuid::Uuid;
+
+/// Header with the ID of a request. Clients may send their own ID, e.g. to correlate the logs of
+/// several services, otherwise one is generated. Responses always carry the ID.
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// Request IDs sent by clients are only used if they are at most this long.
+const MAX_REQUEST_ID_LEN: usize = 64;
+
+/// Changes the log level of the running process.
+pub struct LogLevel {
+    handle: reload::Handle<EnvFilter, Registry>,
+}
+
+impl LogLevel {
+    /// Switches to the level of the given settings. The format cannot be changed after `init`.
+    pub fn apply(&self, settings: &LoggingConfig) -> Result<(), anyhow::Error> {
+        self.handle.reload(filter(settings)?)?;
+        Ok(())
+    }
+
+    /// Applies the log level whenever the configuration is reloaded.
+    pub fn follow(self, live_config: &LiveConfig) {
+        live_config.on_reload(move |config, _| {
+            if let Err(err) = self.apply(&config.logging) {
+                tracing::warn!("Keeping the current log level: {:#}", err);
+            }
+        });
+    }
+}
+
+/// Installs the logger of the process, which writes to stderr. Fails if a logger is already
+/// installed.
+pub fn init(settings: &LoggingConfig) -> Result<LogLevel, anyhow::Error> {
+    let (filter, handle) = reload::Layer::new(filter(settings)?);
+    let registry = tracing_subscriber::registry().with(filter);
+    match settings.format {
+        LogFormat::Pretty => registry
+            .with(fmt::layer().with_writer(io::stderr))
+            .try_init()?,
+        LogFormat::Json => registry
+            .with(
+                fmt::layer()
+                    .json()
+                    .flatten_event(true)
+                    .with_span_list(false)
+                    .with_writer(io::stderr),
+            )
+            .try_init()?,
+    }
+    Ok(LogLevel { handle })
+}
+
+fn filter(settings: &LoggingConfig) -> Result<EnvFilter, anyhow::Error> {
+    EnvFilter::builder()
+        .parse(&settings.level)
+        .with_context(|| format!("'{}' is not a valid log level", settings.level))
+}
+
+/// The span of one request, carrying its ID, method, route, status and duration. Only the route
+/// is recorded rather than the path, because paths can contain keys.
+pub struct RequestSpan {
+    span: Span,
+    request_id: HeaderValue,
+    start: Instant,
+}
+
+impl RequestSpan {
+    pub fn new<B>(req: &Request<B>, route: &str) -> RequestSpan {
+        let request_id = req
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .filter(|id| is_valid_request_id(id))
+            .cloned()
+            .unwrap_or_else(|| HeaderValue::from_str(&Uuid::new_v4().to_string()).unwrap());
+        let span = tracing::info_span!(
+            "request",
+            request_id = request_id.to_str().unwrap(),
+            method = %req.method(),
+            route,
+            status = Empty,
+            duration_ms = Empty,
+        );
+        RequestSpan {
+            span,
+            request_id,
+            start: Instant::now(),
+        }
+    }
+
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    /// Records the status and logs the finished request. Adds the request ID to the response and
+    /// returns how long the request took.
+    pub fn finish<B>(self, response: &mut Response<B>) -> Duration {
+        let elapsed = self.start.elapsed();
+        let status = response.status();
+        self.span.record("status", status.as_u16());
+        self.span
+            .record("duration_ms", elapsed.as_secs_f64() * 1000.0);
+        self.span.in_scope(|| match status.is_server_error() {
+            true => tracing::error!("Request failed"),
+            false => tracing::info!("Request finished"),
+        });
+        response
+            .headers_mut()
+            .insert(REQUEST_ID_HEADER, self.request_id);
+        elapsed
+    }
+}
+
+fn is_valid_request_id(id: &HeaderValue) -> bool {
+    id.len() <= MAX_REQUEST_ID_LEN
+        && !id.is_empty()
+        && id
+            .as_bytes()
+            .iter()
+            .all(|byte| byte.is_ascii_alphanumeric() || b"-_.".contains(byte))
+}
+
+/// Logs only the length of key material and message contents, e.g.
+/// `tracing::debug!(key = %Redacted(&key), "Revoked a key")`.
+pub struct Redacted<T: AsRef<[u8]>>(pub T);
+
+impl<T: AsRef<[u8]>> Display for Redacted<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[redacted {} bytes]", self.0.as_ref().len())
+    }
+}
+
+impl<T: AsRef<[u8]>> Debug for Redacted<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
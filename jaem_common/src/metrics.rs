@@ -0,0 +1,142 @@
+use std::{collections::HashMap, time::Duration};
+
+use http_body_util::{combinators::BoxBody, BodyExt, Full};
+use hyper::{body::Bytes, Method, Response, StatusCode};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry, TextEncoder,
+};
+
+use crate::error::ApiError;
+
+/// Why the authentication of a request failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthFailure {
+    /// The timestamp of the signature differs too much from the current time.
+    Expired,
+    /// The signature is missing or was not made with the given key.
+    BadSignature,
+    /// The given public key is not a valid key of a supported algorithm.
+    BadKey,
+    /// The signature was already used for another request.
+    Replayed,
+}
+
+impl AuthFailure {
+    /// The value of the `reason` label.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Expired => "expired",
+            Self::BadSignature => "bad_signature",
+            Self::BadKey => "bad_key",
+            Self::Replayed => "replayed",
+        }
+    }
+}
+
+/// The metrics every service exposes under `/metrics`. Each service has its own registry, so the
+/// combined server reports the metrics of a service on that service's port only.
+pub struct HttpMetrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    request_duration: HistogramVec,
+    auth_failures: IntCounterVec,
+}
+
+impl HttpMetrics {
+    /// Creates the metrics of a service. All of them are prefixed with `jaem_` and carry the name
+    /// of the service in the `service` label.
+    pub fn new(service: &str) -> HttpMetrics {
+        let labels = HashMap::from([("service".to_string(), service.to_string())]);
+        let registry = Registry::new_custom(Some("jaem".to_string()), Some(labels)).unwrap();
+        let requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "Handled requests"),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time it took to handle a request",
+            ),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let auth_failures = IntCounterVec::new(
+            Opts::new("auth_failures_total", "Requests that failed authentication"),
+            &["reason"],
+        )
+        .unwrap();
+        let metrics = HttpMetrics {
+            registry,
+            requests,
+            request_duration,
+            auth_failures,
+        };
+        metrics.register(metrics.requests.clone());
+        metrics.register(metrics.request_duration.clone());
+        metrics.register(metrics.auth_failures.clone());
+        metrics
+    }
+
+    /// Adds a metric of the service to its registry.
+    pub fn register<C: prometheus::core::Collector + 'static>(&self, collector: C) {
+        self.registry.register(Box::new(collector)).unwrap();
+    }
+
+    /// Records a handled request. `route` has to be one of the routes of the service rather than
+    /// the requested path, so that the number of time series stays bounded.
+    pub fn observe_request(
+        &self,
+        method: &Method,
+        route: &str,
+        status: StatusCode,
+        elapsed: Duration,
+    ) {
+        let labels = [method_label(method), route, status.as_str()];
+        self.requests.with_label_values(&labels).inc();
+        self.request_duration
+            .with_label_values(&labels)
+            .observe(elapsed.as_secs_f64());
+    }
+
+    pub fn auth_failure(&self, failure: AuthFailure) {
+        self.auth_failures
+            .with_label_values(&[failure.label()])
+            .inc();
+    }
+
+    /// The metrics in the Prometheus text format.
+    pub fn response(&self) -> Response<BoxBody<Bytes, hyper::Error>> {
+        let encoder = TextEncoder::new();
+        let mut buffer = Vec::new();
+        if encoder
+            .encode(&self.registry.gather(), &mut buffer)
+            .is_err()
+        {
+            return ApiError::Internal("Could not encode the metrics".to_string()).into_response();
+        }
+        Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", encoder.format_type())
+            .body(
+                Full::new(Bytes::from(buffer))
+                    .map_err(|never| match never {})
+                    .boxed(),
+            )
+            .unwrap()
+    }
+}
+
+/// Methods other than the standard ones are counted together.
+fn method_label(method: &Method) -> &'static str {
+    match *method {
+        Method::GET => "GET",
+        Method::POST => "POST",
+        Method::PUT => "PUT",
+        Method::PATCH => "PATCH",
+        Method::DELETE => "DELETE",
+        Method::HEAD => "HEAD",
+        Method::OPTIONS => "OPTIONS",
+        _ => "other",
+    }
+}
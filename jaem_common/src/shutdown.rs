@@ -0,0 +1,29 @@
+use std::time::Duration;
+
+use hyper_util::server::graceful::GracefulShutdown;
+
+/// Completes when the process is asked to stop with Ctrl+C or, on unix, SIGTERM.
+pub async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate()).expect("Could not listen for SIGTERM.");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+/// Asks the connections watched by `connections` to close once their current request is answered
+/// and waits for them, but at most `timeout`. Returns false if connections were still open when
+/// the timeout elapsed.
+pub async fn drain_connections(connections: GracefulShutdown, timeout: Duration) -> bool {
+    tokio::time::timeout(timeout, connections.shutdown())
+        .await
+        .is_ok()
+}
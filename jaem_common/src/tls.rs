@@ -0,0 +1,255 @@
+use std::{
+    fs, io,
+    path::Path,
+    pin::Pin,
+    sync::{Arc, RwLock},
+    task::{Context as TaskContext, Poll},
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{bail, Context};
+use hyper::Request;
+use jaem_config::{TlsConfig, TlsVersion};
+use rustls::{
+    crypto::ring,
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    server::WebPkiClientVerifier,
+    RootCertStore, ServerConfig,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::TcpStream,
+};
+use tokio_rustls::{server::TlsStream, TlsAcceptor};
+
+use crate::error::ApiError;
+
+/// How long a client may take for the TLS handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Whether a connection may use admin endpoints. It is added to the extensions of every request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdminAccess {
+    /// No client certificates are configured, so admin endpoints are not restricted.
+    Open,
+    /// The client presented a certificate signed by one of the configured authorities.
+    Verified,
+    /// Client certificates are configured, but the client did not present one.
+    Denied,
+}
+
+/// Fails with `Forbidden` unless the request may use admin endpoints. Requests without
+/// `AdminAccess` are denied, so a connection that was not classified never gets access.
+pub fn check_admin_access<B>(req: &Request<B>) -> Result<(), ApiError> {
+    match req.extensions().get::<AdminAccess>() {
+        Some(AdminAccess::Open | AdminAccess::Verified) => return Ok(()),
+        Some(AdminAccess::Denied) => {}
+        None => tracing::warn!("Denied an admin request without an AdminAccess extension"),
+    }
+    Err(ApiError::Forbidden(
+        "This endpoint requires a client certificate".to_string(),
+    ))
+}
+
+/// Builds the rustls configuration of a service from its TLS settings.
+pub fn server_config(settings: &TlsConfig) -> Result<ServerConfig, anyhow::Error> {
+    let certificates = read_certificates(&settings.certificate_path)?;
+    let key = PrivateKeyDer::from_pem_file(&settings.key_path).with_context(|| {
+        format!(
+            "Could not read a private key from {}",
+            settings.key_path.display()
+        )
+    })?;
+
+    let provider = Arc::new(ring::default_provider());
+    let versions: &[&rustls::SupportedProtocolVersion] = match settings.min_version {
+        TlsVersion::Tls12 => &[&rustls::version::TLS13, &rustls::version::TLS12],
+        TlsVersion::Tls13 => &[&rustls::version::TLS13],
+    };
+    let builder =
+        ServerConfig::builder_with_provider(provider.clone()).with_protocol_versions(versions)?;
+    let builder = match &settings.admin_client_ca_path {
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            for certificate in read_certificates(path)? {
+                roots.add(certificate)?;
+            }
+            // clients without a certificate may connect, but cannot use admin endpoints
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .allow_unauthenticated()
+                .build()?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let mut config = builder.with_single_cert(certificates, key)?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(config)
+}
+
+fn read_certificates(path: &Path) -> Result<Vec<CertificateDer<'static>>, anyhow::Error> {
+    let certificates = CertificateDer::pem_file_iter(path)
+        .and_then(|certificates| certificates.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("Could not read certificates from {}", path.display()))?;
+    if certificates.is_empty() {
+        bail!("{} does not contain any certificates", path.display());
+    }
+    Ok(certificates)
+}
+
+/// The TLS settings a service currently uses. Certificates are replaced without a restart when
+/// the settings change or, with `watch`, when the files are modified. A failed reload keeps the
+/// previous certificates.
+#[derive(Default)]
+pub struct TlsTermination {
+    current: RwLock<Option<LoadedTls>>,
+}
+
+struct LoadedTls {
+    settings: TlsConfig,
+    acceptor: TlsAcceptor,
+    modified: Vec<Option<SystemTime>>,
+    client_auth: bool,
+}
+
+impl TlsTermination {
+    /// Creates the TLS termination with the given settings, or plain HTTP if there are none.
+    pub fn new(settings: Option<&TlsConfig>) -> Result<TlsTermination, anyhow::Error> {
+        let tls = TlsTermination::default();
+        tls.configure(settings)?;
+        Ok(tls)
+    }
+
+    /// Switches to the given settings, reading the certificates again.
+    pub fn configure(&self, settings: Option<&TlsConfig>) -> Result<(), anyhow::Error> {
+        let loaded = match settings {
+            Some(settings) => Some(LoadedTls {
+                modified: modified_times(settings),
+                acceptor: TlsAcceptor::from(Arc::new(server_config(settings)?)),
+                client_auth: settings.admin_client_ca_path.is_some(),
+                settings: settings.clone(),
+            }),
+            None => None,
+        };
+        *self.current.write().unwrap() = loaded;
+        Ok(())
+    }
+
+    /// Reads the certificates again if one of the files was modified since they were read.
+    /// Returns whether they were reloaded.
+    pub fn reload_if_modified(&self) -> Result<bool, anyhow::Error> {
+        let (settings, modified) = match &*self.current.read().unwrap() {
+            Some(loaded) => {
+                let modified = modified_times(&loaded.settings);
+                if modified == loaded.modified {
+                    return Ok(false);
+                }
+                (loaded.settings.clone(), modified)
+            }
+            None => return Ok(false),
+        };
+        if let Err(err) = self.configure(Some(&settings)) {
+            // files that cannot be loaded are only tried again after the next modification
+            if let Some(loaded) = self.current.write().unwrap().as_mut() {
+                loaded.modified = modified;
+            }
+            return Err(err);
+        }
+        Ok(true)
+    }
+
+    /// Checks every `interval` whether the certificate files were modified and reloads them.
+    pub fn watch(self: &Arc<Self>, interval: Duration) -> JoinHandle<()> {
+        let tls = Arc::clone(self);
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            match tls.reload_if_modified() {
+                Ok(true) => tracing::info!("Reloaded the TLS certificates"),
+                Ok(false) => {}
+                Err(err) => tracing::warn!("Keeping the current TLS certificates: {:#}", err),
+            }
+        })
+    }
+
+    /// Performs the TLS handshake if TLS is enabled.
+    pub async fn accept(&self, stream: TcpStream) -> io::Result<MaybeTlsStream> {
+        let (acceptor, client_auth) = match &*self.current.read().unwrap() {
+            Some(loaded) => (loaded.acceptor.clone(), loaded.client_auth),
+            None => return Ok(MaybeTlsStream::Plain(stream)),
+        };
+        let stream = tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "TLS handshake timed out"))??;
+        let admin_access = match (client_auth, stream.get_ref().1.peer_certificates()) {
+            (false, _) => AdminAccess::Open,
+            (true, Some(_)) => AdminAccess::Verified,
+            (true, None) => AdminAccess::Denied,
+        };
+        Ok(MaybeTlsStream::Tls(Box::new(stream), admin_access))
+    }
+}
+
+fn modified_times(settings: &TlsConfig) -> Vec<Option<SystemTime>> {
+    [Some(&settings.certificate_path), Some(&settings.key_path)]
+        .into_iter()
+        .chain([settings.admin_client_ca_path.as_ref()])
+        .flatten()
+        .map(|path| fs::metadata(path).and_then(|meta| meta.modified()).ok())
+        .collect()
+}
+
+/// An accepted connection, with or without TLS.
+pub enum MaybeTlsStream {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>, AdminAccess),
+}
+
+impl MaybeTlsStream {
+    pub fn admin_access(&self) -> AdminAccess {
+        match self {
+            MaybeTlsStream::Plain(_) => AdminAccess::Open,
+            MaybeTlsStream::Tls(_, admin_access) => *admin_access,
+        }
+    }
+}
+
+impl AsyncRead for MaybeTlsStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MaybeTlsStream::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            MaybeTlsStream::Tls(stream, _) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for MaybeTlsStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            MaybeTlsStream::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            MaybeTlsStream::Tls(stream, _) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MaybeTlsStream::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            MaybeTlsStream::Tls(stream, _) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MaybeTlsStream::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            MaybeTlsStream::Tls(stream, _) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
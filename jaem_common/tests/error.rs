@@ -0,0 +1,19 @@
+use anyhow::anyhow;
+use hyper::StatusCode;
+use jaem_common::error::ApiError;
+
+#[test]
+fn api_errors_keep_their_status_through_anyhow() {
+    let err: ApiError =
+        anyhow::Error::from(ApiError::NotFound("User not found".to_string())).into();
+    assert_eq!(err, ApiError::NotFound("User not found".to_string()));
+    assert_eq!(err.status(), StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn other_errors_are_internal_without_details() {
+    let err: ApiError = anyhow!("Could not write /var/lib/jaem/users.json").into();
+    assert_eq!(err.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(err.body().code, "internal_error");
+    assert!(!err.body().message.contains("/var/lib"));
+}
@@ -0,0 +1,101 @@
+use std::{
+    io::{self, Write},
+    sync::{Arc, Mutex},
+};
+
+use hyper::{Request, Response, StatusCode};
+use jaem_common::logging::{self, Redacted, RequestSpan, REQUEST_ID_HEADER};
+use jaem_config::LoggingConfig;
+use tracing::Level;
+
+/// Collects the log output of a test.
+#[derive(Clone, Default)]
+struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Buffer {
+    fn contents(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+}
+
+#[test]
+fn requests_are_logged_without_keys() {
+    let buffer = Buffer::default();
+    let writer = buffer.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .json()
+        .with_max_level(Level::DEBUG)
+        .with_writer(move || writer.clone())
+        .finish();
+
+    let secret = "c2VjcmV0IGtleSBtYXRlcmlhbA==";
+    let request = Request::builder()
+        .uri(format!("/prekeys/1/{}", secret))
+        .header(REQUEST_ID_HEADER, "client-id-1")
+        .body(())
+        .unwrap();
+    let mut response = Response::new(());
+    // IDs that could break the log lines are replaced
+    let evil_request = Request::builder()
+        .header(REQUEST_ID_HEADER, "evil\" id")
+        .body(())
+        .unwrap();
+    let mut error_response = Response::builder()
+        .status(StatusCode::INTERNAL_SERVER_ERROR)
+        .body(())
+        .unwrap();
+    tracing::subscriber::with_default(subscriber, || {
+        let request_span = RequestSpan::new(&request, "prekeys");
+        request_span.span().in_scope(|| {
+            tracing::debug!(key = %Redacted(secret), "Looking up the prekeys");
+        });
+        request_span.finish(&mut response);
+        RequestSpan::new(&evil_request, "unknown").finish(&mut error_response);
+    });
+
+    let output = buffer.contents();
+    assert!(
+        output.contains(r#""request_id":"client-id-1""#),
+        "{}",
+        output
+    );
+    assert!(output.contains(r#""route":"prekeys""#), "{}", output);
+    assert!(output.contains(r#""status":200"#), "{}", output);
+    assert!(output.contains("[redacted 28 bytes]"), "{}", output);
+    assert!(!output.contains(secret), "{}", output);
+    assert_eq!(response.headers()[REQUEST_ID_HEADER], "client-id-1");
+    assert!(!output.contains("evil"), "{}", output);
+    assert!(output.contains(r#""level":"ERROR""#), "{}", output);
+    assert_eq!(error_response.headers()[REQUEST_ID_HEADER].len(), 36);
+}
+
+#[test]
+fn log_level_can_be_changed() {
+    let mut settings = LoggingConfig {
+        level: "warn".to_string(),
+        ..LoggingConfig::default()
+    };
+    let log_level = logging::init(&settings).unwrap();
+    assert!(!tracing::enabled!(Level::INFO));
+
+    settings.level = "info,jaem_common=debug".to_string();
+    log_level.apply(&settings).unwrap();
+    assert!(tracing::enabled!(Level::INFO));
+
+    settings.level = "jaem_common=loud".to_string();
+    assert!(log_level.apply(&settings).is_err());
+    assert!(tracing::enabled!(Level::INFO));
+
+    // there is only one logger per process
+    assert!(logging::init(&settings).is_err());
+}
@@ -0,0 +1,185 @@
+use std::{fs, path::Path, sync::Arc};
+
+use hyper::Request;
+use jaem_common::tls::{check_admin_access, AdminAccess, TlsTermination};
+use jaem_config::{TlsConfig, TlsVersion};
+use rcgen::{
+    BasicConstraints, CertificateParams, ExtendedKeyUsagePurpose, IsCa, KeyPair, KeyUsagePurpose,
+};
+use rustls::{
+    crypto::ring,
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer, ServerName},
+    ClientConfig, RootCertStore, SupportedProtocolVersion,
+};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::TlsConnector;
+
+type ClientCertificate = (Vec<CertificateDer<'static>>, PrivateKeyDer<'static>);
+
+/// Writes a new self-signed certificate for localhost to the directory.
+fn write_server_certificate(dir: &Path) -> (TlsConfig, CertificateDer<'static>) {
+    fs::create_dir_all(dir).unwrap();
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let settings = TlsConfig {
+        certificate_path: dir.join("certificate.pem"),
+        key_path: dir.join("key.pem"),
+        min_version: TlsVersion::Tls12,
+        admin_client_ca_path: None,
+    };
+    fs::write(&settings.certificate_path, certified.cert.pem()).unwrap();
+    fs::write(&settings.key_path, certified.key_pair.serialize_pem()).unwrap();
+    (settings, certified.cert.der().clone())
+}
+
+fn client_config(
+    server_certificate: &CertificateDer<'static>,
+    versions: &[&'static SupportedProtocolVersion],
+    client_certificate: Option<ClientCertificate>,
+) -> ClientConfig {
+    let mut roots = RootCertStore::empty();
+    roots.add(server_certificate.clone()).unwrap();
+    let builder = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_protocol_versions(versions)
+        .unwrap()
+        .with_root_certificates(roots);
+    match client_certificate {
+        Some((chain, key)) => builder.with_client_auth_cert(chain, key).unwrap(),
+        None => builder.with_no_client_auth(),
+    }
+}
+
+/// Connects a client to a server using the TLS termination. Returns the admin access of the
+/// connection if the server completed the handshake, and whether the client did.
+async fn connect(tls: &TlsTermination, client: ClientConfig) -> (Option<AdminAccess>, bool) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = async {
+        let (stream, _) = listener.accept().await.unwrap();
+        tls.accept(stream)
+            .await
+            .ok()
+            .map(|stream| stream.admin_access())
+    };
+    let client = async move {
+        let stream = TcpStream::connect(addr).await.unwrap();
+        TlsConnector::from(Arc::new(client))
+            .connect(ServerName::try_from("localhost").unwrap(), stream)
+            .await
+            .is_ok()
+    };
+    tokio::join!(server, client)
+}
+
+#[tokio::test]
+async fn certificates_are_served_and_reloaded() {
+    let dir = Path::new("./temp_tls_01");
+    let (mut settings, certificate) = write_server_certificate(dir);
+    let tls = TlsTermination::new(Some(&settings)).unwrap();
+
+    let all_versions = rustls::ALL_VERSIONS;
+    let client = client_config(&certificate, all_versions, None);
+    assert_eq!(connect(&tls, client).await, (Some(AdminAccess::Open), true));
+
+    // clients that only speak TLS 1.2 are rejected if TLS 1.3 is required
+    settings.min_version = TlsVersion::Tls13;
+    tls.configure(Some(&settings)).unwrap();
+    let client = client_config(&certificate, &[&rustls::version::TLS12], None);
+    assert_eq!(connect(&tls, client).await, (None, false));
+
+    // a renewed certificate is picked up without a restart
+    assert!(!tls.reload_if_modified().unwrap());
+    let (_, renewed) = write_server_certificate(dir);
+    assert!(tls.reload_if_modified().unwrap());
+    let client = client_config(&renewed, all_versions, None);
+    assert_eq!(connect(&tls, client).await, (Some(AdminAccess::Open), true));
+
+    // a broken certificate keeps the current one
+    fs::write(&settings.certificate_path, "not a certificate").unwrap();
+    assert!(tls.reload_if_modified().is_err());
+    assert!(!tls.reload_if_modified().unwrap());
+    let client = client_config(&renewed, all_versions, None);
+    assert_eq!(connect(&tls, client).await, (Some(AdminAccess::Open), true));
+
+    // Clean up
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn admin_endpoints_require_client_certificates() {
+    let dir = Path::new("./temp_tls_02");
+    let (mut settings, certificate) = write_server_certificate(dir);
+
+    let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    ca_params.key_usages = vec![KeyUsagePurpose::KeyCertSign];
+    let ca_key = KeyPair::generate().unwrap();
+    let ca = ca_params.self_signed(&ca_key).unwrap();
+    let ca_path = dir.join("admin_ca.pem");
+    fs::write(&ca_path, ca.pem()).unwrap();
+    settings.admin_client_ca_path = Some(ca_path);
+
+    let mut admin_params = CertificateParams::new(vec!["admin".to_string()]).unwrap();
+    admin_params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+    let admin_key = KeyPair::generate().unwrap();
+    let admin = admin_params.signed_by(&admin_key, &ca, &ca_key).unwrap();
+    let admin_certificate = (
+        vec![admin.der().clone()],
+        PrivateKeyDer::from_pem_slice(admin_key.serialize_pem().as_bytes()).unwrap(),
+    );
+
+    let tls = TlsTermination::new(Some(&settings)).unwrap();
+    let client = client_config(&certificate, rustls::ALL_VERSIONS, None);
+    assert_eq!(
+        connect(&tls, client).await,
+        (Some(AdminAccess::Denied), true)
+    );
+    let client = client_config(&certificate, rustls::ALL_VERSIONS, Some(admin_certificate));
+    assert_eq!(
+        connect(&tls, client).await,
+        (Some(AdminAccess::Verified), true)
+    );
+
+    let request = |access: Option<AdminAccess>| {
+        let mut request = Request::new(());
+        if let Some(access) = access {
+            request.extensions_mut().insert(access);
+        }
+        request
+    };
+    assert!(check_admin_access(&request(Some(AdminAccess::Denied))).is_err());
+    assert!(check_admin_access(&request(Some(AdminAccess::Verified))).is_ok());
+    assert!(check_admin_access(&request(Some(AdminAccess::Open))).is_ok());
+    // requests that were not classified are denied
+    assert!(check_admin_access(&request(None)).is_err());
+
+    // Clean up
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn http2_is_negotiated_with_alpn() {
+    let dir = Path::new("./temp_tls_03");
+    let (settings, certificate) = write_server_certificate(dir);
+    let tls = TlsTermination::new(Some(&settings)).unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = async {
+        let (stream, _) = listener.accept().await.unwrap();
+        tls.accept(stream).await.unwrap()
+    };
+    let mut client = client_config(&certificate, rustls::ALL_VERSIONS, None);
+    client.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    let client = async move {
+        let stream = TcpStream::connect(addr).await.unwrap();
+        TlsConnector::from(Arc::new(client))
+            .connect(ServerName::try_from("localhost").unwrap(), stream)
+            .await
+            .unwrap()
+    };
+    let (_, client) = tokio::join!(server, client);
+    assert_eq!(client.get_ref().1.alpn_protocol(), Some(&b"h2"[..]));
+
+    // Clean up
+    fs::remove_dir_all(dir).unwrap();
+}
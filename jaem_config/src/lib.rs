@@ -1,28 +1,36 @@
 use std::{
     fs::{self, File, OpenOptions},
     io::{Read, Write},
+    net::{IpAddr, SocketAddr},
     path::PathBuf,
     str::FromStr,
 };
 
 use serde::{Deserialize, Serialize};
 
+pub mod loader;
+pub mod reload;
+pub mod validation;
+
 pub const DEFAULT_CONFIG_PATH: &str = "jaem_config.toml";
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct JaemConfig {
     pub message_delivery_config: Option<MessageDeliveryConfig>,
     pub user_discovery_config: Option<UserDiscoveryConfig>,
+    #[serde(default)]
+    pub logging: LoggingConfig,
 }
 
 impl JaemConfig {
     pub fn get_message_delivery_config(&self) -> MessageDeliveryConfig {
-        self.message_delivery_config.clone().unwrap()
+        self.message_delivery_config.clone().unwrap_or_default()
     }
     pub fn create_default() -> JaemConfig {
-        return JaemConfig {
+        JaemConfig {
             message_delivery_config: Some(MessageDeliveryConfig::default()),
             user_discovery_config: Some(UserDiscoveryConfig::default()),
-        };
+            logging: LoggingConfig::default(),
+        }
     }
 
     pub fn read_from_file(file_path: &str) -> Result<JaemConfig, anyhow::Error> {
@@ -37,8 +45,9 @@ impl JaemConfig {
         let mut file = OpenOptions::new()
             .create(true)
             .write(true)
+            .truncate(false)
             .open(file_path)?;
-        file.write(toml::to_string_pretty(self).unwrap().as_bytes())?;
+        file.write_all(toml::to_string_pretty(self).unwrap().as_bytes())?;
         Ok(())
     }
 }
@@ -54,17 +63,40 @@ pub struct MessageDeliveryConfig {
     pub share_directory: PathBuf,
     #[serde(default = "MessageDeliveryConfig::default_storage_path")]
     pub storage_path: PathBuf,
+    /// Seconds after which shared data is deleted.
+    #[serde(default = "MessageDeliveryConfig::default_share_ttl")]
+    pub share_ttl: u64,
+    /// Seconds after which retrieved messages that were not deleted by the client are kept again.
+    #[serde(default = "MessageDeliveryConfig::default_deletion_timeout")]
+    pub deletion_timeout: u64,
+    /// How many seconds the timestamp of a proof of authenticity may differ from the server time.
+    #[serde(default = "MessageDeliveryConfig::default_auth_clock_skew")]
+    pub auth_clock_skew: u64,
+    /// Seconds to wait for open connections to finish when the service shuts down.
+    #[serde(default = "MessageDeliveryConfig::default_shutdown_timeout")]
+    pub shutdown_timeout: u64,
+    /// Serve HTTPS instead of plain HTTP.
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
 }
 
-impl MessageDeliveryConfig {
-    pub fn default() -> MessageDeliveryConfig {
-        return MessageDeliveryConfig {
+impl Default for MessageDeliveryConfig {
+    fn default() -> MessageDeliveryConfig {
+        MessageDeliveryConfig {
             storage_path: Self::default_storage_path(),
             share_directory: Self::default_share_directory(),
             address: Self::default_address(),
             port: Self::default_port(),
-        };
+            share_ttl: Self::default_share_ttl(),
+            deletion_timeout: Self::default_deletion_timeout(),
+            auth_clock_skew: Self::default_auth_clock_skew(),
+            shutdown_timeout: Self::default_shutdown_timeout(),
+            tls: None,
+        }
     }
+}
+
+impl MessageDeliveryConfig {
     fn default_address() -> String {
         String::from("0.0.0.0")
     }
@@ -74,11 +106,32 @@ impl MessageDeliveryConfig {
     }
 
     fn default_share_directory() -> PathBuf {
-        return PathBuf::from_str("./share").unwrap();
+        PathBuf::from_str("./share").unwrap()
     }
 
     fn default_storage_path() -> PathBuf {
-        return PathBuf::from_str("./messages").unwrap();
+        PathBuf::from_str("./messages").unwrap()
+    }
+
+    fn default_share_ttl() -> u64 {
+        600
+    }
+
+    fn default_deletion_timeout() -> u64 {
+        20
+    }
+
+    fn default_auth_clock_skew() -> u64 {
+        5
+    }
+
+    fn default_shutdown_timeout() -> u64 {
+        30
+    }
+
+    /// The address the service listens on.
+    pub fn socket_addr(&self) -> Result<SocketAddr, anyhow::Error> {
+        Ok(SocketAddr::new(IpAddr::from_str(&self.address)?, self.port))
     }
 
     pub fn create_dirs(&mut self) -> Result<(), anyhow::Error> {
@@ -125,35 +178,106 @@ pub struct UserDiscoveryConfig {
     pub port: u16,
     #[serde(default = "UserDiscoveryConfig::default_storage_path")]
     pub storage_path: PathBuf,
+    #[serde(default = "UserDiscoveryConfig::default_profile_picture_directory")]
+    pub profile_picture_directory: PathBuf,
+    #[serde(default = "UserDiscoveryConfig::default_max_profile_picture_size")]
+    pub max_profile_picture_size: usize,
+    /// File containing the ed25519 key the tree heads of the key transparency log are signed
+    /// with. A new key is generated if the file does not exist.
+    #[serde(default = "UserDiscoveryConfig::default_log_signing_key_path")]
+    pub log_signing_key_path: PathBuf,
+    /// Whether `GET /users` lists the directory. Users can still be searched and looked up if
+    /// listing is disabled.
+    #[serde(default = "UserDiscoveryConfig::default_allow_listing")]
+    pub allow_listing: bool,
+    /// How many seconds the timestamp of a signed request may differ from the server time.
+    #[serde(default = "UserDiscoveryConfig::default_auth_clock_skew")]
+    pub auth_clock_skew: u64,
+    /// Seconds to wait for open connections to finish when the service shuts down.
+    #[serde(default = "UserDiscoveryConfig::default_shutdown_timeout")]
+    pub shutdown_timeout: u64,
+    /// Reverse proxies whose `X-Forwarded-For` header is trusted to name the client of a request.
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpAddr>,
+    /// Serve HTTPS instead of plain HTTP.
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+    #[serde(default)]
+    pub username_rules: UsernameRules,
+    #[serde(default)]
+    pub contact_discovery: ContactDiscoveryLimits,
 }
 
-impl UserDiscoveryConfig {
+impl Default for UserDiscoveryConfig {
     fn default() -> UserDiscoveryConfig {
-        return Self {
+        Self {
             address: Self::default_address(),
             port: Self::default_port(),
             storage_path: Self::default_storage_path(),
-        };
+            profile_picture_directory: Self::default_profile_picture_directory(),
+            max_profile_picture_size: Self::default_max_profile_picture_size(),
+            log_signing_key_path: Self::default_log_signing_key_path(),
+            allow_listing: Self::default_allow_listing(),
+            auth_clock_skew: Self::default_auth_clock_skew(),
+            shutdown_timeout: Self::default_shutdown_timeout(),
+            trusted_proxies: Vec::new(),
+            tls: None,
+            username_rules: UsernameRules::default(),
+            contact_discovery: ContactDiscoveryLimits::default(),
+        }
     }
+}
 
+impl UserDiscoveryConfig {
     fn default_address() -> String {
         String::from("0.0.0.0")
     }
 
     fn default_port() -> u16 {
-        return 8082;
+        8082
     }
 
     fn default_storage_path() -> PathBuf {
-        return PathBuf::from_str("./users.json").unwrap();
+        PathBuf::from_str("./users.json").unwrap()
+    }
+
+    fn default_profile_picture_directory() -> PathBuf {
+        PathBuf::from_str("./src/profile_pictures/").unwrap()
+    }
+
+    fn default_max_profile_picture_size() -> usize {
+        2 * 1024 * 1024
+    }
+
+    fn default_log_signing_key_path() -> PathBuf {
+        PathBuf::from_str("./log_signing_key").unwrap()
+    }
+
+    fn default_allow_listing() -> bool {
+        true
+    }
+
+    fn default_auth_clock_skew() -> u64 {
+        30
     }
 
+    fn default_shutdown_timeout() -> u64 {
+        30
+    }
+
+    /// The address the service listens on.
+    pub fn socket_addr(&self) -> Result<SocketAddr, anyhow::Error> {
+        Ok(SocketAddr::new(IpAddr::from_str(&self.address)?, self.port))
+    }
+
+    /// Sets the file users are stored in and creates the directory containing it.
     pub fn set_storage_path(&mut self, storage_path: &str) -> Result<(), anyhow::Error> {
         let new_path = PathBuf::from_str(storage_path)?;
-        match new_path.try_exists() {
-            Ok(true) => {}
-            _ => fs::create_dir_all(&new_path)?,
-        };
+        if let Some(parent) = new_path.parent() {
+            if !parent.as_os_str().is_empty() {
+                fs::create_dir_all(parent)?;
+            }
+        }
         self.storage_path = new_path;
         Ok(())
     }
@@ -168,3 +292,129 @@ impl UserDiscoveryConfig {
         Ok(())
     }
 }
+
+/// The oldest TLS version a service accepts.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TlsVersion {
+    #[default]
+    #[serde(rename = "1.2")]
+    Tls12,
+    #[serde(rename = "1.3")]
+    Tls13,
+}
+
+/// TLS settings of a service. The files are read again when they change.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TlsConfig {
+    /// PEM file with the certificate chain, starting with the certificate of the service.
+    pub certificate_path: PathBuf,
+    /// PEM file with the private key of the certificate.
+    pub key_path: PathBuf,
+    #[serde(default)]
+    pub min_version: TlsVersion,
+    /// PEM file with the certificate authorities that sign client certificates. If set, admin
+    /// endpoints only answer clients with a certificate signed by one of them.
+    #[serde(default)]
+    pub admin_client_ca_path: Option<PathBuf>,
+}
+
+/// How log lines are written.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// Human readable lines, e.g. for a terminal.
+    #[default]
+    Pretty,
+    /// One JSON object per line, e.g. for a log collector.
+    Json,
+}
+
+/// Logging settings shared by all services of a process.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct LoggingConfig {
+    /// The least severe level that is logged, optionally followed by levels for single modules,
+    /// e.g. `info,jaem_user_discovery=debug`.
+    pub level: String,
+    pub format: LogFormat,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> LoggingConfig {
+        Self {
+            level: String::from("info"),
+            format: LogFormat::Pretty,
+        }
+    }
+}
+
+/// Classes of characters that may be allowed in usernames.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CharacterClass {
+    Letters,
+    Digits,
+    Whitespace,
+    Punctuation,
+}
+
+/// Rules every username has to follow when a user is created or renamed.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct UsernameRules {
+    /// Minimum number of characters after normalization.
+    pub min_length: usize,
+    /// Maximum number of characters after normalization.
+    pub max_length: usize,
+    /// Character classes that may appear anywhere in a username.
+    pub allowed_classes: Vec<CharacterClass>,
+    /// Additional characters that are allowed regardless of their class.
+    pub allowed_symbols: String,
+    /// Reject usernames that mix lookalike scripts (e.g. Latin and Cyrillic) and, if usernames
+    /// are unique, usernames that can be confused with an existing one.
+    pub reject_confusables: bool,
+    /// Enforce that no two users share the same username, ignoring case and, if
+    /// `reject_confusables` is set, lookalike characters.
+    pub unique: bool,
+}
+
+impl Default for UsernameRules {
+    fn default() -> UsernameRules {
+        Self {
+            min_length: 1,
+            max_length: 32,
+            allowed_classes: vec![
+                CharacterClass::Letters,
+                CharacterClass::Digits,
+                CharacterClass::Whitespace,
+            ],
+            allowed_symbols: String::from("_-."),
+            reject_confusables: true,
+            unique: false,
+        }
+    }
+}
+
+/// Limits for contact discovery by hashed identifiers, which make it expensive to guess
+/// identifiers by trying out many hashes.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct ContactDiscoveryLimits {
+    /// Maximum number of hashes in one request.
+    pub max_batch_size: usize,
+    /// Number of hashes every client may look up per hour. Unused lookups accumulate up to this
+    /// number.
+    pub hashes_per_hour: u32,
+    /// Minimum number of bytes a truncated hash must have.
+    pub min_hash_length: usize,
+}
+
+impl Default for ContactDiscoveryLimits {
+    fn default() -> ContactDiscoveryLimits {
+        Self {
+            max_batch_size: 100,
+            hashes_per_hour: 1000,
+            min_hash_length: 4,
+        }
+    }
+}
@@ -0,0 +1,281 @@
+use std::{fmt::Display, fs, path::PathBuf};
+
+use anyhow::{anyhow, bail, Context};
+use toml::{Table, Value};
+
+use crate::{JaemConfig, TlsConfig, TlsVersion, DEFAULT_CONFIG_PATH};
+
+/// Environment variable and flag that select the configuration file.
+pub const CONFIG_PATH_VARIABLE: &str = "JAEM_CONFIG";
+pub const CONFIG_PATH_FLAG: &str = "--config";
+
+/// Prefix of the environment variables that override single settings, e.g.
+/// `JAEM_USER_DISCOVERY_PORT=3000` or `JAEM_USER_DISCOVERY_USERNAME_RULES_UNIQUE=true`.
+pub const ENV_PREFIX: &str = "JAEM_";
+
+/// The sections of the configuration file and the prefix of their settings in environment
+/// variables (after `JAEM_`) and flags (after `--`).
+const SECTIONS: [(&str, &str); 3] = [
+    ("message_delivery_config", "message_delivery_"),
+    ("user_discovery_config", "user_discovery_"),
+    ("logging", "logging_"),
+];
+
+/// A variable or flag that does not name a setting of the configuration.
+#[derive(Debug)]
+struct UnknownSetting;
+
+impl Display for UnknownSetting {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Unknown setting")
+    }
+}
+
+impl std::error::Error for UnknownSetting {}
+
+/// Builds the configuration from several layers, each overriding the previous one:
+///
+/// 1. the defaults,
+/// 2. the configuration file given by `--config`, `JAEM_CONFIG` or `jaem_config.toml`,
+/// 3. `JAEM_*` environment variables, ignoring unknown ones, see `load_reporting_ignored`,
+/// 4. command line flags like `--user-discovery-port 3000`.
+///
+/// The loader keeps the overrides, so that the configuration can be loaded again when the file
+/// changes.
+#[derive(Debug, Clone)]
+pub struct ConfigLoader {
+    path: PathBuf,
+    // whether the path was given explicitly, in which case the file has to exist
+    explicit_path: bool,
+    env_overrides: Vec<(String, String)>,
+    flag_overrides: Vec<(String, String)>,
+}
+
+impl ConfigLoader {
+    /// Creates a loader from the arguments and environment of the current process.
+    pub fn from_env() -> Result<ConfigLoader, anyhow::Error> {
+        Self::new(std::env::args().skip(1), std::env::vars())
+    }
+
+    /// Creates a loader from command line arguments (without the program name) and environment
+    /// variables.
+    pub fn new(
+        args: impl IntoIterator<Item = String>,
+        env: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<ConfigLoader, anyhow::Error> {
+        let mut path = None;
+        let mut env_overrides = Vec::new();
+        for (name, value) in env {
+            if name == CONFIG_PATH_VARIABLE {
+                path = Some(PathBuf::from(value));
+            } else if let Some(setting) = name.strip_prefix(ENV_PREFIX) {
+                if is_setting(&setting.to_lowercase()) {
+                    env_overrides.push((name, value));
+                }
+            }
+        }
+        // environment variables are applied in a stable order
+        env_overrides.sort();
+
+        let mut flag_overrides = Vec::new();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let Some(flag) = arg.strip_prefix("--") else {
+                bail!("Unexpected argument '{}'", arg);
+            };
+            let (flag, value) = match flag.split_once('=') {
+                Some((flag, value)) => (flag.to_string(), value.to_string()),
+                None => match args.next() {
+                    Some(value) => (flag.to_string(), value),
+                    None => bail!("Missing value for '--{}'", flag),
+                },
+            };
+            if format!("--{}", flag) == CONFIG_PATH_FLAG {
+                path = Some(PathBuf::from(value));
+            } else if is_setting(&flag.replace('-', "_")) {
+                flag_overrides.push((format!("--{}", flag), value));
+            } else {
+                bail!("Unknown flag '--{}'", flag);
+            }
+        }
+
+        Ok(ConfigLoader {
+            explicit_path: path.is_some(),
+            path: path.unwrap_or(PathBuf::from(DEFAULT_CONFIG_PATH)),
+            env_overrides,
+            flag_overrides,
+        })
+    }
+
+    /// The configuration file that is read.
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    /// Reads the configuration file, applies the overrides and validates the result. A missing
+    /// file is only an error if its path was given explicitly, while a file that cannot be parsed
+    /// always is.
+    ///
+    /// Sections missing from the file stay unset, unless one of their settings is overridden.
+    /// Ignored environment variables are logged, see `load_reporting_ignored`.
+    pub fn load(&self) -> Result<JaemConfig, anyhow::Error> {
+        let (config, ignored) = self.load_reporting_ignored()?;
+        warn_about_ignored(&ignored);
+        Ok(config)
+    }
+
+    /// Like `load`, but returns the `JAEM_*` environment variables that were ignored because they
+    /// do not name a setting instead of logging them, since logging is only set up once the
+    /// configuration is known.
+    pub fn load_reporting_ignored(&self) -> Result<(JaemConfig, Vec<String>), anyhow::Error> {
+        let config = match fs::read_to_string(&self.path) {
+            Ok(contents) => toml::from_str(&contents)
+                .with_context(|| format!("Could not parse {}", self.path.display()))?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound && !self.explicit_path => {
+                JaemConfig::create_default()
+            }
+            Err(err) => {
+                return Err(err).with_context(|| format!("Could not read {}", self.path.display()))
+            }
+        };
+
+        let defaults = Table::try_from(JaemConfig::create_default())?;
+        let template = template()?;
+        let mut table = Table::try_from(&config)?;
+        let mut ignored = Vec::new();
+        for (name, value) in &self.env_overrides {
+            let setting = name[ENV_PREFIX.len()..].to_lowercase();
+            match set(&mut table, &defaults, &template, &setting, value) {
+                // the environment is shared with other programs, so a stray variable or one of a
+                // newer version must not prevent the service from starting
+                Err(err) if err.is::<UnknownSetting>() => ignored.push(name.clone()),
+                result => result.with_context(|| format!("Invalid {}", name))?,
+            }
+        }
+        for (flag, value) in &self.flag_overrides {
+            let setting = flag[2..].replace('-', "_");
+            set(&mut table, &defaults, &template, &setting, value)
+                .with_context(|| format!("Invalid {}", flag))?;
+        }
+        let config: JaemConfig = table
+            .try_into()
+            .context("The overrides leave the configuration incomplete")?;
+        config.validate()?;
+        Ok((config, ignored))
+    }
+}
+
+/// Logs a warning for every environment variable returned by `load_reporting_ignored`.
+pub fn warn_about_ignored(ignored: &[String]) {
+    for name in ignored {
+        tracing::warn!("Ignoring {}, which is not a known setting", name);
+    }
+}
+
+/// The defaults with every optional table and setting present, which gives the type of every
+/// setting that can be overridden.
+fn template() -> Result<Table, anyhow::Error> {
+    let tls = TlsConfig {
+        certificate_path: PathBuf::new(),
+        key_path: PathBuf::new(),
+        min_version: TlsVersion::default(),
+        admin_client_ca_path: Some(PathBuf::new()),
+    };
+    let mut config = JaemConfig::create_default();
+    if let Some(md_config) = &mut config.message_delivery_config {
+        md_config.tls = Some(tls.clone());
+    }
+    if let Some(ud_config) = &mut config.user_discovery_config {
+        ud_config.tls = Some(tls);
+    }
+    Ok(Table::try_from(config)?)
+}
+
+/// Whether the name belongs to one of the sections of the configuration, e.g.
+/// `user_discovery_port`.
+fn is_setting(name: &str) -> bool {
+    SECTIONS.iter().any(|(_, prefix)| name.starts_with(prefix))
+}
+
+/// Sets the setting with the given name, e.g. `user_discovery_username_rules_unique`, to a value
+/// of the type the setting has in the template. A missing section is added with its default
+/// settings, a missing optional table like `tls` with only the settings that are overridden.
+fn set(
+    table: &mut Table,
+    defaults: &Table,
+    template: &Table,
+    name: &str,
+    value: &str,
+) -> Result<(), anyhow::Error> {
+    let (section, rest) = SECTIONS
+        .iter()
+        .find_map(|(section, prefix)| Some((*section, name.strip_prefix(prefix)?)))
+        .ok_or(UnknownSetting)?;
+    let mut table = table
+        .entry(section)
+        .or_insert_with(|| defaults[section].clone())
+        .as_table_mut()
+        .ok_or(UnknownSetting)?;
+    let mut template = template[section].as_table().ok_or(UnknownSetting)?;
+    let mut rest = rest.to_string();
+    loop {
+        if let Some(current) = template.get(&rest) {
+            if current.is_table() {
+                bail!("{} is a section, set its fields instead", rest);
+            }
+            let value = parse_like(current, value)?;
+            table.insert(rest, value);
+            return Ok(());
+        }
+        // settings in nested tables have the name of the table as prefix
+        let key = template
+            .iter()
+            .filter(|(key, value)| value.is_table() && rest.starts_with(&format!("{}_", key)))
+            .map(|(key, _)| key.clone())
+            .max_by_key(String::len)
+            .ok_or(UnknownSetting)?;
+        rest = rest[key.len() + 1..].to_string();
+        template = template[&key].as_table().unwrap();
+        table = table
+            .entry(key)
+            .or_insert_with(|| Value::Table(Table::new()))
+            .as_table_mut()
+            .ok_or(UnknownSetting)?;
+    }
+}
+
+/// Parses a value given as text into the type of the current value of a setting. Arrays may be
+/// given as TOML arrays or as comma separated lists.
+fn parse_like(current: &Value, value: &str) -> Result<Value, anyhow::Error> {
+    Ok(match current {
+        Value::String(_) => Value::String(value.to_string()),
+        Value::Integer(_) => Value::Integer(
+            value
+                .parse()
+                .map_err(|_| anyhow!("'{}' is not a number", value))?,
+        ),
+        Value::Float(_) => Value::Float(
+            value
+                .parse()
+                .map_err(|_| anyhow!("'{}' is not a number", value))?,
+        ),
+        Value::Boolean(_) => Value::Boolean(
+            value
+                .parse()
+                .map_err(|_| anyhow!("'{}' is neither true nor false", value))?,
+        ),
+        Value::Array(_) if value.trim_start().starts_with('[') => {
+            let table: Table = toml::from_str(&format!("value = {}", value))?;
+            table["value"].clone()
+        }
+        Value::Array(_) => Value::Array(
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(|item| Value::String(item.to_string()))
+                .collect(),
+        ),
+        Value::Datetime(_) | Value::Table(_) => bail!("Unsupported setting"),
+    })
+}
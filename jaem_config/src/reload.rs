@@ -0,0 +1,248 @@
+use std::{
+    fmt::Display,
+    fs,
+    sync::{Arc, Mutex, RwLock},
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime},
+};
+
+use toml::{Table, Value};
+
+use crate::{loader::ConfigLoader, JaemConfig};
+
+/// How often services check whether the configuration file was modified.
+pub const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Settings that are only read when a service starts. Changes to them are reported, but only
+/// applied after a restart.
+pub const RESTART_ONLY: [&str; 10] = [
+    "message_delivery_config.address",
+    "message_delivery_config.port",
+    "message_delivery_config.storage_path",
+    "message_delivery_config.share_directory",
+    "user_discovery_config.address",
+    "user_discovery_config.port",
+    "user_discovery_config.storage_path",
+    "user_discovery_config.profile_picture_directory",
+    "user_discovery_config.log_signing_key_path",
+    "logging.format",
+];
+
+/// A setting whose value changed when the configuration was reloaded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChangedSetting {
+    /// Path of the setting, e.g. `user_discovery_config.allow_listing`.
+    pub key: String,
+    pub old: Option<Value>,
+    pub new: Option<Value>,
+}
+
+/// The result of reloading the configuration.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReloadReport {
+    /// Settings that changed and are in effect now.
+    pub applied: Vec<ChangedSetting>,
+    /// Settings that changed, but keep their old value until the service is restarted.
+    pub not_applied: Vec<ChangedSetting>,
+}
+
+impl ReloadReport {
+    pub fn is_empty(&self) -> bool {
+        self.applied.is_empty() && self.not_applied.is_empty()
+    }
+}
+
+impl Display for ChangedSetting {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let show = |value: &Option<Value>| match value {
+            Some(value) => value.to_string(),
+            None => "unset".to_string(),
+        };
+        write!(
+            f,
+            "{}: {} -> {}",
+            self.key,
+            show(&self.old),
+            show(&self.new)
+        )
+    }
+}
+
+impl Display for ReloadReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_empty() {
+            return write!(f, "No settings changed");
+        }
+        write!(f, "Reloaded the configuration")?;
+        for setting in &self.applied {
+            write!(f, "\n  applied {}", setting)?;
+        }
+        for setting in &self.not_applied {
+            write!(f, "\n  not applied until restart {}", setting)?;
+        }
+        Ok(())
+    }
+}
+
+type ReloadListener = Box<dyn Fn(&JaemConfig, &ReloadReport) + Send>;
+
+/// The configuration a service is currently running with. Readers get a snapshot with `get`, while
+/// `reload` replaces the whole configuration at once, so that a request never sees a mix of old
+/// and new settings.
+pub struct LiveConfig {
+    loader: ConfigLoader,
+    current: RwLock<Arc<JaemConfig>>,
+    listeners: Mutex<Vec<ReloadListener>>,
+}
+
+impl LiveConfig {
+    pub fn new(loader: ConfigLoader, config: JaemConfig) -> LiveConfig {
+        LiveConfig {
+            loader,
+            current: RwLock::new(Arc::new(config)),
+            listeners: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// The current configuration.
+    pub fn get(&self) -> Arc<JaemConfig> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// Loads and validates the configuration again and swaps it in. Restart-only settings and
+    /// sections that were removed from the file keep their current values. If the new
+    /// configuration is invalid the current one stays in place.
+    pub fn reload(&self) -> Result<ReloadReport, anyhow::Error> {
+        let mut new = Table::try_from(self.loader.load()?)?;
+        let mut current = self.current.write().unwrap();
+        let old = Table::try_from(current.as_ref())?;
+
+        let mut report = ReloadReport::default();
+        // a service whose section was removed keeps running with it until it is restarted, rather
+        // than falling back to the defaults, e.g. another storage path
+        for (section, value) in &old {
+            if value.is_table() && !new.contains_key(section) {
+                new.insert(section.clone(), value.clone());
+                report.not_applied.push(ChangedSetting {
+                    key: section.clone(),
+                    old: Some(value.clone()),
+                    new: None,
+                });
+            }
+        }
+
+        let mut old_settings = Vec::new();
+        let mut new_settings = Vec::new();
+        flatten("", &old, &mut old_settings);
+        flatten("", &new, &mut new_settings);
+        let mut keys: Vec<&String> = old_settings
+            .iter()
+            .chain(new_settings.iter())
+            .map(|(key, _)| key)
+            .collect();
+        keys.sort();
+        keys.dedup();
+        for key in keys {
+            let find = |settings: &Vec<(String, Value)>| {
+                settings
+                    .iter()
+                    .find(|(setting, _)| setting == key)
+                    .map(|(_, value)| value.clone())
+            };
+            let (old_value, new_value) = (find(&old_settings), find(&new_settings));
+            if old_value == new_value {
+                continue;
+            }
+            let setting = ChangedSetting {
+                key: key.clone(),
+                old: old_value,
+                new: new_value,
+            };
+            if RESTART_ONLY.contains(&key.as_str()) {
+                restore(&mut new, key, setting.old.clone());
+                report.not_applied.push(setting);
+            } else {
+                report.applied.push(setting);
+            }
+        }
+
+        *current = Arc::new(new.try_into()?);
+        Ok(report)
+    }
+
+    /// Registers a function that is called after every reload that changed settings, so that
+    /// services can apply settings they keep elsewhere.
+    pub fn on_reload(&self, listener: impl Fn(&JaemConfig, &ReloadReport) + Send + 'static) {
+        self.listeners.lock().unwrap().push(Box::new(listener));
+    }
+
+    /// Reloads the configuration whenever the configuration file is modified, checking every
+    /// `interval`. The report of every reload is logged and passed to the listeners registered
+    /// with `on_reload`.
+    pub fn watch(self: &Arc<Self>, interval: Duration) -> JoinHandle<()> {
+        let live_config = Arc::clone(self);
+        thread::spawn(move || {
+            let path = live_config.loader.path().clone();
+            let modified = || fs::metadata(&path).and_then(|meta| meta.modified()).ok();
+            let mut last_modified: Option<SystemTime> = modified();
+            loop {
+                thread::sleep(interval);
+                let current_modified = modified();
+                if current_modified == last_modified {
+                    continue;
+                }
+                last_modified = current_modified;
+                match live_config.reload() {
+                    Ok(report) if report.is_empty() => {}
+                    Ok(report) => {
+                        for setting in &report.applied {
+                            tracing::info!(%setting, "Applied a changed setting");
+                        }
+                        for setting in &report.not_applied {
+                            tracing::warn!(%setting, "A changed setting needs a restart");
+                        }
+                        let config = live_config.get();
+                        for listener in live_config.listeners.lock().unwrap().iter() {
+                            listener(&config, &report);
+                        }
+                    }
+                    Err(err) => tracing::warn!(
+                        "Keeping the current configuration, because {} could not be loaded: {:#}",
+                        path.display(),
+                        err
+                    ),
+                }
+            }
+        })
+    }
+}
+
+/// Collects all settings of a table with their paths, e.g. `user_discovery_config.port`.
+fn flatten(prefix: &str, table: &Table, settings: &mut Vec<(String, Value)>) {
+    for (key, value) in table {
+        let key = match prefix.is_empty() {
+            true => key.clone(),
+            false => format!("{}.{}", prefix, key),
+        };
+        match value {
+            Value::Table(table) => flatten(&key, table, settings),
+            value => settings.push((key, value.clone())),
+        }
+    }
+}
+
+/// Sets the setting with the given path back to its previous value.
+fn restore(table: &mut Table, key: &str, value: Option<Value>) {
+    let (path, name) = key.rsplit_once('.').unwrap_or(("", key));
+    let mut table = table;
+    for part in path.split('.').filter(|part| !part.is_empty()) {
+        table = match table.get_mut(part).and_then(Value::as_table_mut) {
+            Some(table) => table,
+            None => return,
+        };
+    }
+    match value {
+        Some(value) => table.insert(name.to_string(), value),
+        None => table.remove(name),
+    };
+}
@@ -0,0 +1,417 @@
+use std::{
+    fmt::Display,
+    fs::{self, OpenOptions},
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    str::FromStr,
+};
+
+use crate::{JaemConfig, LoggingConfig, MessageDeliveryConfig, TlsConfig, UserDiscoveryConfig};
+
+/// A problem with one setting of the configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigProblem {
+    /// Path of the setting, e.g. `user_discovery_config.port`.
+    pub key: String,
+    pub message: String,
+    pub suggestion: String,
+}
+
+impl Display for ConfigProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {}\n    hint: {}",
+            self.key, self.message, self.suggestion
+        )
+    }
+}
+
+/// All problems found while validating a configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationErrors(pub Vec<ConfigProblem>);
+
+impl Display for ValidationErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The configuration is invalid:")?;
+        for problem in &self.0 {
+            write!(f, "\n  {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ValidationErrors {}
+
+#[derive(Debug, Default)]
+struct Problems(Vec<ConfigProblem>);
+
+impl Problems {
+    fn add(&mut self, key: &str, message: impl Into<String>, suggestion: impl Into<String>) {
+        self.0.push(ConfigProblem {
+            key: key.to_string(),
+            message: message.into(),
+            suggestion: suggestion.into(),
+        });
+    }
+
+    fn check_address(&mut self, key: &str, address: &str) {
+        if IpAddr::from_str(address).is_err() {
+            self.add(
+                key,
+                format!("'{}' is not an IP address", address),
+                "use an IPv4 or IPv6 address like \"0.0.0.0\" or \"127.0.0.1\"",
+            );
+        }
+    }
+
+    fn check_port(&mut self, key: &str, port: u16) {
+        if port == 0 {
+            self.add(
+                key,
+                "port 0 would bind a random port",
+                "choose a fixed port like 8081",
+            );
+        }
+    }
+
+    fn check_duration(&mut self, key: &str, seconds: u64) {
+        if seconds == 0 {
+            self.add(
+                key,
+                "the duration has to be at least one second",
+                "give the duration in seconds, e.g. 30",
+            );
+        }
+    }
+
+    /// Checks that the path is an existing file the service can read.
+    fn check_readable(&mut self, key: &str, path: &Path) {
+        match fs::metadata(path) {
+            Ok(metadata) if metadata.is_dir() => self.add(
+                key,
+                format!("{} is a directory, but a file is expected", path.display()),
+                "point the setting to a PEM file",
+            ),
+            Ok(_) if fs::File::open(path).is_err() => self.add(
+                key,
+                format!("the file {} is not readable", path.display()),
+                "fix the permissions of the file",
+            ),
+            Ok(_) => {}
+            Err(_) => self.add(
+                key,
+                format!("{} does not exist", path.display()),
+                "create the file or correct the path",
+            ),
+        }
+    }
+
+    fn check_tls(&mut self, section: &str, tls: &TlsConfig) {
+        self.check_readable(
+            &format!("{}.tls.certificate_path", section),
+            &tls.certificate_path,
+        );
+        self.check_readable(&format!("{}.tls.key_path", section), &tls.key_path);
+        if let Some(path) = &tls.admin_client_ca_path {
+            self.check_readable(&format!("{}.tls.admin_client_ca_path", section), path);
+        }
+    }
+
+    /// Checks that the path is a directory the service can write to, or can be created.
+    fn check_directory(&mut self, key: &str, path: &Path) {
+        match fs::metadata(path) {
+            Ok(metadata) if !metadata.is_dir() => self.add(
+                key,
+                format!("{} is a file, but a directory is expected", path.display()),
+                "point the setting to a directory or remove the file",
+            ),
+            Ok(_) if !is_writable_directory(path) => self.add(
+                key,
+                format!("the directory {} is not writable", path.display()),
+                "fix the permissions of the directory or choose another one",
+            ),
+            Ok(_) => {}
+            Err(_) => self.check_creatable(key, path),
+        }
+    }
+
+    /// Checks that the path is a file the service can write to, or can be created.
+    fn check_file(&mut self, key: &str, path: &Path) {
+        match fs::metadata(path) {
+            Ok(metadata) if metadata.is_dir() => self.add(
+                key,
+                format!("{} is a directory, but a file is expected", path.display()),
+                format!(
+                    "point the setting to a file inside the directory, e.g. {}",
+                    path.join(file_name_hint(key)).display()
+                ),
+            ),
+            Ok(_) if OpenOptions::new().append(true).open(path).is_err() => self.add(
+                key,
+                format!("the file {} is not writable", path.display()),
+                "fix the permissions of the file or choose another path",
+            ),
+            Ok(_) => {}
+            Err(_) => match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => self.check_creatable(key, parent),
+                _ => {}
+            },
+        }
+    }
+
+    /// Checks that a missing directory can be created, i.e. that its closest existing ancestor is
+    /// a writable directory.
+    fn check_creatable(&mut self, key: &str, path: &Path) {
+        let Some(ancestor) = path
+            .ancestors()
+            .skip(1)
+            .find(|ancestor| ancestor.as_os_str().is_empty() || fs::metadata(ancestor).is_ok())
+        else {
+            return;
+        };
+        let ancestor = match ancestor.as_os_str().is_empty() {
+            true => Path::new("."),
+            false => ancestor,
+        };
+        if !ancestor.is_dir() {
+            self.add(
+                key,
+                format!(
+                    "{} cannot be created, because {} is a file",
+                    path.display(),
+                    ancestor.display()
+                ),
+                "choose a path that does not lead through a file",
+            );
+        } else if !is_writable_directory(ancestor) {
+            self.add(
+                key,
+                format!(
+                    "{} cannot be created, because {} is not writable",
+                    path.display(),
+                    ancestor.display()
+                ),
+                format!(
+                    "create {} beforehand or choose a writable location",
+                    path.display()
+                ),
+            );
+        }
+    }
+}
+
+/// Whether files can be created in the directory, which is checked by creating and deleting a
+/// probe file.
+fn is_writable_directory(path: &Path) -> bool {
+    let probe = path.join(format!(".jaem_write_test_{}", std::process::id()));
+    match OpenOptions::new().write(true).create_new(true).open(&probe) {
+        Ok(_) => {
+            let _ = fs::remove_file(probe);
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+fn file_name_hint(key: &str) -> &'static str {
+    match key {
+        "user_discovery_config.storage_path" => "users.json",
+        _ => "log_signing_key",
+    }
+}
+
+/// Whether two services listening on the given addresses and ports would conflict.
+fn addresses_overlap(first: &SocketAddr, second: &SocketAddr) -> bool {
+    first.port() == second.port()
+        && (first.ip() == second.ip()
+            || first.ip().is_unspecified()
+            || second.ip().is_unspecified())
+}
+
+impl MessageDeliveryConfig {
+    fn check(&self, problems: &mut Problems) {
+        problems.check_address("message_delivery_config.address", &self.address);
+        problems.check_port("message_delivery_config.port", self.port);
+        problems.check_directory("message_delivery_config.storage_path", &self.storage_path);
+        problems.check_directory(
+            "message_delivery_config.share_directory",
+            &self.share_directory,
+        );
+        problems.check_duration("message_delivery_config.share_ttl", self.share_ttl);
+        problems.check_duration(
+            "message_delivery_config.deletion_timeout",
+            self.deletion_timeout,
+        );
+        problems.check_duration(
+            "message_delivery_config.auth_clock_skew",
+            self.auth_clock_skew,
+        );
+        problems.check_duration(
+            "message_delivery_config.shutdown_timeout",
+            self.shutdown_timeout,
+        );
+        if let Some(tls) = &self.tls {
+            problems.check_tls("message_delivery_config", tls);
+        }
+        if self.storage_path == self.share_directory {
+            problems.add(
+                "message_delivery_config.share_directory",
+                "messages and shared data would be stored in the same directory",
+                "use a separate directory, e.g. \"./share\"",
+            );
+        }
+    }
+}
+
+impl UserDiscoveryConfig {
+    fn check(&self, problems: &mut Problems) {
+        problems.check_address("user_discovery_config.address", &self.address);
+        problems.check_port("user_discovery_config.port", self.port);
+        problems.check_file("user_discovery_config.storage_path", &self.storage_path);
+        problems.check_directory(
+            "user_discovery_config.profile_picture_directory",
+            &self.profile_picture_directory,
+        );
+        problems.check_file(
+            "user_discovery_config.log_signing_key_path",
+            &self.log_signing_key_path,
+        );
+        problems.check_duration(
+            "user_discovery_config.auth_clock_skew",
+            self.auth_clock_skew,
+        );
+        problems.check_duration(
+            "user_discovery_config.shutdown_timeout",
+            self.shutdown_timeout,
+        );
+        if let Some(tls) = &self.tls {
+            problems.check_tls("user_discovery_config", tls);
+        }
+        if self.max_profile_picture_size == 0 {
+            problems.add(
+                "user_discovery_config.max_profile_picture_size",
+                "no profile picture can be uploaded with a maximum size of 0 bytes",
+                "use a size in bytes like 2097152 (2 MiB)",
+            );
+        }
+
+        let rules = &self.username_rules;
+        if rules.min_length == 0 {
+            problems.add(
+                "user_discovery_config.username_rules.min_length",
+                "usernames cannot be empty",
+                "use a minimum length of at least 1",
+            );
+        }
+        if rules.min_length > rules.max_length {
+            problems.add(
+                "user_discovery_config.username_rules.max_length",
+                format!(
+                    "the maximum length {} is smaller than min_length {}",
+                    rules.max_length, rules.min_length
+                ),
+                "raise max_length or lower min_length",
+            );
+        }
+        if rules.allowed_classes.is_empty() && rules.allowed_symbols.is_empty() {
+            problems.add(
+                "user_discovery_config.username_rules.allowed_classes",
+                "no characters are allowed in usernames",
+                "allow at least one class, e.g. [\"letters\", \"digits\"]",
+            );
+        }
+
+        let limits = &self.contact_discovery;
+        if !(1..=32).contains(&limits.min_hash_length) {
+            problems.add(
+                "user_discovery_config.contact_discovery.min_hash_length",
+                format!(
+                    "hashes are between 1 and 32 bytes long, not {}",
+                    limits.min_hash_length
+                ),
+                "use a length between 4 and 32",
+            );
+        }
+        if limits.max_batch_size == 0 {
+            problems.add(
+                "user_discovery_config.contact_discovery.max_batch_size",
+                "requests could not contain any hashes",
+                "allow at least one hash per request, e.g. 100",
+            );
+        }
+        if (limits.hashes_per_hour as usize) < limits.max_batch_size {
+            problems.add(
+                "user_discovery_config.contact_discovery.hashes_per_hour",
+                format!(
+                    "{} hashes per hour are less than max_batch_size {}, so full batches are always rejected",
+                    limits.hashes_per_hour, limits.max_batch_size
+                ),
+                "raise hashes_per_hour or lower max_batch_size",
+            );
+        }
+    }
+}
+
+/// The levels that can be given in `logging.level`, from the most to the least verbose.
+const LOG_LEVELS: [&str; 6] = ["trace", "debug", "info", "warn", "error", "off"];
+
+impl LoggingConfig {
+    fn check(&self, problems: &mut Problems) {
+        // every directive is a level, optionally preceded by a module, e.g. jaem_common=debug
+        let invalid = self
+            .level
+            .split(',')
+            .map(|directive| directive.rsplit('=').next().unwrap_or_default().trim())
+            .find(|level| !LOG_LEVELS.contains(&level.to_lowercase().as_str()));
+        if let Some(level) = invalid {
+            problems.add(
+                "logging.level",
+                format!("'{}' is not a log level", level),
+                format!(
+                    "use one of {}, e.g. \"info\" or \"info,jaem_user_discovery=debug\"",
+                    LOG_LEVELS.join(", ")
+                ),
+            );
+        }
+    }
+}
+
+impl JaemConfig {
+    /// Checks all settings and reports every problem found, instead of failing on the first one.
+    pub fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut problems = Problems::default();
+        if let Some(config) = &self.message_delivery_config {
+            config.check(&mut problems);
+        }
+        if let Some(config) = &self.user_discovery_config {
+            config.check(&mut problems);
+        }
+        self.logging.check(&mut problems);
+
+        if let (Some(md_config), Some(ud_config)) =
+            (&self.message_delivery_config, &self.user_discovery_config)
+        {
+            if let (Ok(md_addr), Ok(ud_addr)) = (md_config.socket_addr(), ud_config.socket_addr()) {
+                if addresses_overlap(&md_addr, &ud_addr) {
+                    problems.add(
+                        "user_discovery_config.port",
+                        format!(
+                            "port {} is already used by message_delivery_config.port",
+                            ud_addr.port()
+                        ),
+                        format!(
+                            "use different ports for the services, e.g. {}",
+                            if md_addr.port() == 8082 { 8081 } else { 8082 }
+                        ),
+                    );
+                }
+            }
+        }
+
+        match problems.0.is_empty() {
+            true => Ok(()),
+            false => Err(ValidationErrors(problems.0)),
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use std::fs;
+
+use jaem_config::{loader::ConfigLoader, reload::LiveConfig, LogFormat};
+
+fn live_config(file_path: &str) -> LiveConfig {
+    let loader = ConfigLoader::new(
+        vec!["--config".to_string(), file_path.to_string()],
+        Vec::new(),
+    )
+    .unwrap();
+    let config = loader.load().unwrap();
+    LiveConfig::new(loader, config)
+}
+
+#[test]
+fn tunable_settings_are_reloaded() {
+    let file_path = "temp_hot_reload_01.toml";
+    fs::write(
+        file_path,
+        "[message_delivery_config]\nport = 9100\nshare_ttl = 600\n",
+    )
+    .unwrap();
+    let live_config = live_config(file_path);
+
+    // nothing changed
+    assert!(live_config.reload().unwrap().is_empty());
+
+    fs::write(
+        file_path,
+        "[message_delivery_config]\nport = 9101\nshare_ttl = 60\n",
+    )
+    .unwrap();
+    let report = live_config.reload().unwrap();
+    let keys = |settings: &Vec<jaem_config::reload::ChangedSetting>| {
+        settings
+            .iter()
+            .map(|setting| setting.key.clone())
+            .collect::<Vec<String>>()
+    };
+    assert_eq!(keys(&report.applied), ["message_delivery_config.share_ttl"]);
+    assert_eq!(keys(&report.not_applied), ["message_delivery_config.port"]);
+
+    // the port keeps its value until the service is restarted
+    let md_config = live_config.get().get_message_delivery_config();
+    assert_eq!(md_config.share_ttl, 60);
+    assert_eq!(md_config.port, 9100);
+
+    // Clean up
+    fs::remove_file(file_path).unwrap();
+}
+
+#[test]
+fn invalid_changes_keep_the_current_configuration() {
+    let file_path = "temp_hot_reload_02.toml";
+    fs::write(file_path, "[user_discovery_config]\nauth_clock_skew = 30\n").unwrap();
+    let live_config = live_config(file_path);
+
+    fs::write(file_path, "[user_discovery_config]\nauth_clock_skew = 0\n").unwrap();
+    let err = live_config.reload().unwrap_err();
+    assert!(format!("{:#}", err).contains("user_discovery_config.auth_clock_skew"));
+
+    fs::write(file_path, "[user_discovery_config\n").unwrap();
+    assert!(live_config.reload().is_err());
+
+    let ud_config = live_config.get().user_discovery_config.clone().unwrap();
+    assert_eq!(ud_config.auth_clock_skew, 30);
+
+    // Clean up
+    fs::remove_file(file_path).unwrap();
+}
+
+#[test]
+fn log_level_is_reloaded_but_not_the_format() {
+    let file_path = "temp_hot_reload_03.toml";
+    fs::write(file_path, "[logging]\nlevel = \"info\"\n").unwrap();
+    let live_config = live_config(file_path);
+
+    fs::write(
+        file_path,
+        "[logging]\nlevel = \"warn,jaem_user_discovery=debug\"\nformat = \"json\"\n",
+    )
+    .unwrap();
+    let report = live_config.reload().unwrap();
+    assert_eq!(report.applied[0].key, "logging.level");
+    assert_eq!(report.not_applied[0].key, "logging.format");
+    let logging = live_config.get().logging.clone();
+    assert_eq!(logging.level, "warn,jaem_user_discovery=debug");
+    assert_eq!(logging.format, LogFormat::Pretty);
+
+    // unknown levels are rejected
+    fs::write(file_path, "[logging]\nlevel = \"loud\"\n").unwrap();
+    let err = live_config.reload().unwrap_err();
+    assert!(format!("{:#}", err).contains("logging.level"));
+
+    // Clean up
+    fs::remove_file(file_path).unwrap();
+}
+
+#[test]
+fn removed_sections_need_a_restart() {
+    let file_path = "temp_hot_reload_04.toml";
+    fs::write(
+        file_path,
+        "[message_delivery_config]\nstorage_path = \"./temp_hot_reload_04\"\nshare_ttl = 60\n\n[user_discovery_config]\nport = 9200\n",
+    )
+    .unwrap();
+    let live_config = live_config(file_path);
+
+    fs::write(file_path, "[user_discovery_config]\nport = 9200\n").unwrap();
+    let report = live_config.reload().unwrap();
+    assert!(report.applied.is_empty());
+    assert_eq!(report.not_applied.len(), 1);
+    assert_eq!(report.not_applied[0].key, "message_delivery_config");
+    assert!(report.not_applied[0].new.is_none());
+
+    // the message delivery keeps its storage instead of falling back to the defaults
+    let md_config = live_config.get().get_message_delivery_config();
+    assert_eq!(
+        md_config.storage_path.to_str(),
+        Some("./temp_hot_reload_04")
+    );
+    assert_eq!(md_config.share_ttl, 60);
+
+    // Clean up
+    fs::remove_file(file_path).unwrap();
+    let _ = fs::remove_dir_all("./temp_hot_reload_04");
+}
@@ -0,0 +1,166 @@
+use std::fs;
+
+use jaem_config::{loader::ConfigLoader, CharacterClass, TlsVersion};
+
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+    vars.iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+#[test]
+fn later_layers_override_earlier_ones() {
+    let file_path = "temp_layered_config_01.toml";
+    fs::write(
+        file_path,
+        "[user_discovery_config]\nport = 9000\naddress = \"127.0.0.1\"\n\n[user_discovery_config.username_rules]\nmax_length = 10\n",
+    )
+    .unwrap();
+
+    let loader = ConfigLoader::new(
+        args(&["--config", file_path, "--user-discovery-port=9002"]),
+        env(&[
+            ("JAEM_USER_DISCOVERY_PORT", "9001"),
+            ("JAEM_USER_DISCOVERY_USERNAME_RULES_UNIQUE", "true"),
+            (
+                "JAEM_USER_DISCOVERY_USERNAME_RULES_ALLOWED_CLASSES",
+                "letters, digits",
+            ),
+            ("JAEM_MESSAGE_DELIVERY_SHARE_DIRECTORY", "/tmp/share"),
+            ("HOME", "/root"),
+        ]),
+    )
+    .unwrap();
+    let config = loader.load().unwrap();
+    let ud_config = config.user_discovery_config.unwrap();
+    assert_eq!(ud_config.port, 9002);
+    assert_eq!(ud_config.address, "127.0.0.1");
+    assert_eq!(ud_config.username_rules.max_length, 10);
+    assert!(ud_config.username_rules.unique);
+    assert_eq!(
+        ud_config.username_rules.allowed_classes,
+        [CharacterClass::Letters, CharacterClass::Digits]
+    );
+    let md_config = config.message_delivery_config.unwrap();
+    assert_eq!(md_config.share_directory.to_str(), Some("/tmp/share"));
+    assert_eq!(md_config.port, 8081);
+
+    // Clean up
+    fs::remove_file(file_path).unwrap();
+}
+
+#[test]
+fn invalid_configurations_are_rejected() {
+    let file_path = "temp_layered_config_02.toml";
+    fs::write(file_path, "[user_discovery_config\nport = 9000\n").unwrap();
+    let loader = ConfigLoader::new(args(&[]), env(&[("JAEM_CONFIG", file_path)])).unwrap();
+    let err = loader.load().unwrap_err();
+    assert!(format!("{:#}", err).contains("Could not parse"));
+    // the broken file is left alone
+    assert_eq!(
+        fs::read_to_string(file_path).unwrap(),
+        "[user_discovery_config\nport = 9000\n"
+    );
+
+    // a missing file is only fine if no path was given
+    let loader = ConfigLoader::new(args(&["--config", "missing.toml"]), env(&[])).unwrap();
+    assert!(loader.load().is_err());
+
+    let loader = ConfigLoader::new(
+        args(&["--config", "missing.toml"]),
+        env(&[("JAEM_USER_DISCOVERY_PORT", "eighty")]),
+    )
+    .unwrap();
+    assert!(loader.load().is_err());
+
+    // unknown variables are ignored and reported, unlike invalid values of known ones
+    let loader =
+        ConfigLoader::new(args(&[]), env(&[("JAEM_USER_DISCOVERY_PROT", "8080")])).unwrap();
+    let (_, ignored) = loader.load_reporting_ignored().unwrap();
+    assert_eq!(ignored, ["JAEM_USER_DISCOVERY_PROT"]);
+    let loader =
+        ConfigLoader::new(args(&[]), env(&[("JAEM_USER_DISCOVERY_PORT", "eighty")])).unwrap();
+    assert!(format!("{:#}", loader.load().unwrap_err()).contains("JAEM_USER_DISCOVERY_PORT"));
+
+    assert!(ConfigLoader::new(args(&["--verbose", "true"]), env(&[])).is_err());
+    assert!(ConfigLoader::new(args(&["--user-discovery-port"]), env(&[])).is_err());
+
+    // Clean up
+    fs::remove_file(file_path).unwrap();
+}
+
+#[test]
+fn missing_sections_stay_unset() {
+    let file_path = "temp_layered_config_03.toml";
+    fs::write(file_path, "[user_discovery_config]\nport = 9000\n").unwrap();
+
+    let loader = ConfigLoader::new(args(&["--config", file_path]), env(&[])).unwrap();
+    let config = loader.load().unwrap();
+    assert!(config.message_delivery_config.is_none());
+    assert_eq!(config.user_discovery_config.unwrap().port, 9000);
+
+    // overriding a setting of a missing section adds the section with its defaults
+    let loader = ConfigLoader::new(
+        args(&["--config", file_path, "--message-delivery-port", "9001"]),
+        env(&[]),
+    )
+    .unwrap();
+    let md_config = loader.load().unwrap().message_delivery_config.unwrap();
+    assert_eq!(md_config.port, 9001);
+    assert_eq!(md_config.share_directory.to_str(), Some("./share"));
+
+    // Clean up
+    fs::remove_file(file_path).unwrap();
+}
+
+#[test]
+fn tls_can_be_configured_with_overrides() {
+    let dir = "temp_layered_config_04";
+    fs::create_dir_all(dir).unwrap();
+    for file in ["certificate.pem", "key.pem", "ca.pem"] {
+        fs::write(format!("{}/{}", dir, file), "").unwrap();
+    }
+    let file_path = format!("{}/config.toml", dir);
+    fs::write(&file_path, "[user_discovery_config]\nport = 9000\n").unwrap();
+
+    // the tls table is added although the file has none
+    let certificate = format!("{}/certificate.pem", dir);
+    let key = format!("{}/key.pem", dir);
+    let loader = ConfigLoader::new(
+        args(&[
+            "--config",
+            &file_path,
+            "--user-discovery-tls-admin-client-ca-path",
+            &format!("{}/ca.pem", dir),
+        ]),
+        env(&[
+            ("JAEM_USER_DISCOVERY_TLS_CERTIFICATE_PATH", &certificate),
+            ("JAEM_USER_DISCOVERY_TLS_KEY_PATH", &key),
+            ("JAEM_USER_DISCOVERY_TLS_MIN_VERSION", "1.3"),
+        ]),
+    )
+    .unwrap();
+    let (config, ignored) = loader.load_reporting_ignored().unwrap();
+    assert!(ignored.is_empty());
+    let tls = config.user_discovery_config.unwrap().tls.unwrap();
+    assert_eq!(tls.certificate_path.to_str(), Some(certificate.as_str()));
+    assert_eq!(tls.key_path.to_str(), Some(key.as_str()));
+    assert_eq!(tls.min_version, TlsVersion::Tls13);
+    assert!(tls.admin_client_ca_path.is_some());
+
+    // a table that is added needs all of its required settings
+    let loader = ConfigLoader::new(
+        args(&["--config", &file_path]),
+        env(&[("JAEM_USER_DISCOVERY_TLS_KEY_PATH", &key)]),
+    )
+    .unwrap();
+    let err = format!("{:#}", loader.load().unwrap_err());
+    assert!(err.contains("certificate_path"), "{}", err);
+
+    // Clean up
+    fs::remove_dir_all(dir).unwrap();
+}
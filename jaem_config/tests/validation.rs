@@ -0,0 +1,107 @@
+use std::fs;
+
+use jaem_config::{JaemConfig, TlsConfig, TlsVersion, UserDiscoveryConfig};
+
+#[test]
+fn every_problem_is_reported() {
+    let directory = "temp_validation_01";
+    fs::create_dir_all(directory).unwrap();
+
+    let mut config = JaemConfig::create_default();
+    let md_config = config.message_delivery_config.as_mut().unwrap();
+    md_config.address = "localhost".to_string();
+    md_config.port = 8081;
+    let ud_config = config.user_discovery_config.as_mut().unwrap();
+    ud_config.address = "127.0.0.1".to_string();
+    ud_config.port = 8081;
+    ud_config.storage_path = directory.into();
+    ud_config.username_rules.min_length = 40;
+    ud_config.contact_discovery.hashes_per_hour = 10;
+
+    let errors = config.validate().unwrap_err();
+    let keys: Vec<&str> = errors
+        .0
+        .iter()
+        .map(|problem| problem.key.as_str())
+        .collect();
+    assert_eq!(
+        keys,
+        [
+            "message_delivery_config.address",
+            "user_discovery_config.storage_path",
+            "user_discovery_config.username_rules.max_length",
+            "user_discovery_config.contact_discovery.hashes_per_hour",
+        ]
+    );
+    assert!(errors.0[1]
+        .suggestion
+        .contains(&format!("{}/users.json", directory)));
+
+    // services on the same port conflict once both addresses are valid
+    let md_config = config.message_delivery_config.as_mut().unwrap();
+    md_config.address = "0.0.0.0".to_string();
+    let errors = config.validate().unwrap_err();
+    let conflict = errors
+        .0
+        .iter()
+        .find(|problem| problem.key == "user_discovery_config.port")
+        .unwrap();
+    assert!(conflict.message.contains("message_delivery_config.port"));
+    assert!(conflict.suggestion.contains("8082"));
+    assert!(errors.to_string().contains("hint: "));
+
+    // Clean up
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn shipped_test_config_has_no_port_conflict() {
+    let contents = fs::read_to_string("../testconfig.toml").unwrap();
+    let config: JaemConfig = toml::from_str(&contents).unwrap();
+    let errors = match config.validate() {
+        Ok(()) => return,
+        Err(errors) => errors,
+    };
+    // the paths below /var/lib may not exist where the tests run
+    assert!(errors
+        .0
+        .iter()
+        .all(|problem| problem.key.ends_with("storage_path")));
+}
+
+#[test]
+fn storage_path_is_not_created_as_directory() {
+    let file_path = "temp_validation_03/nested/users.json";
+    let mut config = UserDiscoveryConfig::default();
+    config.set_storage_path(file_path).unwrap();
+    assert!(fs::metadata("temp_validation_03/nested").unwrap().is_dir());
+    assert!(fs::metadata(file_path).is_err());
+
+    // Clean up
+    fs::remove_dir_all("temp_validation_03").unwrap();
+}
+
+#[test]
+fn tls_files_have_to_exist() {
+    let mut config = JaemConfig::create_default();
+    config.message_delivery_config.as_mut().unwrap().tls = Some(TlsConfig {
+        certificate_path: "Cargo.toml".into(),
+        key_path: "missing_key.pem".into(),
+        min_version: TlsVersion::Tls13,
+        admin_client_ca_path: Some("src".into()),
+    });
+
+    let errors = config.validate().unwrap_err();
+    let keys: Vec<&str> = errors
+        .0
+        .iter()
+        .map(|problem| problem.key.as_str())
+        .collect();
+    assert_eq!(
+        keys,
+        [
+            "message_delivery_config.tls.key_path",
+            "message_delivery_config.tls.admin_client_ca_path",
+        ]
+    );
+}
@@ -2,8 +2,9 @@ use std::time::UNIX_EPOCH;
 
 use anyhow::bail;
 use ed25519_dalek::{Signature, VerifyingKey};
+use jaem_common::metrics::AuthFailure;
 
-use crate::sign_algos::AlgoSign;
+use crate::{metrics::METRICS, sign_algos::AlgoSign};
 
 /// A representation of a proof of authenticity as is needed for retrieving and
 /// deleting messages.
@@ -59,35 +60,41 @@ impl AuthProof {
     }
 
     /// Verifies the proof. Returns an Error if the public key is not a valid key.
-    /// Otherwise returns either Ok(true) or Ok(false). False is returned if the timestamp is
-    /// expired, the signature has been tampered with or the proof could otherwise not be verified.
-    pub fn verify(&self) -> Result<bool, anyhow::Error> {
-        match self.algorithm {
-            AlgoSign::ED25519 => Ok(self.verify_ed25519()?),
+    /// Otherwise returns either Ok(true) or Ok(false). False is returned if the timestamp differs
+    /// from the current time by more than `max_clock_skew` seconds, the signature has been
+    /// tampered with or the proof could otherwise not be verified. Failures are counted by
+    /// their reason.
+    pub fn verify(&self, max_clock_skew: u64) -> Result<bool, anyhow::Error> {
+        let verified = match self.algorithm {
+            AlgoSign::ED25519 => self.verify_ed25519(max_clock_skew),
+        };
+        if let Err(failure) = verified {
+            METRICS.http.auth_failure(failure);
+            if failure == AuthFailure::BadKey {
+                bail!("The provided key is not valid.");
+            }
         }
+        Ok(verified.is_ok())
     }
 
-    fn verify_ed25519(&self) -> Result<bool, anyhow::Error> {
+    fn verify_ed25519(&self, max_clock_skew: u64) -> Result<(), AuthFailure> {
         let mut encoded_pub_key = [0u8; 32];
         let mut encoded_sig = [0u8; 64];
         encoded_pub_key.copy_from_slice(self.pub_key.as_slice());
         encoded_sig.copy_from_slice(self.signature.as_slice());
 
         let message: Vec<u8> = [encoded_pub_key.as_slice(), &self.timestamp.to_be_bytes()].concat();
-        let verifying_key = VerifyingKey::from_bytes(&encoded_pub_key)?;
+        let verifying_key =
+            VerifyingKey::from_bytes(&encoded_pub_key).map_err(|_| AuthFailure::BadKey)?;
         let signature = Signature::from_bytes(&encoded_sig);
 
-        if self.timestamp > self.current_time {
-            // allow timestamp to be upto five seconds in the future
-            if self.timestamp - self.current_time > 5 {
-                return Ok(false);
-            }
-        } else if self.current_time - self.timestamp > 5 {
-            return Ok(false);
+        // the timestamp may also be slightly in the future
+        if self.timestamp.abs_diff(self.current_time) > max_clock_skew {
+            return Err(AuthFailure::Expired);
         }
 
-        Ok(verifying_key
+        verifying_key
             .verify_strict(message.as_slice(), &signature)
-            .is_ok())
+            .map_err(|_| AuthFailure::BadSignature)
     }
 }
@@ -1,6 +1,8 @@
 pub mod authentication;
 pub mod message_deletion;
+pub mod metrics;
 pub mod request_handling;
 pub mod response_body;
+pub mod server;
 pub mod share_link;
 pub mod sign_algos;
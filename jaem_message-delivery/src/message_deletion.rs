@@ -1,13 +1,25 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::{collections::HashMap, fs};
 
+use anyhow::Context;
 use base64::{engine::general_purpose::URL_SAFE, Engine as _};
+use serde::{Deserialize, Serialize};
+
+/// Name of the file in the storage directory that keeps staged deletions while the service is not
+/// running.
+pub const STAGED_DELETIONS_FILE: &str = "staged_deletions.json";
+
+/// Outstanding deletions by the identifier of their ressource.
+pub type OutstandingDeletions = HashMap<Vec<u8>, OutstandingDeletion>;
 
 /// A Ressource that is intended to be deleted at a later time.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct OutstandingDeletion {
     pub timestamp: u64,
     pub identifier: Vec<u8>,
+    /// Number of messages in the mailbox when it was retrieved, which are counted as deleted.
+    #[serde(default)]
+    pub messages: u64,
 }
 
 impl OutstandingDeletion {
@@ -17,6 +29,7 @@ impl OutstandingDeletion {
         Self {
             timestamp,
             identifier: identifier.to_vec(),
+            messages: 0,
         }
     }
 
@@ -61,10 +74,61 @@ pub fn delete_expired_deletions(
                     return;
                 }
                 Err(e) => {
-                    eprintln!("{}", e);
+                    tracing::warn!("Could not delete expired shared data: {}", e);
                     return;
                 }
             }
         }
     }
 }
+
+/// Outstanding deletions of messages and shared data, as they are stored when the service shuts
+/// down.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct StagedDeletions {
+    messages: Vec<OutstandingDeletion>,
+    shares: Vec<OutstandingDeletion>,
+}
+
+/// Writes the outstanding deletions of messages and shared data to the storage directory, so that
+/// they survive a restart of the service.
+pub fn save_staged_deletions(
+    storage_path: &Path,
+    messages: &OutstandingDeletions,
+    shares: &OutstandingDeletions,
+) -> Result<(), anyhow::Error> {
+    let staged = StagedDeletions {
+        messages: messages.values().cloned().collect(),
+        shares: shares.values().cloned().collect(),
+    };
+    let path = storage_path.join(STAGED_DELETIONS_FILE);
+    // a failed write must not leave a truncated file behind
+    let temp_path = path.with_extension("json.tmp");
+    fs::write(&temp_path, serde_json::to_vec(&staged)?)
+        .with_context(|| format!("Could not write {}", temp_path.display()))?;
+    fs::rename(&temp_path, &path).with_context(|| format!("Could not write {}", path.display()))
+}
+
+/// Reads the outstanding deletions of messages and shared data saved by `save_staged_deletions`
+/// and removes the file, so that they are not restored again after a crash. Both maps are empty if
+/// nothing was saved.
+pub fn load_staged_deletions(
+    storage_path: &Path,
+) -> Result<(OutstandingDeletions, OutstandingDeletions), anyhow::Error> {
+    let path = storage_path.join(STAGED_DELETIONS_FILE);
+    let staged: StagedDeletions = match fs::read(&path) {
+        Ok(contents) => serde_json::from_slice(&contents)
+            .with_context(|| format!("Could not parse {}", path.display()))?,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => StagedDeletions::default(),
+        Err(err) => return Err(err).with_context(|| format!("Could not read {}", path.display())),
+    };
+    let _ = fs::remove_file(&path);
+    // deletions are keyed by their identifier
+    let by_identifier = |deletions: Vec<OutstandingDeletion>| {
+        deletions
+            .into_iter()
+            .map(|deletion| (deletion.identifier.clone(), deletion))
+            .collect()
+    };
+    Ok((by_identifier(staged.messages), by_identifier(staged.shares)))
+}
@@ -0,0 +1,93 @@
+use std::{collections::HashMap, fs, path::Path, sync::LazyLock};
+
+use jaem_common::metrics::HttpMetrics;
+use prometheus::{IntCounter, IntGauge};
+
+use crate::message_deletion::OutstandingDeletion;
+
+/// The metrics of the message delivery, exposed under `/metrics`.
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+pub struct Metrics {
+    pub http: HttpMetrics,
+    pub messages_stored: IntCounter,
+    pub messages_retrieved: IntCounter,
+    pub messages_deleted: IntCounter,
+    pub mailbox_bytes: IntGauge,
+    pub active_shares: IntGauge,
+    pub staged_deletions: IntGauge,
+}
+
+impl Metrics {
+    fn new() -> Metrics {
+        let metrics = Metrics {
+            http: HttpMetrics::new("message_delivery"),
+            messages_stored: IntCounter::new("messages_stored_total", "Messages received").unwrap(),
+            messages_retrieved: IntCounter::new(
+                "messages_retrieved_total",
+                "Messages handed out to their recipients",
+            )
+            .unwrap(),
+            messages_deleted: IntCounter::new(
+                "messages_deleted_total",
+                "Messages deleted after they were retrieved",
+            )
+            .unwrap(),
+            mailbox_bytes: IntGauge::new("mailbox_bytes", "Size of all mailboxes on disk").unwrap(),
+            active_shares: IntGauge::new("active_shares", "Shared data that has not expired")
+                .unwrap(),
+            staged_deletions: IntGauge::new(
+                "staged_deletions",
+                "Retrieved mailboxes waiting for their deletion",
+            )
+            .unwrap(),
+        };
+        metrics.http.register(metrics.messages_stored.clone());
+        metrics.http.register(metrics.messages_retrieved.clone());
+        metrics.http.register(metrics.messages_deleted.clone());
+        metrics.http.register(metrics.mailbox_bytes.clone());
+        metrics.http.register(metrics.active_shares.clone());
+        metrics.http.register(metrics.staged_deletions.clone());
+        metrics
+    }
+
+    /// Updates the number of staged message deletions and active shares.
+    pub fn set_outstanding(
+        &self,
+        message_deletions: &HashMap<Vec<u8>, OutstandingDeletion>,
+        share_deletions: &HashMap<Vec<u8>, OutstandingDeletion>,
+    ) {
+        self.staged_deletions.set(message_deletions.len() as i64);
+        self.active_shares.set(share_deletions.len() as i64);
+    }
+
+    /// Adds up the size of the mailboxes in the storage directory.
+    pub fn measure_mailboxes(&self, storage_path: &Path) {
+        let bytes: u64 = fs::read_dir(storage_path)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok()?.metadata().ok())
+            .filter(|meta| meta.is_file())
+            .map(|meta| meta.len())
+            .sum();
+        self.mailbox_bytes.set(bytes as i64);
+    }
+}
+
+/// Counts the messages in a mailbox. Every message is preceded by its length. Counting stops at a
+/// length that exceeds the address space, since such a mailbox is corrupt.
+pub fn count_messages(mailbox: &[u8]) -> u64 {
+    const LEN_SIZE: usize = size_of::<usize>();
+    let mut count = 0;
+    let mut head = 0;
+    while head + LEN_SIZE <= mailbox.len() {
+        let mut len_bytes = [0u8; LEN_SIZE];
+        len_bytes.copy_from_slice(&mailbox[head..head + LEN_SIZE]);
+        count += 1;
+        match (head + LEN_SIZE).checked_add(usize::from_be_bytes(len_bytes)) {
+            Some(next) => head = next,
+            None => break,
+        }
+    }
+    count
+}
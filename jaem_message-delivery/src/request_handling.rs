@@ -1,6 +1,7 @@
 use crate::{
     authentication::AuthProof,
     message_deletion::OutstandingDeletion,
+    metrics::{count_messages, METRICS},
     response_body::{empty, full},
     share_link::ShareLink,
     sign_algos::AlgoSign,
@@ -11,6 +12,7 @@ use hyper::{
     body::{Body, Buf, Bytes},
     Request, Response, StatusCode,
 };
+use jaem_common::{error::ApiError, logging::Redacted};
 use jaem_config::MessageDeliveryConfig;
 use std::{
     collections::HashMap,
@@ -31,7 +33,7 @@ where
     while data.remaining() > 0 {
         buf.push(data.get_u8());
     }
-    return buf;
+    buf
 }
 
 /// Deals with requests send to /get_messages.
@@ -46,15 +48,10 @@ where
     let body = body_as_vec(body).await;
     let auth_proof = match AuthProof::new(&body) {
         Ok(auth_proof) => auth_proof,
-        Err(e) => {
-            return Ok(Response::builder()
-                .status(StatusCode::BAD_REQUEST)
-                .body(full(e.to_string()))
-                .unwrap())
-        }
+        Err(e) => return Ok(ApiError::InvalidRequest(e.to_string()).into_response()),
     };
 
-    match auth_proof.verify() {
+    match auth_proof.verify(config.auth_clock_skew) {
         Ok(true) => {
             let mut file_path = config.storage_path.clone();
             file_path.push(URL_SAFE.encode(auth_proof.pub_key.as_slice()));
@@ -64,27 +61,24 @@ where
             };
             let mut buffer = Vec::new();
             file.read_to_end(&mut buffer).unwrap();
-            let delete_later =
+            let messages = count_messages(&buffer);
+            METRICS.messages_retrieved.inc_by(messages);
+            let mut delete_later =
                 OutstandingDeletion::new(auth_proof.current_time, &auth_proof.pub_key);
+            delete_later.messages = messages;
             let mut outstanding_deletions = outstanding_deletions.lock().unwrap();
             outstanding_deletions.insert(auth_proof.pub_key, delete_later);
-            return Ok(Response::builder()
+            Ok(Response::builder()
                 .status(StatusCode::OK)
                 .body(full(buffer))
-                .unwrap());
-        }
-        Ok(false) => {
-            return Ok(Response::builder()
-                .status(StatusCode::FORBIDDEN)
-                .body(full("Signature or timestamp are invalid."))
-                .unwrap())
-        }
-        Err(_) => {
-            return Ok(Response::builder()
-                .status(StatusCode::BAD_REQUEST)
-                .body(full("The provided key is not valid."))
                 .unwrap())
         }
+        Ok(false) => Ok(
+            ApiError::Forbidden("Signature or timestamp are invalid.".to_string()).into_response(),
+        ),
+        Err(_) => Ok(
+            ApiError::InvalidRequest("The provided key is not valid.".to_string()).into_response(),
+        ),
     }
 }
 
@@ -98,23 +92,20 @@ where
 {
     let body = body_as_vec(body).await;
 
-    let signing_algorithm = match AlgoSign::from_repr(body[0]) {
+    let signing_algorithm = match body.first().and_then(|algo| AlgoSign::from_repr(*algo)) {
         Some(algo) => algo,
         None => {
-            return Ok(Response::builder()
-                .status(StatusCode::BAD_REQUEST)
-                .body(full(format!(
+            return Ok(ApiError::InvalidRequest(format!(
                 "The specified signing algorithm is not supported. Currently supported are: \n{}\n",
                 AlgoSign::list()
-            )))
-                .unwrap())
+            ))
+            .into_response())
         }
     };
     if body.len() <= signing_algorithm.get_key_len() + 1 {
-        return Ok(Response::builder()
-            .status(StatusCode::BAD_REQUEST)
-            .body(full("The given key is too short."))
-            .unwrap());
+        return Ok(
+            ApiError::InvalidRequest("The given key is too short.".to_string()).into_response(),
+        );
     }
 
     let pub_key = body[1..=signing_algorithm.get_key_len()].to_vec();
@@ -129,9 +120,15 @@ where
         .unwrap();
 
     let message = &body[signing_algorithm.get_key_len() + 1..];
-    file.write(&message.len().to_be_bytes())
+    file.write_all(&message.len().to_be_bytes())
         .expect("could not write to file.");
-    file.write(&message).expect("could not write to file.");
+    file.write_all(message).expect("could not write to file.");
+    METRICS.messages_stored.inc();
+    tracing::debug!(
+        mailbox = %Redacted(&pub_key),
+        bytes = message.len(),
+        "Stored a message"
+    );
 
     Ok(Response::new(empty()))
 }
@@ -148,57 +145,42 @@ where
     let body = body_as_vec(body).await;
     let auth_proof = match AuthProof::new(&body) {
         Ok(auth_proof) => auth_proof,
-        Err(e) => {
-            return Ok(Response::builder()
-                .status(StatusCode::BAD_REQUEST)
-                .body(full(e.to_string()))
-                .unwrap())
-        }
+        Err(e) => return Ok(ApiError::InvalidRequest(e.to_string()).into_response()),
     };
-    match auth_proof.verify() {
+    match auth_proof.verify(config.auth_clock_skew) {
         Ok(true) => {
             let file_path = config.storage_path.clone();
             let mut outstanding_deletions = outstanding_deletions.lock().unwrap();
             let deletion = match outstanding_deletions.get(&auth_proof.pub_key) {
                 Some(del) => del,
                 None => {
-                    return Ok(Response::builder()
-                        .status(StatusCode::CONFLICT)
-                        .body(full("You cannot delete unretrieved messages."))
-                        .unwrap())
+                    return Ok(ApiError::Conflict(
+                        "You cannot delete unretrieved messages.".to_string(),
+                    )
+                    .into_response())
                 }
             };
-            match deletion.delete(file_path) {
-                Err(_) => {
-                    return Ok(Response::builder()
-                        .status(StatusCode::INTERNAL_SERVER_ERROR)
-                        .body(full("Could not delete Messages."))
-                        .unwrap())
-                }
-                Ok(_) => {}
+            let deleted = deletion.messages;
+            if deletion.delete(file_path).is_err() {
+                return Ok(
+                    ApiError::Internal("Could not delete Messages.".to_string()).into_response()
+                );
             }
 
             outstanding_deletions
                 .remove(&auth_proof.pub_key)
                 .expect("Deleted Messages, but could not remove from deletion queue.");
+            METRICS.messages_deleted.inc_by(deleted);
 
-            return Ok(Response::builder()
+            Ok(Response::builder()
                 .status(StatusCode::OK)
                 .body(full("Messages deleted"))
-                .unwrap());
-        }
-        Ok(false) => {
-            return Ok(Response::builder()
-                .status(StatusCode::FORBIDDEN)
-                .body(full("Invalid signature."))
-                .unwrap())
-        }
-        Err(_) => {
-            return Ok(Response::builder()
-                .status(StatusCode::BAD_REQUEST)
-                .body(full("The provided key is not valid."))
                 .unwrap())
         }
+        Ok(false) => Ok(ApiError::Forbidden("Invalid signature.".to_string()).into_response()),
+        Err(_) => Ok(
+            ApiError::InvalidRequest("The provided key is not valid.".to_string()).into_response(),
+        ),
     }
 }
 
@@ -226,20 +208,16 @@ where
     let mut share_file = match File::create(path) {
         Ok(file) => file,
         Err(_) => {
-            return Ok(Response::builder()
-                .status(StatusCode::INTERNAL_SERVER_ERROR)
-                .body(full("Could not create share file."))
-                .unwrap())
+            return Ok(
+                ApiError::Internal("Could not create share file.".to_string()).into_response(),
+            )
         }
     };
 
     let req_body = body_as_vec(body).await;
     match share_file.write_all(&req_body) {
         Err(_) => {
-            return Ok(Response::builder()
-                .status(StatusCode::INTERNAL_SERVER_ERROR)
-                .body(full("Could not write to share file."))
-                .unwrap())
+            Ok(ApiError::Internal("Could not write to share file.".to_string()).into_response())
         }
         Ok(_) => {
             // stage the shared data for deletoin at a later time
@@ -252,12 +230,12 @@ where
             let share_deletion = OutstandingDeletion::new(current_time, share_link.as_bytes());
             share_deletions.insert(share_link.clone().into_bytes(), share_deletion);
 
-            return Ok(Response::builder()
+            Ok(Response::builder()
                 .status(StatusCode::OK)
                 .body(full(share_link))
-                .unwrap());
+                .unwrap())
         }
-    };
+    }
 }
 
 /// Retrieves shared data.
@@ -271,10 +249,7 @@ where
     // cut of the /share/ part of the path
     let uri = &req.uri().path()[7..];
     if uri.contains('/') {
-        return Ok(Response::builder()
-            .status(StatusCode::NOT_FOUND)
-            .body(empty())
-            .unwrap());
+        return Ok(ApiError::NotFound("Shared data not found".to_string()).into_response());
     }
 
     let mut path = config.share_directory.clone();
@@ -283,10 +258,7 @@ where
     let mut share_file = match File::open(path) {
         Ok(file) => file,
         Err(_) => {
-            return Ok(Response::builder()
-                .status(StatusCode::NOT_FOUND)
-                .body(empty())
-                .unwrap())
+            return Ok(ApiError::NotFound("Shared data not found".to_string()).into_response())
         }
     };
 
@@ -294,15 +266,12 @@ where
     match share_file.read_to_end(&mut buf) {
         Ok(_) => {}
         Err(_) => {
-            return Ok(Response::builder()
-                .status(StatusCode::INTERNAL_SERVER_ERROR)
-                .body(full("Could not read from file"))
-                .unwrap())
+            return Ok(ApiError::Internal("Could not read from file".to_string()).into_response())
         }
     }
 
-    return Ok(Response::builder()
+    Ok(Response::builder()
         .status(StatusCode::OK)
         .body(full(buf))
-        .unwrap());
+        .unwrap())
 }
@@ -0,0 +1,315 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, UNIX_EPOCH};
+
+use anyhow::Context;
+use http_body_util::combinators::BoxBody;
+use hyper::body::{Bytes, Incoming};
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response};
+use hyper_util::{
+    rt::TokioExecutor,
+    server::{conn::auto, graceful::GracefulShutdown},
+};
+use jaem_common::{
+    error::ApiError,
+    health::{check_writable, healthz, Readiness, ReadinessCache},
+    logging::RequestSpan,
+    shutdown::drain_connections,
+    tls::{check_admin_access, TlsTermination},
+};
+use jaem_config::{
+    reload::{LiveConfig, WATCH_INTERVAL},
+    MessageDeliveryConfig,
+};
+use tracing::Instrument;
+
+use crate::message_deletion::{
+    delete_expired_deletions, load_staged_deletions, remove_expired_deletions,
+    save_staged_deletions, OutstandingDeletion,
+};
+use crate::metrics::METRICS;
+use crate::request_handling::{
+    delete_messages, get_shared_data, receive_messages, retrieve_messages, share_data,
+};
+
+/// How often expired staged deletions and shared data are removed.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The service is not ready if the sweeper did not run for this many seconds.
+const SWEEP_STALE_AFTER: u64 = 30;
+
+/// Route the requests to the correct functoin to deal with them, logging them and recording them
+/// in the metrics.
+async fn handle_request(
+    req: Request<Incoming>,
+    config: &MessageDeliveryConfig,
+    message_deletions: Arc<Mutex<HashMap<Vec<u8>, OutstandingDeletion>>>,
+    share_deletions: Arc<Mutex<HashMap<Vec<u8>, OutstandingDeletion>>>,
+    last_sweep: Arc<AtomicU64>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    // probes of orchestrators are frequent, so they are neither logged nor counted
+    match (req.method(), req.uri().path()) {
+        (&Method::GET, "/healthz") => return Ok(healthz()),
+        (&Method::GET, "/readyz") => {
+            let last_sweep = last_sweep.load(Ordering::Relaxed);
+            let cache = req.extensions().get::<Arc<ReadinessCache>>().cloned();
+            let readiness = match cache {
+                Some(cache) => {
+                    cache
+                        .get_or_check(async { readiness(config, last_sweep) })
+                        .await
+                }
+                None => readiness(config, last_sweep),
+            };
+            return Ok(readiness.into_response());
+        }
+        _ => {}
+    }
+
+    let method = req.method().clone();
+    let route = route_label(req.uri().path());
+    let request_span = RequestSpan::new(&req, route);
+    let mut response = route_request(req, config, message_deletions, share_deletions)
+        .instrument(request_span.span().clone())
+        .await?;
+    let elapsed = request_span.finish(&mut response);
+    METRICS
+        .http
+        .observe_request(&method, route, response.status(), elapsed);
+    Ok(response)
+}
+
+async fn route_request(
+    req: Request<Incoming>,
+    config: &MessageDeliveryConfig,
+    message_deletions: Arc<Mutex<HashMap<Vec<u8>, OutstandingDeletion>>>,
+    share_deletions: Arc<Mutex<HashMap<Vec<u8>, OutstandingDeletion>>>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    match (req.method(), req.uri().path()) {
+        (&Method::POST, "/send_message") => Ok(receive_messages(req, config).await?),
+        (&Method::POST, "/get_messages") => {
+            Ok(retrieve_messages(req, config, message_deletions).await?)
+        }
+        (&Method::POST, "/delete_messages") => {
+            Ok(delete_messages(req, config, message_deletions).await?)
+        }
+        (&Method::POST, "/share") => Ok(share_data(req, config, share_deletions).await?),
+        (&Method::GET, "/metrics") => {
+            if let Err(err) = check_admin_access(&req) {
+                return Ok(err.into_response());
+            }
+            METRICS.set_outstanding(
+                &message_deletions.lock().unwrap(),
+                &share_deletions.lock().unwrap(),
+            );
+            METRICS.measure_mailboxes(&config.storage_path);
+            Ok(METRICS.http.response())
+        }
+        _ => {
+            if req.method() == Method::GET && req.uri().path().starts_with("/share/") {
+                return get_shared_data(req, config).await;
+            }
+            Ok(ApiError::NotFound("Resource not found".to_string()).into_response())
+        }
+    }
+}
+
+/// The route of a request as it is reported in the metrics.
+fn route_label(path: &str) -> &'static str {
+    match path {
+        "/send_message" => "/send_message",
+        "/get_messages" => "/get_messages",
+        "/delete_messages" => "/delete_messages",
+        "/share" => "/share",
+        "/metrics" => "/metrics",
+        _ if path.starts_with("/share/") => "/share/{link}",
+        _ => "unknown",
+    }
+}
+
+/// Checks whether the message delivery can serve requests: its directories have to be writable and
+/// the sweeper has to have run in the last `SWEEP_STALE_AFTER` seconds. `last_sweep` is the UNIX
+/// time of its last run.
+pub fn readiness(config: &MessageDeliveryConfig, last_sweep: u64) -> Readiness {
+    let mut readiness = Readiness::default();
+    readiness.check("storage", check_writable(&config.storage_path));
+    readiness.check("storage", check_writable(&config.share_directory));
+    let since_sweep = unix_time().saturating_sub(last_sweep);
+    readiness.check(
+        "sweeper",
+        match since_sweep <= SWEEP_STALE_AFTER {
+            true => Ok(()),
+            false => Err(format!("last run {} seconds ago", since_sweep)),
+        },
+    );
+    readiness
+}
+
+/// Removes staged deletions and shared data after their timeouts every `SWEEP_INTERVAL`, always
+/// with the current configuration, and stores the time of every run in `last_sweep`.
+async fn sweep(
+    live_config: Arc<LiveConfig>,
+    message_deletions: Arc<Mutex<HashMap<Vec<u8>, OutstandingDeletion>>>,
+    share_deletions: Arc<Mutex<HashMap<Vec<u8>, OutstandingDeletion>>>,
+    last_sweep: Arc<AtomicU64>,
+) {
+    let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+    loop {
+        interval.tick().await;
+        let current_time = unix_time();
+
+        let md_config = live_config.get().get_message_delivery_config();
+        // remove staged deletions of outstanding message deletoins after the deletion timeout.
+        remove_expired_deletions(
+            &mut message_deletions.lock().unwrap(),
+            current_time,
+            md_config.deletion_timeout,
+        );
+        // delete shared data older than the share TTL.
+        delete_expired_deletions(
+            &mut share_deletions.lock().unwrap(),
+            current_time,
+            md_config.share_ttl,
+            md_config.share_directory,
+        );
+        METRICS.set_outstanding(
+            &message_deletions.lock().unwrap(),
+            &share_deletions.lock().unwrap(),
+        );
+        last_sweep.store(current_time, Ordering::Relaxed);
+    }
+}
+
+fn unix_time() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// Runs the message delivery with the current configuration until `shutdown` completes. Fails if
+/// the directories cannot be created or the address cannot be bound.
+///
+/// On shutdown no new connections are accepted, open connections get `shutdown_timeout` seconds
+/// to finish and staged deletions are saved, so that they are picked up again after a restart.
+pub async fn run(
+    live_config: Arc<LiveConfig>,
+    shutdown: impl Future<Output = ()>,
+) -> Result<(), anyhow::Error> {
+    // create the necessary directories.
+    let mut md_config = live_config.get().get_message_delivery_config();
+    md_config
+        .create_dirs()
+        .context("Could not create necessary directories")?;
+
+    // create ressources that are shared between threads, with the deletions that were staged when
+    // the service stopped the last time.
+    let (message_deletions, share_deletions) = load_staged_deletions(&md_config.storage_path)?;
+    let message_deletions: Arc<Mutex<HashMap<Vec<u8>, OutstandingDeletion>>> =
+        Arc::new(Mutex::new(message_deletions));
+    let share_deletions: Arc<Mutex<HashMap<Vec<u8>, OutstandingDeletion>>> =
+        Arc::new(Mutex::new(share_deletions));
+
+    // the address was validated when the configuration was loaded
+    let addr = md_config.socket_addr()?;
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("Could not listen on {}", addr))?;
+
+    // TLS settings and certificates are applied without a restart
+    let tls = Arc::new(TlsTermination::new(md_config.tls.as_ref())?);
+    tls.watch(WATCH_INTERVAL);
+    let reloaded_tls = Arc::clone(&tls);
+    live_config.on_reload(move |config, _| {
+        let md_config = config.get_message_delivery_config();
+        if let Err(err) = reloaded_tls.configure(md_config.tls.as_ref()) {
+            tracing::warn!("Keeping the current TLS settings: {:#}", err);
+        }
+    });
+
+    // expired deletions are removed in the background
+    let last_sweep = Arc::new(AtomicU64::new(0));
+    let sweeper = tokio::spawn(sweep(
+        Arc::clone(&live_config),
+        Arc::clone(&message_deletions),
+        Arc::clone(&share_deletions),
+        Arc::clone(&last_sweep),
+    ));
+
+    // probes share the outcome of the readiness checks for a few seconds
+    let readiness_cache = Arc::new(ReadinessCache::default());
+
+    let connections = GracefulShutdown::new();
+    tokio::pin!(shutdown);
+    loop {
+        let stream = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => stream,
+                Err(err) => {
+                    tracing::warn!("Could not accept a connection: {}", err);
+                    continue;
+                }
+            },
+            _ = &mut shutdown => break,
+        };
+        let message_deletions_mv = Arc::clone(&message_deletions);
+        let share_deletions_mv = Arc::clone(&share_deletions);
+        let last_sweep_mv = Arc::clone(&last_sweep);
+        let live_config_mv = Arc::clone(&live_config);
+        let readiness_cache = Arc::clone(&readiness_cache);
+        let tls = Arc::clone(&tls);
+        let connections = connections.watcher();
+        tokio::task::spawn(async move {
+            let stream = match tls.accept(stream).await {
+                Ok(stream) => stream,
+                Err(err) => {
+                    tracing::debug!("TLS handshake failed: {}", err);
+                    return;
+                }
+            };
+            let admin_access = stream.admin_access();
+            let io = hyper_util::rt::TokioIo::new(stream);
+            // HTTP/2 is detected by its preface, both with prior knowledge (h2c) and after ALPN
+            let builder = auto::Builder::new(TokioExecutor::new());
+            let connection = builder.serve_connection(
+                io,
+                service_fn(move |mut req| {
+                    req.extensions_mut().insert(admin_access);
+                    req.extensions_mut().insert(Arc::clone(&readiness_cache));
+                    let config = live_config_mv.get().get_message_delivery_config();
+                    let message_deletions = message_deletions_mv.clone();
+                    let share_deletions = share_deletions_mv.clone();
+                    let last_sweep = last_sweep_mv.clone();
+                    async move {
+                        handle_request(req, &config, message_deletions, share_deletions, last_sweep)
+                            .await
+                    }
+                }),
+            );
+            if let Err(err) = connections.watch(connection).await {
+                tracing::debug!("Connection closed with an error: {}", err);
+            }
+        });
+    }
+
+    // stop accepting connections and let the open ones finish
+    drop(listener);
+    sweeper.abort();
+    let md_config = live_config.get().get_message_delivery_config();
+    if !drain_connections(connections, Duration::from_secs(md_config.shutdown_timeout)).await {
+        tracing::warn!(
+            "Closing connections that did not finish within {} seconds",
+            md_config.shutdown_timeout
+        );
+    }
+    let message_deletions = message_deletions.lock().unwrap();
+    let share_deletions = share_deletions.lock().unwrap();
+    save_staged_deletions(
+        &md_config.storage_path,
+        &message_deletions,
+        &share_deletions,
+    )
+}
@@ -124,6 +124,12 @@ const ANIMALS: &[&str; 100] = &[
     "Mongoose",
 ];
 
+impl Default for ShareLink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ShareLink {
     /// Constructs a new one
     pub fn new() -> ShareLink {
@@ -143,6 +149,6 @@ impl ShareLink {
         link.push_str(self.animals.choose(&mut rng).unwrap());
         link.push_str(format!("{:0>4}", random_num.to_string()).as_str());
 
-        return link;
+        link
     }
 }
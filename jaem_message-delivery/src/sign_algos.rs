@@ -12,13 +12,13 @@ pub enum AlgoSign {
 impl AlgoSign {
     pub fn get_key_len(&self) -> usize {
         match self {
-            Self::ED25519 => return 32,
+            Self::ED25519 => 32,
         }
     }
 
     pub fn get_signature_len(&self) -> usize {
         match self {
-            Self::ED25519 => return 64,
+            Self::ED25519 => 64,
         }
     }
 
@@ -28,6 +28,6 @@ impl AlgoSign {
             list.push_str(&format!("{:?}", algo));
             list.push_str(", ");
         }
-        return list[0..list.len() - 2].to_string();
+        list[0..list.len() - 2].to_string()
     }
 }
@@ -0,0 +1,81 @@
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use http_body_util::{combinators::BoxBody, BodyExt};
+use hyper::{body::Bytes, Response, StatusCode};
+use jaem_common::health::healthz;
+use jaem_config::JaemConfig;
+use jaem_message_delivery::server::readiness;
+use serde_json::Value;
+
+async fn read(response: Response<BoxBody<Bytes, hyper::Error>>) -> (StatusCode, Value) {
+    let status = response.status();
+    let body = response.collect().await.unwrap().to_bytes();
+    (status, serde_json::from_slice(&body).unwrap())
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[tokio::test]
+async fn healthz_answers_ok() {
+    let (status, body) = read(healthz()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "ok");
+}
+
+#[tokio::test]
+async fn ready_with_writable_directories_and_a_recent_sweep() {
+    let test_dir = "./health_tests01";
+    let config = JaemConfig::create_default();
+    let mut md_config = config.get_message_delivery_config();
+    md_config.set_storage_path(test_dir).unwrap();
+    md_config
+        .set_share_dir(&format!("{}/share", test_dir))
+        .unwrap();
+
+    let (status, body) = read(readiness(&md_config, now()).into_response()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "ready");
+    assert_eq!(body["checks"]["storage"], "ok");
+    assert_eq!(body["checks"]["sweeper"], "ok");
+    // the probe files are removed again
+    assert_eq!(fs::read_dir(test_dir).unwrap().count(), 1);
+
+    fs::remove_dir_all(test_dir).unwrap();
+}
+
+#[tokio::test]
+async fn not_ready_without_sweeps_or_storage() {
+    let test_dir = "./health_tests02";
+    let config = JaemConfig::create_default();
+    let mut md_config = config.get_message_delivery_config();
+    md_config.set_storage_path(test_dir).unwrap();
+    md_config
+        .set_share_dir(&format!("{}/share", test_dir))
+        .unwrap();
+
+    // the sweeper never ran
+    let (status, body) = read(readiness(&md_config, 0).into_response()).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["status"], "not_ready");
+    assert_eq!(body["checks"]["storage"], "ok");
+    assert!(body["checks"]["sweeper"]
+        .as_str()
+        .unwrap()
+        .starts_with("last run"));
+
+    // the share directory was removed
+    fs::remove_dir_all(format!("{}/share", test_dir)).unwrap();
+    let (status, body) = read(readiness(&md_config, now()).into_response()).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    // the path and error are only logged
+    assert_eq!(body["checks"]["storage"], "not writable");
+    assert_eq!(body["checks"]["sweeper"], "ok");
+
+    fs::remove_dir_all(test_dir).unwrap();
+}
@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::UNIX_EPOCH;
+
+use ed25519_dalek::ed25519::signature::SignerMut;
+use ed25519_dalek::SigningKey;
+use http_body_util::BodyExt;
+use hyper::{Request, StatusCode};
+use jaem_config::JaemConfig;
+use jaem_message_delivery::metrics::{count_messages, METRICS};
+use jaem_message_delivery::request_handling::{
+    delete_messages, receive_messages, retrieve_messages,
+};
+use jaem_message_delivery::response_body::full;
+use rand::rngs::OsRng;
+
+/// Constructs a proof of authenticity for the given time.
+fn auth_proof(signing_key: &mut SigningKey, timestamp: u64) -> Vec<u8> {
+    let mut signed = signing_key.verifying_key().as_bytes().to_vec();
+    signed.extend_from_slice(&timestamp.to_be_bytes());
+    let mut proof = vec![0];
+    proof.extend_from_slice(&signing_key.sign(&signed).to_bytes());
+    proof.append(&mut signed);
+    proof
+}
+
+async fn metrics_text() -> String {
+    let body = METRICS.http.response().collect().await.unwrap().to_bytes();
+    String::from_utf8(body.to_vec()).unwrap()
+}
+
+#[tokio::test]
+async fn messages_and_auth_failures_are_counted() {
+    let test_dir = "./metrics_tests01";
+    let config = JaemConfig::create_default();
+    let mut md_config = config.get_message_delivery_config();
+    md_config.set_storage_path(test_dir).unwrap();
+    let now = std::time::SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    // Send two messages to the same mailbox
+    let mut signing_key = SigningKey::generate(&mut OsRng);
+    for message in ["first", "second"] {
+        let mut body = vec![0];
+        body.extend_from_slice(signing_key.verifying_key().as_bytes());
+        body.extend_from_slice(message.as_bytes());
+        let request = Request::builder().body(full(body)).unwrap();
+        receive_messages(request, &md_config).await.unwrap();
+    }
+
+    // An expired proof and a tampered signature are rejected
+    let deletions = Arc::new(Mutex::new(HashMap::new()));
+    let expired = auth_proof(&mut signing_key, now - 3600);
+    let request = Request::builder().body(full(expired)).unwrap();
+    let response = retrieve_messages(request, &md_config, deletions.clone())
+        .await
+        .unwrap();
+    assert_eq!(StatusCode::FORBIDDEN, response.status());
+    let mut tampered = auth_proof(&mut signing_key, now);
+    tampered[1] ^= 1;
+    let request = Request::builder().body(full(tampered)).unwrap();
+    let response = delete_messages(request, &md_config, deletions.clone())
+        .await
+        .unwrap();
+    assert_eq!(StatusCode::FORBIDDEN, response.status());
+
+    // Retrieve and delete the messages
+    METRICS.measure_mailboxes(Path::new(test_dir));
+    assert_eq!(2 * 8 + 11, METRICS.mailbox_bytes.get());
+    let request = Request::builder()
+        .body(full(auth_proof(&mut signing_key, now)))
+        .unwrap();
+    retrieve_messages(request, &md_config, deletions.clone())
+        .await
+        .unwrap();
+    METRICS.set_outstanding(&deletions.lock().unwrap(), &HashMap::new());
+    assert_eq!(1, METRICS.staged_deletions.get());
+    // a message that arrives after the retrieval is not counted as deleted
+    let mut body = vec![0];
+    body.extend_from_slice(signing_key.verifying_key().as_bytes());
+    body.extend_from_slice(b"late");
+    receive_messages(Request::builder().body(full(body)).unwrap(), &md_config)
+        .await
+        .unwrap();
+    let request = Request::builder()
+        .body(full(auth_proof(&mut signing_key, now)))
+        .unwrap();
+    delete_messages(request, &md_config, deletions.clone())
+        .await
+        .unwrap();
+    METRICS.measure_mailboxes(Path::new(test_dir));
+
+    let text = metrics_text().await;
+    for line in [
+        r#"jaem_messages_stored_total{service="message_delivery"} 3"#,
+        r#"jaem_messages_retrieved_total{service="message_delivery"} 2"#,
+        r#"jaem_messages_deleted_total{service="message_delivery"} 2"#,
+        r#"jaem_mailbox_bytes{service="message_delivery"} 0"#,
+        r#"jaem_auth_failures_total{reason="expired",service="message_delivery"} 1"#,
+        r#"jaem_auth_failures_total{reason="bad_signature",service="message_delivery"} 1"#,
+    ] {
+        assert!(text.contains(line), "{} missing in\n{}", line, text);
+    }
+
+    // Clean up
+    std::fs::remove_dir_all(test_dir).unwrap();
+}
+
+#[test]
+fn corrupt_mailboxes_are_counted_without_overflow() {
+    let mut mailbox = 5usize.to_be_bytes().to_vec();
+    mailbox.extend_from_slice(b"first");
+    mailbox.extend_from_slice(&usize::MAX.to_be_bytes());
+    mailbox.extend_from_slice(b"garbage");
+    assert_eq!(2, count_messages(&mailbox));
+}
@@ -1,5 +1,6 @@
 use std::fs;
 
+use http_body_util::BodyExt;
 use hyper::{Request, StatusCode};
 use jaem_config::JaemConfig;
 use jaem_message_delivery::{request_handling::receive_messages, response_body::full};
@@ -69,3 +70,24 @@ async fn sending_valid_message() {
     // Clean up
     fs::remove_dir_all("./receive_message_tests04").unwrap();
 }
+#[tokio::test]
+async fn sending_empty_body() {
+    let config = JaemConfig::create_default();
+    let mut md_config = config.get_message_delivery_config();
+    md_config
+        .set_storage_path("./receive_message_tests05")
+        .unwrap();
+
+    let request = Request::builder().body(full(Vec::new())).unwrap();
+    let response = receive_messages(request, &md_config).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(response.headers()["Content-Type"], "application/json");
+
+    // errors are reported as JSON with a stable error code
+    let body = response.collect().await.unwrap().to_bytes();
+    let body = String::from_utf8(body.to_vec()).unwrap();
+    assert!(body.starts_with(r#"{"code":"invalid_request","message":"#));
+
+    // Clean up
+    fs::remove_dir_all("./receive_message_tests05").unwrap();
+}
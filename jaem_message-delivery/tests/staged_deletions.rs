@@ -0,0 +1,32 @@
+use jaem_message_delivery::message_deletion::{
+    load_staged_deletions, save_staged_deletions, OutstandingDeletion, STAGED_DELETIONS_FILE,
+};
+use std::collections::HashMap;
+use std::path::Path;
+
+#[test]
+fn staged_deletions_survive_a_restart() {
+    let test_dir = Path::new("./staged_deletions_tests01");
+    std::fs::create_dir_all(test_dir).unwrap();
+
+    // nothing was saved yet
+    let (messages, shares) = load_staged_deletions(test_dir).unwrap();
+    assert!(messages.is_empty() && shares.is_empty());
+
+    let mut messages = HashMap::new();
+    messages.insert(vec![1, 2, 3], OutstandingDeletion::new(100, &[1, 2, 3]));
+    let mut shares = HashMap::new();
+    shares.insert(b"link".to_vec(), OutstandingDeletion::new(200, b"link"));
+    save_staged_deletions(test_dir, &messages, &shares).unwrap();
+    // the temporary file was renamed
+    assert_eq!(std::fs::read_dir(test_dir).unwrap().count(), 1);
+
+    let (messages, shares) = load_staged_deletions(test_dir).unwrap();
+    assert_eq!(100, messages[&vec![1, 2, 3]].timestamp);
+    assert_eq!(200, shares[&b"link".to_vec()].timestamp);
+    // the deletions are only restored once
+    assert!(!test_dir.join(STAGED_DELETIONS_FILE).exists());
+
+    // Clean up
+    std::fs::remove_dir_all(test_dir).unwrap();
+}
@@ -0,0 +1,123 @@
+use std::{
+    collections::HashMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::bail;
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use hyper::{HeaderMap, Method};
+use jaem_common::{error::ApiError, metrics::AuthFailure};
+use sha2::{Digest, Sha256};
+
+use crate::{keys::decode_base64, metrics::METRICS};
+
+/// Header containing the UNIX timestamp an authenticated request was signed at.
+pub const TIMESTAMP_HEADER: &str = "X-Jaem-Timestamp";
+
+/// Header containing the base64 encoded signature of an authenticated request.
+pub const SIGNATURE_HEADER: &str = "X-Jaem-Signature";
+
+/// Authenticated requests older than this many seconds are rejected, unless a different
+/// `auth_clock_skew` is configured. Within this window every signature is only accepted once.
+pub const MAX_REQUEST_AGE: u64 = 30;
+
+/// Decodes a base64 encoded ED25519 public key as it is stored in `PubKey::signature_key`.
+pub fn decode_verifying_key(key: &str) -> Option<VerifyingKey> {
+    let key = decode_base64(key)?;
+    VerifyingKey::from_bytes(&key.try_into().ok()?).ok()
+}
+
+/// Checks a base64 encoded ED25519 signature of a message.
+pub fn verify_signature(key: &VerifyingKey, message: &[u8], signature: &str) -> bool {
+    decode_signature(signature).is_some_and(|signature| key.verify(message, &signature).is_ok())
+}
+
+fn decode_signature(signature: &str) -> Option<Signature> {
+    let signature = STANDARD.decode(signature).ok()?;
+    Signature::from_slice(&signature).ok()
+}
+
+/// The message signed to authenticate a request:
+/// `jaem-auth:{method}:{path}:{timestamp}:{body_hash}`, where path is the percent encoded path of
+/// the request and body_hash the hex encoded SHA-256 hash of the body.
+pub fn request_message(method: &Method, path: &str, timestamp: u64, body: &[u8]) -> Vec<u8> {
+    let body_hash: String = Sha256::digest(body)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("jaem-auth:{}:{}:{}:{}", method, path, timestamp, body_hash).into_bytes()
+}
+
+/// The signatures of the requests that were accepted within the last `max_age` seconds, so that a
+/// captured request cannot be sent again.
+#[derive(Debug, Default)]
+pub struct UsedSignatures {
+    // maps every signature to the timestamp it was made for
+    signatures: HashMap<[u8; Signature::BYTE_SIZE], u64>,
+}
+
+impl UsedSignatures {
+    /// Remembers a signature. Returns false if it was already used.
+    fn insert(&mut self, signature: &Signature, timestamp: u64, now: u64, max_age: u64) -> bool {
+        self.signatures
+            .retain(|_, used_at| now.abs_diff(*used_at) <= max_age);
+        self.signatures
+            .insert(signature.to_bytes(), timestamp)
+            .is_none()
+    }
+}
+
+/// Verifies that a request with the given body was signed by the owner of the given ED25519 key
+/// at most `max_age` seconds ago (or ahead), and that its signature was not used before. Failures
+/// are counted by their reason.
+pub fn verify_request(
+    signature_key: &str,
+    method: &Method,
+    path: &str,
+    headers: &HeaderMap,
+    body: &[u8],
+    max_age: u64,
+    used: &mut UsedSignatures,
+) -> Result<(), anyhow::Error> {
+    let Some(key) = decode_verifying_key(signature_key) else {
+        METRICS.http.auth_failure(AuthFailure::BadKey);
+        bail!(ApiError::InvalidRequest(
+            "Only ED25519 keys can authenticate requests".to_string()
+        ));
+    };
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+    let (Some(timestamp), Some(signature)) = (
+        header(TIMESTAMP_HEADER).and_then(|timestamp| timestamp.parse::<u64>().ok()),
+        header(SIGNATURE_HEADER),
+    ) else {
+        METRICS.http.auth_failure(AuthFailure::BadSignature);
+        bail!(ApiError::Forbidden(format!(
+            "Request has to be signed with the {} and {} headers",
+            TIMESTAMP_HEADER, SIGNATURE_HEADER
+        )));
+    };
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    if now.abs_diff(timestamp) > max_age {
+        METRICS.http.auth_failure(AuthFailure::Expired);
+        bail!(ApiError::Forbidden("Request signature expired".to_string()));
+    }
+    let message = request_message(method, path, timestamp, body);
+    let Some(signature) =
+        decode_signature(signature).filter(|signature| key.verify(&message, signature).is_ok())
+    else {
+        METRICS.http.auth_failure(AuthFailure::BadSignature);
+        bail!(ApiError::Forbidden("Invalid request signature".to_string()));
+    };
+    if !used.insert(&signature, timestamp, now, max_age) {
+        METRICS.http.auth_failure(AuthFailure::Replayed);
+        bail!(ApiError::Forbidden(
+            "Request signature was already used".to_string()
+        ));
+    }
+    Ok(())
+}
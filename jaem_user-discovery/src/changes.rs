@@ -0,0 +1,146 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::user_data::Visibility;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Create,
+    /// The username, description, picture or visibility changed.
+    Update,
+    KeyAdd,
+    /// A key was revoked.
+    KeyRemove,
+    Delete,
+}
+
+/// A change to a user as it is returned to clients.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Change {
+    /// Position of the change in the log, starting at 1.
+    pub sequence: u64,
+    pub uid: String,
+    pub kind: ChangeKind,
+    /// Version of the user after the change.
+    pub version: u64,
+    pub timestamp: u64,
+}
+
+/// How long changes are kept in the log. Clients that did not fetch the feed for longer have to
+/// fetch their contacts again.
+pub const CHANGE_RETENTION_SECS: u64 = 30 * 24 * 60 * 60;
+
+/// A change together with the visibility the user had before and after it, which decides whether
+/// the change shows up in the unfiltered feed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ChangeEvent {
+    #[serde(flatten)]
+    change: Change,
+    visibility: Visibility,
+    /// Missing for creations and for changes logged before it was recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    previous_visibility: Option<Visibility>,
+}
+
+impl ChangeEvent {
+    /// Whether the change is announced to everybody: the user was listed before or after it, so
+    /// that users who hide themselves are still announced once.
+    fn is_listed(&self) -> bool {
+        self.visibility.is_listed()
+            || self
+                .previous_visibility
+                .is_some_and(|visibility| visibility.is_listed())
+    }
+}
+
+/// A page of the change feed. `cursor` is passed as `since` to get the following changes. If
+/// `resync` is set, changes after `since` were already discarded and cached contacts have to be
+/// fetched again.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChangePage {
+    pub changes: Vec<Change>,
+    pub cursor: u64,
+    pub has_more: bool,
+    pub resync: bool,
+}
+
+/// Log of the changes to users of the last `CHANGE_RETENTION_SECS`. Older changes are discarded,
+/// but sequence numbers keep counting.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ChangeLog {
+    /// Number of changes discarded from the front of the log.
+    #[serde(default)]
+    discarded: u64,
+    events: Vec<ChangeEvent>,
+}
+
+impl ChangeLog {
+    pub fn append(
+        &mut self,
+        uid: &str,
+        kind: ChangeKind,
+        version: u64,
+        visibility: Visibility,
+        previous_visibility: Option<Visibility>,
+        timestamp: u64,
+    ) {
+        self.discard_before(timestamp.saturating_sub(CHANGE_RETENTION_SECS));
+        let sequence = self.latest() + 1;
+        self.events.push(ChangeEvent {
+            change: Change {
+                sequence,
+                uid: uid.to_string(),
+                kind,
+                version,
+                timestamp,
+            },
+            visibility,
+            previous_visibility,
+        });
+    }
+
+    /// Discards the changes made before the given time.
+    pub fn discard_before(&mut self, timestamp: u64) {
+        let count = self
+            .events
+            .partition_point(|event| event.change.timestamp < timestamp);
+        if count > 0 {
+            self.events.drain(..count);
+            self.discarded += count as u64;
+        }
+    }
+
+    /// Up to `limit` changes after the given sequence number. Without `uids` only changes to users
+    /// who were listed at the time are returned, otherwise all changes to the given users.
+    pub fn since(&self, since: u64, limit: usize, uids: Option<&HashSet<String>>) -> ChangePage {
+        let start = (since.saturating_sub(self.discarded) as usize).min(self.events.len());
+        let mut changes = Vec::new();
+        let mut cursor = since.max(self.discarded).min(self.latest());
+        for event in &self.events[start..] {
+            if changes.len() == limit {
+                break;
+            }
+            cursor = event.change.sequence;
+            let included = match uids {
+                Some(uids) => uids.contains(&event.change.uid),
+                None => event.is_listed(),
+            };
+            if included {
+                changes.push(event.change.clone());
+            }
+        }
+        ChangePage {
+            changes,
+            cursor,
+            has_more: cursor < self.latest(),
+            resync: since < self.discarded,
+        }
+    }
+
+    /// Sequence number of the latest change.
+    pub fn latest(&self) -> u64 {
+        self.discarded + self.events.len() as u64
+    }
+}
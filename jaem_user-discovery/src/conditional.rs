@@ -0,0 +1,73 @@
+use hyper::{
+    header::{IF_MATCH, IF_NONE_MATCH},
+    HeaderMap,
+};
+use jaem_common::error::ApiError;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use sha2::{Digest, Sha256};
+
+/// Strong ETag of a response body, the quoted and hex encoded SHA-256 hash of the body.
+pub fn etag(body: &[u8]) -> String {
+    let hash: String = Sha256::digest(body)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("\"{}\"", hash)
+}
+
+/// ETag of a user, built from the uid, the version of the user and the keys it contains, so that
+/// it does not require building the user data. Keys expire without a new version, so the tag
+/// counts the expired keys that are left out, e.g. `"123-4-1"`, or ends in `all` if expired keys
+/// are included, e.g. `"123-4-all"`. Characters that are not allowed in ETags are percent encoded.
+pub fn user_etag(uid: &str, version: u64, include_expired: bool, expired_keys: usize) -> String {
+    let keys = match include_expired {
+        true => "all".to_string(),
+        false => expired_keys.to_string(),
+    };
+    format!(
+        "\"{}-{}-{}\"",
+        utf8_percent_encode(uid, NON_ALPHANUMERIC),
+        version,
+        keys
+    )
+}
+
+/// The entity tags listed in a header like `If-None-Match: "a", W/"b"`.
+fn entity_tags(headers: &HeaderMap, name: impl hyper::header::AsHeaderName) -> Vec<&str> {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty())
+        .collect()
+}
+
+/// Whether the client already has the representation with the given ETag, in which case it is
+/// answered with `304 Not Modified`. Uses the weak comparison, as required for `If-None-Match`.
+pub fn is_not_modified(headers: &HeaderMap, etag: &str) -> bool {
+    entity_tags(headers, IF_NONE_MATCH)
+        .iter()
+        .any(|tag| *tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+/// Fails if the request has an `If-Match` header that does not match one of the current ETags of
+/// the resource, which has none if it does not exist. Weak tags never match, because `If-Match`
+/// uses the strong comparison.
+pub fn check_if_match(headers: &HeaderMap, current: &[String]) -> Result<(), ApiError> {
+    let tags = entity_tags(headers, IF_MATCH);
+    if tags.is_empty() {
+        return Ok(());
+    }
+    let matches = !current.is_empty()
+        && tags
+            .iter()
+            .any(|tag| *tag == "*" || current.iter().any(|current| tag == current));
+    if !matches {
+        return Err(ApiError::PreconditionFailed(
+            "The resource was modified by another request".to_string(),
+        ));
+    }
+    Ok(())
+}
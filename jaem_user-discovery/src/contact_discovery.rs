@@ -0,0 +1,198 @@
+use std::{collections::BTreeMap, net::IpAddr, ops::Bound, time::Duration};
+
+use anyhow::bail;
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use jaem_common::error::ApiError;
+use jaem_config::ContactDiscoveryLimits;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    keys::decode_base64, rate_limit::RateLimiter, user_data::ReturnUserData, username::fold_case,
+};
+
+/// Hashes of identifiers are SHA-256 hashes, which clients may truncate.
+pub const HASH_LENGTH: usize = 32;
+
+/// The identifiers users can be discovered by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdentifierKind {
+    Username,
+    Uid,
+}
+
+/// The body of a contact discovery request, containing base64 encoded, possibly truncated hashes.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DiscoveryRequest {
+    pub hashes: Vec<String>,
+}
+
+/// The users found for one of the hashes of a request.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ContactMatch {
+    pub hash: String,
+    pub users: Vec<ReturnUserData>,
+}
+
+/// What clients need to know to compute the hashes of their contacts.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DiscoveryParameters {
+    pub salt: String,
+    pub min_hash_length: usize,
+    pub max_batch_size: usize,
+}
+
+/// Index of the salted hashes of all usernames and uids. Only the salt is persisted, the index is
+/// rebuilt when the users are loaded.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ContactIndex {
+    #[serde(default = "random_salt")]
+    salt: String,
+    #[serde(skip)]
+    hashes: BTreeMap<[u8; HASH_LENGTH], Vec<(String, IdentifierKind)>>,
+    #[serde(skip)]
+    limits: ContactDiscoveryLimits,
+    #[serde(skip, default = "default_limiter")]
+    limiter: RateLimiter,
+    // set if the salt was generated instead of loaded and still has to be saved
+    #[serde(skip)]
+    new_salt: bool,
+}
+
+impl Default for ContactIndex {
+    fn default() -> ContactIndex {
+        ContactIndex {
+            salt: random_salt(),
+            hashes: BTreeMap::new(),
+            limits: ContactDiscoveryLimits::default(),
+            limiter: default_limiter(),
+            new_salt: true,
+        }
+    }
+}
+
+fn random_salt() -> String {
+    let mut salt = [0; 16];
+    rand::thread_rng().fill_bytes(&mut salt);
+    STANDARD.encode(salt)
+}
+
+fn default_limiter() -> RateLimiter {
+    limiter(&ContactDiscoveryLimits::default())
+}
+
+fn limiter(limits: &ContactDiscoveryLimits) -> RateLimiter {
+    RateLimiter::new(limits.hashes_per_hour, Duration::from_secs(60 * 60))
+}
+
+/// The salted hash of an identifier: `SHA-256(salt || identifier)`, where salt are the decoded
+/// bytes of the salt and usernames are normalized with `fold_case` first.
+pub fn identifier_hash(salt: &[u8], identifier: &str, kind: IdentifierKind) -> [u8; HASH_LENGTH] {
+    let identifier = match kind {
+        IdentifierKind::Username => fold_case(identifier),
+        IdentifierKind::Uid => identifier.to_string(),
+    };
+    let mut hasher = Sha256::new();
+    hasher.update(salt);
+    hasher.update(identifier.as_bytes());
+    hasher.finalize().into()
+}
+
+impl ContactIndex {
+    pub fn parameters(&self) -> DiscoveryParameters {
+        DiscoveryParameters {
+            salt: self.salt.clone(),
+            min_hash_length: self.limits.min_hash_length,
+            max_batch_size: self.limits.max_batch_size,
+        }
+    }
+
+    /// Whether the salt was generated when the storage was loaded, because it did not have one.
+    pub fn has_new_salt(&self) -> bool {
+        self.new_salt
+    }
+
+    /// Replaces the limits and resets the rate limits of all clients if they changed.
+    pub fn set_limits(&mut self, limits: ContactDiscoveryLimits) {
+        if self.limits == limits {
+            return;
+        }
+        self.limiter = limiter(&limits);
+        self.limits = limits;
+    }
+
+    pub fn insert(&mut self, uid: &str, username: &str) {
+        for (identifier, kind) in [
+            (username, IdentifierKind::Username),
+            (uid, IdentifierKind::Uid),
+        ] {
+            let hash = self.hash(identifier, kind);
+            self.hashes
+                .entry(hash)
+                .or_default()
+                .push((uid.to_string(), kind));
+        }
+    }
+
+    pub fn remove(&mut self, uid: &str, username: &str) {
+        for (identifier, kind) in [
+            (username, IdentifierKind::Username),
+            (uid, IdentifierKind::Uid),
+        ] {
+            let hash = self.hash(identifier, kind);
+            if let Some(entries) = self.hashes.get_mut(&hash) {
+                entries.retain(|entry| entry != &(uid.to_string(), kind));
+                if entries.is_empty() {
+                    self.hashes.remove(&hash);
+                }
+            }
+        }
+    }
+
+    fn hash(&self, identifier: &str, kind: IdentifierKind) -> [u8; HASH_LENGTH] {
+        let salt = STANDARD.decode(&self.salt).unwrap_or_default();
+        identifier_hash(&salt, identifier, kind)
+    }
+
+    /// Checks the hashes of a request against the limits and decodes them.
+    pub fn decode_request(
+        &mut self,
+        request: &DiscoveryRequest,
+        client: IpAddr,
+    ) -> Result<Vec<Vec<u8>>, anyhow::Error> {
+        if request.hashes.is_empty() || request.hashes.len() > self.limits.max_batch_size {
+            bail!(ApiError::InvalidRequest(format!(
+                "Between 1 and {} hashes can be looked up at once",
+                self.limits.max_batch_size
+            )));
+        }
+        let mut prefixes = Vec::new();
+        for hash in &request.hashes {
+            match decode_base64(hash) {
+                Some(prefix)
+                    if (self.limits.min_hash_length..=HASH_LENGTH).contains(&prefix.len()) =>
+                {
+                    prefixes.push(prefix)
+                }
+                _ => bail!(ApiError::InvalidRequest(format!(
+                    "Hashes have to be base64 encoded and between {} and {} bytes long",
+                    self.limits.min_hash_length, HASH_LENGTH
+                ))),
+            }
+        }
+        self.limiter.check(client, prefixes.len() as u32)?;
+        Ok(prefixes)
+    }
+
+    /// All identifiers whose hash starts with the given prefix.
+    pub fn lookup(&self, prefix: &[u8]) -> impl Iterator<Item = &(String, IdentifierKind)> {
+        let mut start = [0; HASH_LENGTH];
+        let mut end = [u8::MAX; HASH_LENGTH];
+        start[..prefix.len()].copy_from_slice(prefix);
+        end[..prefix.len()].copy_from_slice(prefix);
+        self.hashes
+            .range((Bound::Included(start), Bound::Included(end)))
+            .flat_map(|(_, entries)| entries)
+    }
+}
@@ -1,28 +1,210 @@
 use std::{
+    collections::BTreeMap,
     fmt::Debug,
+    fs,
+    net::IpAddr,
     ops::{Deref, DerefMut},
     path::Path,
+    pin::pin,
     sync::Arc,
-    usize,
+    time::Duration,
 };
 
-use http_body_util::{combinators::BoxBody, BodyExt, Empty, Full};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use http_body_util::{combinators::BoxBody, BodyExt, Full};
 use hyper::{
-    body::{Body, Bytes},
-    Method, Request, Response, StatusCode,
+    body::{Body, Buf, Bytes},
+    header::ETAG,
+    HeaderMap, Method, Request, Response, StatusCode,
 };
 
+use jaem_common::{
+    error::ApiError,
+    health::{check_writable, healthz, Readiness, ReadinessCache},
+    logging::RequestSpan,
+    tls::check_admin_access,
+};
+use percent_encoding::percent_decode_str;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use tokio::sync::Mutex;
+use tracing::Instrument;
+
+use crate::{
+    conditional::{check_if_match, etag, is_not_modified, user_etag},
+    contact_discovery::DiscoveryRequest,
+    metrics::METRICS,
+    pagination::{PageRequest, MAX_LIMIT},
+    prekeys::PrekeyUpload,
+    user_data::{
+        PubKey, PubKeyAlgo, RevocationReason, UserData, UserPage, UserStorage, Visibility,
+    },
+};
+
+/// Header in which reverse proxies pass on the address of the client they received a request from.
+pub const FORWARDED_FOR_HEADER: &str = "X-Forwarded-For";
+
+/// The address of the client that sent a request. The server adds it to the extensions of every
+/// request, so that clients can be rate limited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RemoteAddr(pub IpAddr);
+
+impl RemoteAddr {
+    /// The client of a request received from `peer`. Requests from trusted proxies are attributed
+    /// to the last address in `X-Forwarded-For` that is not a trusted proxy itself, because
+    /// proxies append the address they received the request from and anything before it could
+    /// have been sent by the client.
+    pub fn resolve(peer: IpAddr, headers: &HeaderMap, trusted_proxies: &[IpAddr]) -> RemoteAddr {
+        if !trusted_proxies.contains(&peer) {
+            return RemoteAddr(peer);
+        }
+        let forwarded: Vec<&str> = headers
+            .get_all(FORWARDED_FOR_HEADER)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .collect();
+        let client = forwarded
+            .iter()
+            .rev()
+            .map(|addr| addr.trim().parse::<IpAddr>())
+            .find(|addr| !matches!(addr, Ok(addr) if trusted_proxies.contains(addr)));
+        match client {
+            Some(Ok(client)) => RemoteAddr(client),
+            // without a valid header the request is attributed to the proxy
+            _ => RemoteAddr(peer),
+        }
+    }
+}
 
-use crate::user_data::{PubKey, PubKeyAlgo, UserData, UserStorage};
+/// The client address the server added to a request. Requests without one cannot be rate limited
+/// and are rejected.
+fn remote_addr<B>(req: &Request<B>) -> Result<IpAddr, ApiError> {
+    match req.extensions().get::<RemoteAddr>() {
+        Some(addr) => Ok(addr.0),
+        None => {
+            tracing::error!("Rejecting a request without a client address");
+            Err(ApiError::Internal(
+                "The client address is unknown".to_string(),
+            ))
+        }
+    }
+}
+
+/// The body of a batch lookup, e.g. `{"uids": ["1", "2"]}`.
+#[derive(Debug, Deserialize)]
+struct BatchLookup {
+    uids: Vec<String>,
+}
 
-// Processes an incoming Request
+/// How long `/readyz` waits for the user store.
+const READY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The first path segments of the routes, as they are reported in the metrics.
+const ROUTES: [&str; 15] = [
+    "users",
+    "search_users",
+    "user_by_uid",
+    "users_by_uid",
+    "changes",
+    "user_by_username",
+    "log",
+    "add_pub_key",
+    "create_user",
+    "profile",
+    "prekeys",
+    "prekey_bundle",
+    "contact_discovery",
+    "user",
+    "metrics",
+];
+
+// Processes an incoming Request, logging it and recording it in the metrics
 pub async fn handle_connection<B: Body + Debug>(
     req: Request<B>,
     users: Arc<Mutex<UserStorage>>,
     file_path: &str,
 ) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error>
+where
+    <B as Body>::Error: Debug,
+{
+    let resource = req.uri().path().split('/').nth(1).unwrap_or_default();
+    // probes of orchestrators are frequent, so they are neither rate limited, logged nor counted
+    match (req.method(), resource) {
+        (&Method::GET, "healthz") => return Ok(healthz()),
+        (&Method::GET, "readyz") => {
+            // the server shares the outcome between requests, see `ReadinessCache`
+            let check = readiness(&users, file_path);
+            let readiness = match req.extensions().get::<Arc<ReadinessCache>>() {
+                Some(cache) => cache.get_or_check(check).await,
+                None => check.await,
+            };
+            return Ok(readiness.into_response());
+        }
+        _ => {}
+    }
+
+    let method = req.method().clone();
+    let route = ROUTES
+        .into_iter()
+        .find(|route| *route == resource)
+        .unwrap_or("unknown");
+    let request_span = RequestSpan::new(&req, route);
+    let mut response = route_request(req, users, file_path)
+        .instrument(request_span.span().clone())
+        .await?;
+    let elapsed = request_span.finish(&mut response);
+    METRICS
+        .http
+        .observe_request(&method, route, response.status(), elapsed);
+    Ok(response)
+}
+
+/// Checks whether the user discovery can serve requests: the user store has to be loaded and must
+/// not stay locked for longer than `READY_TIMEOUT`, and the directories of the users file and the
+/// profile pictures have to be writable.
+pub async fn readiness(users: &Mutex<UserStorage>, file_path: &str) -> Readiness {
+    let mut readiness = Readiness::default();
+    let picture_directory = match tokio::time::timeout(READY_TIMEOUT, users.lock()).await {
+        Ok(users) => {
+            readiness.check("user_store", Ok(()));
+            Some(users.picture_directory().to_path_buf())
+        }
+        Err(_) => {
+            readiness.check(
+                "user_store",
+                Err(format!(
+                    "the users stayed locked for more than {} seconds",
+                    READY_TIMEOUT.as_secs()
+                )),
+            );
+            None
+        }
+    };
+
+    let users_directory = match Path::new(file_path).parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    readiness.check("storage", check_writable(users_directory));
+    // the picture directory is created with the first picture
+    if let Some(directory) = picture_directory {
+        let writable = fs::create_dir_all(&directory)
+            .map_err(|err| {
+                tracing::warn!(directory = %directory.display(), "Could not be created: {}", err);
+                "not writable".to_string()
+            })
+            .and_then(|_| check_writable(&directory));
+        readiness.check("storage", writable);
+    }
+    readiness
+}
+
+async fn route_request<B: Body + Debug>(
+    req: Request<B>,
+    users: Arc<Mutex<UserStorage>>,
+    file_path: &str,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error>
 where
     <B as Body>::Error: Debug,
 {
@@ -36,7 +218,23 @@ where
 
     // Match first parameter (path_resource) to the corresponding implementation
     match (req.method(), path_resource) {
+        /*
+         * Request: users?limit={limit}&cursor={cursor}
+         * or the legacy form users + Optional(/{page}/{page_size})
+         */
         (&Method::GET, "users") => {
+            if !users.lock().await.listing_enabled() {
+                return Ok(error_response(ApiError::Forbidden(
+                    "Listing users is disabled".to_string(),
+                )));
+            }
+            match PageRequest::from_query(req.uri().query()) {
+                Ok(Some(page)) => {
+                    return get_users_page(&page, req.headers(), users.lock().await.deref())
+                }
+                Ok(None) => {}
+                Err(err) => return Ok(error_response(err)),
+            }
             let page = match path_it.next() {
                 Some(page) => page.to_str().unwrap().parse::<usize>().unwrap_or(0),
                 None => 0,
@@ -46,10 +244,11 @@ where
                 None => 20,
             };
 
-            return get_users(page, page_size, users.lock().await.deref());
+            return get_users(page, page_size, req.headers(), users.lock().await.deref());
         }
         /*
-         * Request: search_users/{username}
+         * Request: search_users/{username}?limit={limit}&cursor={cursor}
+         * or the legacy form search_users/{username} + Optional(/{page}/{page_size})
          * Return Users that match the pattern from {username}
          */
         (&Method::GET, "search_users") => {
@@ -57,6 +256,18 @@ where
                 Some(name) => name.to_str().unwrap(),
                 None => return Ok(bad_request("Name cannot be empty")),
             };
+            match PageRequest::from_query(req.uri().query()) {
+                Ok(Some(page)) => {
+                    return search_users_page(
+                        name.to_string(),
+                        &page,
+                        req.headers(),
+                        users.lock().await.deref(),
+                    )
+                }
+                Ok(None) => {}
+                Err(err) => return Ok(error_response(err)),
+            }
             let page = match path_it.next() {
                 Some(page) => page.to_str().unwrap().parse::<usize>().unwrap_or(0),
                 None => 0,
@@ -83,7 +294,98 @@ where
                 Some(key) => key.to_str().unwrap(),
                 None => return Ok(bad_request("Key cannot be empty")),
             };
-            return get_user_by_uid(key.to_string(), users.lock().await.deref());
+            let include_expired = query_flag(req.uri().query(), "include_expired");
+            return get_user_by_uid(
+                key.to_string(),
+                include_expired,
+                req.headers(),
+                users.lock().await.deref(),
+            );
+        }
+
+        /*
+         * Request: users_by_uid?include_expired={true|false} @Body -> uids
+         * Return all users with the specified uids and the uids that do not exist
+         */
+        (&Method::POST, "users_by_uid") => {
+            let include_expired = query_flag(req.uri().query(), "include_expired");
+            let body_bytes = req.collect().await.unwrap().to_bytes();
+            let request = match serde_json::from_slice::<BatchLookup>(&body_bytes) {
+                Ok(request) => request,
+                Err(_) => return Ok(bad_request("Invalid Request Body")),
+            };
+            match users
+                .lock()
+                .await
+                .get_entries_by_uids(&request.uids, include_expired)
+            {
+                Ok(batch) => Ok(json_response(&batch)),
+                Err(err) => Ok(error_response(err)),
+            }
+        }
+
+        /*
+         * Request: changes?since={sequence}&limit={limit}&uids={uid},{uid}
+         * Return the changes to listed users or to the given users after the given sequence
+         * number
+         */
+        (&Method::GET, "changes") => {
+            let query = match parse_changes_query(req.uri().query()) {
+                Ok(query) => query,
+                Err(err) => return Ok(error_response(err)),
+            };
+            let users = users.lock().await;
+            // following known users is possible without listing, like looking them up by uid
+            if query.uids.is_none() && !users.listing_enabled() {
+                return Ok(error_response(ApiError::Forbidden(
+                    "Listing users is disabled".to_string(),
+                )));
+            }
+            match users.changes_since(query.since, query.limit, query.uids.as_deref()) {
+                Ok(page) => Ok(json_response(&page)),
+                Err(err) => Ok(error_response(err)),
+            }
+        }
+
+        /*
+         * Request: user_by_username/{username}
+         * Return User with specified username. Only available if usernames are unique.
+         */
+        (&Method::GET, "user_by_username") => {
+            let username = match path_it.next() {
+                Some(username) => username.to_str().unwrap(),
+                None => return Ok(bad_request("Username cannot be empty")),
+            };
+            get_user_by_username(username.to_string(), users.lock().await.deref())
+        }
+
+        /*
+         * Request: log/tree_head, log/key, log/inclusion/{uid} or log/consistency/{first}/{second}
+         * Key transparency log of all changes to public keys
+         */
+        (&Method::GET, "log") => {
+            let users = users.lock().await;
+            match path_it.next().and_then(|resource| resource.to_str()) {
+                Some("tree_head") => Ok(json_response(&users.key_log().tree_head())),
+                Some("key") => get_log_key(users.deref()),
+                Some("inclusion") => {
+                    let uid = match path_it.next() {
+                        Some(uid) => uid.to_str().unwrap(),
+                        None => return Ok(bad_request("UID cannot be empty")),
+                    };
+                    get_inclusion_proof(uid.to_string(), users.deref())
+                }
+                Some("consistency") => {
+                    let mut sizes = path_it.map(|size| size.to_str().unwrap().parse::<u64>());
+                    match (sizes.next(), sizes.next()) {
+                        (Some(Ok(first)), Some(Ok(second))) => {
+                            get_consistency_proof(first, second, users.deref())
+                        }
+                        _ => Ok(bad_request("Tree sizes have to be numbers")),
+                    }
+                }
+                _ => Ok(not_found()),
+            }
         }
 
         /*
@@ -96,13 +398,7 @@ where
                 Ok(json) => {
                     return add_pub_keys(json, users.lock().await.deref_mut(), file_path);
                 }
-                Err(_) => {
-                    let code = "0";
-                    let message = "Invalid Request Body";
-                    let response_body =
-                        format!("{{\"code\": {}, \"message\": \"{}\"}}", code, message);
-                    return Ok(bad_request(&response_body));
-                }
+                Err(_) => Ok(bad_request("Invalid Request Body")),
             }
         }
 
@@ -116,13 +412,7 @@ where
                 Ok(json) => {
                     return add_new_entry(json, users.lock().await.deref_mut(), file_path);
                 }
-                Err(_) => {
-                    let code = "0";
-                    let message = "Invalid Request Body";
-                    let response_body =
-                        format!("{{\"code\": {}, \"message\": \"{}\"}}", code, message);
-                    return Ok(bad_request(&response_body));
-                }
+                Err(_) => Ok(bad_request("Invalid Request Body")),
             }
         }
 
@@ -131,73 +421,286 @@ where
          * Change users profile picture
          */
         (&Method::PATCH, "profile") => {
+            let headers = req.headers().clone();
             let body_bytes = req.collect().await.unwrap().to_bytes();
             match serde_json::from_slice::<Value>(&body_bytes) {
-                Ok(json) => return change_profile(json, users.lock().await.deref_mut(), file_path),
-                Err(_) => {
-                    let code = "0";
-                    let message = "Invalid Request Body";
-                    let response_body =
-                        format!("{{\"code\": {}, \"message\": \"{}\"}}", code, message);
-                    return Ok(bad_request(&response_body));
+                Ok(json) => {
+                    return change_profile(
+                        json,
+                        &headers,
+                        users.lock().await.deref_mut(),
+                        file_path,
+                    )
                 }
+                Err(_) => Ok(bad_request("Invalid Request Body")),
             }
         }
 
         /*
-         * Request: user/{uid} + Optional(/{signature_key})
-         * Delete user from UDS
+         * Request: prekeys/{uid}/{signature_key} @Body -> Optional(signed_prekey) + one_time_prekeys
+         * Upload prekeys for a device. Has to be signed with the signature key of the device.
          */
-        (&Method::DELETE, "user") => {
+        (&Method::PUT, "prekeys") => {
+            let (uid, signature_key) = match device_path(&mut path_it) {
+                Ok(device) => device,
+                Err(err) => return Ok(error_response(err)),
+            };
+            let (parts, body) = req.into_parts();
+            let body_bytes = body.collect().await.unwrap().to_bytes();
+            let mut users = users.lock().await;
+            if let Err(err) = users.authenticate(
+                &signature_key,
+                &parts.method,
+                parts.uri.path(),
+                &parts.headers,
+                &body_bytes,
+            ) {
+                return Ok(error_response(err));
+            }
+            let upload = match serde_json::from_slice::<PrekeyUpload>(&body_bytes) {
+                Ok(upload) => upload,
+                Err(_) => return Ok(bad_request("Invalid Request Body")),
+            };
+            match users.upload_prekeys(uid, signature_key, upload, file_path) {
+                Ok(count) => Ok(json_response(&count)),
+                Err(err) => Ok(error_response(err)),
+            }
+        }
+
+        /*
+         * Request: prekeys/{uid}/{signature_key}
+         * Return the number of prekeys left for a device. Has to be signed with the signature key
+         * of the device.
+         */
+        (&Method::GET, "prekeys") => {
+            let (uid, signature_key) = match device_path(&mut path_it) {
+                Ok(device) => device,
+                Err(err) => return Ok(error_response(err)),
+            };
+            let (parts, body) = req.into_parts();
+            let body_bytes = body.collect().await.unwrap().to_bytes();
+            let mut users = users.lock().await;
+            if let Err(err) = users.authenticate(
+                &signature_key,
+                &parts.method,
+                parts.uri.path(),
+                &parts.headers,
+                &body_bytes,
+            ) {
+                return Ok(error_response(err));
+            }
+            match users.prekey_count(uid, signature_key) {
+                Ok(count) => Ok(json_response(&count)),
+                Err(err) => Ok(error_response(err)),
+            }
+        }
+
+        /*
+         * Request: prekey_bundle/{uid}/{signature_key}
+         * Return a prekey bundle for the device with the given signature key. Every bundle
+         * consumes one one-time prekey, which is why this is not a GET request.
+         */
+        (&Method::POST, "prekey_bundle") => {
+            let (uid, signature_key) = match device_path(&mut path_it) {
+                Ok(device) => device,
+                Err(err) => return Ok(error_response(err)),
+            };
+            let client = match remote_addr(&req) {
+                Ok(client) => client,
+                Err(err) => return Ok(err.into_response()),
+            };
+            let mut users = users.lock().await;
+            match users.take_prekey_bundle(uid, signature_key, client, file_path) {
+                Ok(bundle) => Ok(json_response(&bundle)),
+                Err(err) => Ok(error_response(err)),
+            }
+        }
+
+        /*
+         * Request: contact_discovery
+         * Return the salt and limits for hashing identifiers
+         */
+        (&Method::GET, "contact_discovery") => {
+            Ok(json_response(&users.lock().await.discovery_parameters()))
+        }
+
+        /*
+         * Request: contact_discovery @Body -> hashes
+         * Return the users whose salted username or uid hash starts with one of the hashes
+         */
+        (&Method::POST, "contact_discovery") => {
+            let client = match remote_addr(&req) {
+                Ok(client) => client,
+                Err(err) => return Ok(err.into_response()),
+            };
+            let body_bytes = req.collect().await.unwrap().to_bytes();
+            let request = match serde_json::from_slice::<DiscoveryRequest>(&body_bytes) {
+                Ok(request) => request,
+                Err(_) => return Ok(bad_request("Invalid Request Body")),
+            };
+            let mut users = users.lock().await;
+            match users.discover_contacts(&request, client) {
+                Ok(matches) => Ok(json_response(&matches)),
+                Err(err) => Ok(error_response(err)),
+            }
+        }
+
+        /*
+         * Request: user/{uid}/picture + Optional(/{size})
+         * Return the profile picture of the user with the specified uid or its thumbnail
+         */
+        (&Method::GET, "user") => {
             let uid = match path_it.next() {
                 Some(uid) => uid.to_str().unwrap(),
                 None => return Ok(bad_request("UID cannot be empty")),
             };
-            let signature_key = match path_it.next() {
-                Some(public_key) => Some(public_key.to_str().unwrap()),
-                None => None,
+            match path_it.next().and_then(|resource| resource.to_str()) {
+                Some("picture") => {
+                    let size = match path_it.next() {
+                        Some(size) => match size.to_str().unwrap().parse::<u32>() {
+                            Ok(size) => Some(size),
+                            Err(_) => return Ok(not_found()),
+                        },
+                        None => None,
+                    };
+                    get_profile_picture(
+                        uid.to_string(),
+                        size,
+                        req.headers(),
+                        users.lock().await.deref(),
+                    )
+                }
+                _ => Ok(not_found()),
+            }
+        }
+
+        /*
+         * Request: user/{uid}/picture @Body -> image (PNG, JPEG or WebP)
+         * Replace the profile picture of the user with the specified uid
+         */
+        (&Method::PUT, "user") => {
+            let uid = match path_it.next() {
+                Some(uid) => uid.to_str().unwrap().to_string(),
+                None => return Ok(bad_request("UID cannot be empty")),
+            };
+            if path_it.next().and_then(|resource| resource.to_str()) != Some("picture") {
+                return Ok(not_found());
+            }
+
+            let max_size = users.lock().await.max_picture_size();
+            let headers = req.headers().clone();
+            let body_bytes = match collect_limited(req.into_body(), max_size).await {
+                Ok(body) => body,
+                Err(err) => return Ok(error_response(err)),
+            };
+            set_profile_picture(
+                uid,
+                &body_bytes,
+                &headers,
+                users.lock().await.deref_mut(),
+                file_path,
+            )
+        }
+
+        /*
+         * Request: user/{uid} + Optional(/{signature_key} @Body -> Optional(reason + signature))
+         * Delete user from UDS or revoke one of its keys
+         */
+        (&Method::DELETE, "user") => {
+            let uid = match path_it.next() {
+                Some(uid) => uid.to_str().unwrap().to_string(),
+                None => return Ok(bad_request("UID cannot be empty")),
             };
+            let signature_key = path_it
+                .next()
+                .map(|public_key| public_key.to_str().unwrap().to_string());
 
-            match signature_key.is_none() {
-                false => {
-                    let public_key = signature_key.unwrap();
+            match signature_key {
+                Some(public_key) => {
+                    let body_bytes = req.collect().await.unwrap().to_bytes();
+                    let reason = if body_bytes.is_empty() {
+                        None
+                    } else {
+                        match serde_json::from_slice::<RevocationReason>(&body_bytes) {
+                            Ok(reason) => Some(reason),
+                            Err(_) => return Ok(bad_request("Invalid Request Body")),
+                        }
+                    };
                     return delete_pub_key_from_user(
-                        uid.to_string(),
-                        public_key.to_string(),
+                        uid,
+                        public_key,
+                        reason,
                         users.lock().await.deref_mut(),
                         file_path,
                     );
                 }
-                true => {
-                    return delete_user(uid.to_string(), users.lock().await.deref_mut(), file_path);
+                None => {
+                    return delete_user(uid, users.lock().await.deref_mut(), file_path);
                 }
             }
         }
-        _ => {
-            let mut not_found = Response::new(empty());
-            *not_found.status_mut() = StatusCode::NOT_FOUND;
-            return Ok(not_found);
+
+        /*
+         * Request: metrics
+         * Return the metrics in the Prometheus text format, only to admins if configured
+         */
+        (&Method::GET, "metrics") => {
+            if let Err(err) = check_admin_access(&req) {
+                return Ok(error_response(err));
+            }
+            METRICS.users.set(users.lock().await.users.len() as i64);
+            Ok(METRICS.http.response())
         }
+        _ => Ok(not_found()),
     }
 }
 
 fn get_users(
     page: usize,
     page_size: usize,
+    headers: &HeaderMap,
     users: &UserStorage,
 ) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
     let results = users.get_users(page, page_size);
-    let json = serde_json::to_string(&results).unwrap();
+    Ok(conditional_json(&results, headers))
+}
 
-    let body: BoxBody<Bytes, hyper::Error> = full(Bytes::from(json));
+fn get_users_page(
+    page: &PageRequest,
+    headers: &HeaderMap,
+    users: &UserStorage,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    match users.get_users_page(page) {
+        Ok(results) => Ok(user_page(results, headers)),
+        Err(err) => Ok(error_response(err)),
+    }
+}
 
-    let response = Response::builder()
-        .status(StatusCode::OK)
-        .header("Content-Type", "application/json")
-        .body(body)
-        .unwrap();
+fn search_users_page(
+    name: String,
+    page: &PageRequest,
+    headers: &HeaderMap,
+    users: &UserStorage,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    if name.is_empty() {
+        return Ok(bad_request("Name cannot be empty"));
+    }
 
-    Ok(response)
+    let timer = METRICS.search_duration.start_timer();
+    let results = users.search_page(name, page);
+    timer.observe_duration();
+    match results {
+        Ok(results) => Ok(user_page(results, headers)),
+        Err(err) => Ok(error_response(err)),
+    }
+}
+
+fn user_page(page: UserPage, headers: &HeaderMap) -> Response<BoxBody<Bytes, hyper::Error>> {
+    let mut response = conditional_json(&page, headers);
+    response
+        .headers_mut()
+        .insert("X-Total-Count", page.total.into());
+    response
 }
 
 fn get_user_by_name_pattern(
@@ -210,14 +713,17 @@ fn get_user_by_name_pattern(
         return Ok(bad_request("Name cannot be empty"));
     }
 
+    let timer = METRICS.search_duration.start_timer();
     let results = users.get_entries_by_pattern(name, page, page_size);
-    let json = serde_json::to_string(&results).unwrap();
+    timer.observe_duration();
+    let json = serde_json::to_string(&results.users).unwrap();
 
     let body: BoxBody<Bytes, hyper::Error> = full(Bytes::from(json));
 
     let response = Response::builder()
         .status(StatusCode::OK)
         .header("Content-Type", "application/json")
+        .header("X-Total-Count", results.total)
         .body(body)
         .unwrap();
 
@@ -226,15 +732,73 @@ fn get_user_by_name_pattern(
 
 fn get_user_by_uid(
     uid: String,
+    include_expired: bool,
+    headers: &HeaderMap,
     users: &UserStorage,
 ) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
     if uid.is_empty() {
         return Ok(bad_request("UID cannot be empty"));
     }
 
-    let result = match users.get_entry_by_uid(uid) {
+    // the ETag is known before the user data with its inlined picture is built
+    let etag = match current_user_etag(users, &uid, include_expired) {
+        Some(etag) => etag,
+        None => return Ok(error_response(user_not_found())),
+    };
+    if is_not_modified(headers, &etag) {
+        return Ok(not_modified(&etag));
+    }
+    let result = match users.get_entry_by_uid(uid, include_expired) {
+        Some(user) => user,
+        None => return Ok(error_response(user_not_found())),
+    };
+    let mut response = json_response(&result);
+    response.headers_mut().insert(ETAG, etag.parse().unwrap());
+    Ok(response)
+}
+
+/// The ETag of `GET /user_by_uid/{uid}` with or without expired keys.
+fn current_user_etag(users: &UserStorage, uid: &str, include_expired: bool) -> Option<String> {
+    users
+        .user_version(uid)
+        .map(|version| user_etag(uid, version, include_expired, users.expired_keys(uid)))
+}
+
+/// The ETags `If-Match` headers of requests that modify the user are compared with, i.e. both
+/// the one with and the one without expired keys. Empty if the user does not exist.
+fn current_user_etags(users: &UserStorage, uid: &str) -> Vec<String> {
+    [false, true]
+        .into_iter()
+        .filter_map(|include_expired| current_user_etag(users, uid, include_expired))
+        .collect()
+}
+
+/// Adds the new ETag of a user to the response of a request that modified the user.
+fn with_user_etag(
+    mut response: Response<BoxBody<Bytes, hyper::Error>>,
+    users: &UserStorage,
+    uid: &str,
+) -> Response<BoxBody<Bytes, hyper::Error>> {
+    if let Some(etag) = current_user_etag(users, uid, false) {
+        response.headers_mut().insert(ETAG, etag.parse().unwrap());
+    }
+    response
+}
+
+fn get_user_by_username(
+    username: String,
+    users: &UserStorage,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    if !users.has_unique_usernames() {
+        return Ok(bad_request(
+            "Lookup by username requires unique usernames to be enabled",
+        ));
+    }
+
+    let username = percent_decode_str(&username).decode_utf8_lossy();
+    let result = match users.get_entry(username.to_string()) {
         Some(user) => user,
-        None => return Ok(bad_request("User not Found")),
+        None => return Ok(error_response(user_not_found())),
     };
 
     let json = serde_json::to_string(&result).unwrap();
@@ -250,6 +814,91 @@ fn get_user_by_uid(
     Ok(response)
 }
 
+fn get_log_key(
+    users: &UserStorage,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    let key = serde_json::json!({
+        "algorithm": "ED25519",
+        "key": STANDARD.encode(users.key_log().verifying_key().as_bytes()),
+    });
+    Ok(json_response(&key))
+}
+
+fn get_inclusion_proof(
+    uid: String,
+    users: &UserStorage,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    let uid = percent_decode_str(&uid).decode_utf8_lossy();
+    match users.key_log().inclusion_proof(&uid) {
+        Some(proof) => Ok(json_response(&proof)),
+        None => Ok(error_response(ApiError::NotFound(
+            "No log entry for user".to_string(),
+        ))),
+    }
+}
+
+fn get_consistency_proof(
+    first: u64,
+    second: u64,
+    users: &UserStorage,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    match users.key_log().consistency_proof(first, second) {
+        Ok(proof) => Ok(json_response(&proof)),
+        Err(err) => Ok(error_response(err)),
+    }
+}
+
+fn get_profile_picture(
+    uid: String,
+    size: Option<u32>,
+    headers: &HeaderMap,
+    users: &UserStorage,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    let uid = percent_decode_str(&uid).decode_utf8_lossy().to_string();
+    let picture = match users.get_profile_picture(uid, size) {
+        Some(picture) => picture,
+        None => return Ok(not_found()),
+    };
+
+    let etag = format!("\"{}\"", picture.hash);
+    if is_not_modified(headers, &etag) {
+        return Ok(not_modified(&etag));
+    }
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", picture.content_type)
+        .header(ETAG, etag)
+        .body(full(picture.data))
+        .unwrap();
+
+    Ok(response)
+}
+
+fn set_profile_picture(
+    uid: String,
+    picture: &[u8],
+    headers: &HeaderMap,
+    users: &mut UserStorage,
+    file_path: &str,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    let uid = percent_decode_str(&uid).decode_utf8_lossy().to_string();
+    if let Err(err) = check_if_match(headers, &current_user_etags(users, &uid)) {
+        return Ok(error_response(err));
+    }
+    match users.set_profile_picture(uid.clone(), picture, file_path) {
+        Ok(_) => {
+            let response_body = full("message: 'Profile picture updated'");
+            let response = Response::builder()
+                .status(StatusCode::OK)
+                .header("Content-Type", "text/plain")
+                .body(response_body)
+                .unwrap();
+            Ok(with_user_etag(response, users, &uid))
+        }
+        Err(err) => Ok(error_response(err)),
+    }
+}
+
 fn add_new_entry(
     json: Value,
     users: &mut UserStorage,
@@ -262,48 +911,40 @@ fn add_new_entry(
     let description = json["description"].as_str().unwrap_or("");
 
     if uid.is_empty() {
-        let code = "1";
-        let message = "UID cannot be empty";
-        let response_body = format!("code: {}, message: '{}'", code, message);
-        return Ok(bad_request(&response_body));
+        return Ok(bad_request("UID cannot be empty"));
     }
 
     if username.is_empty() {
-        let code = "1";
-        let message = "Username cannot be empty";
-        let response_body = format!("code: {}, message: '{}'", code, message);
-        return Ok(bad_request(&response_body));
+        return Ok(bad_request("Username cannot be empty"));
     }
 
     if public_keys.is_none() {
-        let code = "1";
-        let message = "Public keys cannot be empty";
-        let response_body = format!("code: {}, message: '{}'", code, message);
-        return Ok(bad_request(&response_body));
+        return Ok(bad_request("Public keys cannot be empty"));
     }
 
-    let keys: Result<Vec<PubKey>, String> = public_keys
-        .unwrap()
-        .iter()
-        .map(|key| parse_pubkey(key))
-        .collect();
+    let keys: Result<Vec<PubKey>, ApiError> =
+        public_keys.unwrap().iter().map(parse_pubkey).collect();
 
     let public_keys = match keys {
         Ok(k) => k,
-        Err(missing_field) => {
-            let code = "1";
-            let message = missing_field;
-            let response_body = format!("code: {}, message: '{}'", code, message);
-            return Ok(bad_request(&response_body));
-        }
+        Err(err) => return Ok(error_response(err)),
+    };
+
+    let visibility = match parse_visibility(&json) {
+        Ok(visibility) => visibility,
+        Err(err) => return Ok(error_response(err)),
     };
 
     let mut user_data = UserData {
         uid: uid.to_string(),
         username: username.to_string(),
         public_keys,
-        profile_picture: profile_picture.as_str().unwrap_or("").parse().unwrap(),
+        profile_picture: profile_picture.as_str().unwrap_or("").to_string(),
+        profile_picture_hash: None,
         description: description.to_string(),
+        visibility: visibility.unwrap_or_default(),
+        prekeys: BTreeMap::new(),
+        version: 0,
     };
 
     match users.add_entry(&mut user_data, file_path) {
@@ -314,40 +955,58 @@ fn add_new_entry(
                 .header("Content-Type", "text/plain")
                 .body(response_body)
                 .unwrap();
-            return Ok(response);
-        }
-        Err(_) => {
-            return Ok(bad_request("User already exists"));
+            Ok(response)
         }
+        Err(err) => Ok(error_response(err)),
     }
 }
 
-fn parse_pubkey(key: &Value) -> Result<PubKey, String> {
-    let algorithm = key["algorithm"]
-        .as_str()
-        .ok_or("Algorithm cannot be empty!")?;
-    let signature_key = key["signature_key"]
-        .as_str()
-        .ok_or("Signature key missing!")?;
-    let exchange_key = key["exchange_key"]
-        .as_str()
-        .ok_or("Exchange key missing!")?;
-    let rsa_key = key["rsa_key"].as_str().ok_or("RSA key missing!")?;
-
-    let algo_parsed = algorithm
-        .parse::<PubKeyAlgo>()
-        .map_err(|_| "algorithm (invalid value)")?;
+fn parse_pubkey(key: &Value) -> Result<PubKey, ApiError> {
+    let field = |name: &str, message: &str| {
+        key[name]
+            .as_str()
+            .ok_or_else(|| ApiError::InvalidRequest(message.to_string()))
+    };
+    let algorithm = field("algorithm", "Algorithm cannot be empty")?.parse::<PubKeyAlgo>()?;
+    let exchange_algorithm = match key.get("exchange_algorithm").and_then(Value::as_str) {
+        Some(algorithm) => Some(algorithm.parse::<PubKeyAlgo>()?),
+        None => None,
+    };
 
     Ok(PubKey {
-        algorithm: algo_parsed,
-        signature_key: signature_key.to_string(),
-        exchange_key: exchange_key.to_string(),
-        rsa_key: rsa_key.to_string(),
+        algorithm,
+        signature_key: field("signature_key", "Signature key missing")?.to_string(),
+        exchange_algorithm,
+        exchange_key: field("exchange_key", "Exchange key missing")?.to_string(),
+        // older clients send an empty string instead of leaving the RSA key out
+        rsa_key: key["rsa_key"]
+            .as_str()
+            .filter(|rsa_key| !rsa_key.is_empty())
+            .map(str::to_string),
+        created_at: None,
+        expires_at: key["expires_at"].as_u64(),
+        revoked_at: None,
+        revocation_reason: None,
     })
 }
 
+/// Reads the optional `visibility` of a user, e.g. `"visibility": "uid_only"`.
+fn parse_visibility(json: &Value) -> Result<Option<Visibility>, ApiError> {
+    match json.get("visibility") {
+        None | Some(Value::Null) => Ok(None),
+        Some(visibility) => serde_json::from_value(visibility.clone())
+            .map(Some)
+            .map_err(|_| {
+                ApiError::InvalidRequest(
+                    "Visibility has to be public, exact_username or uid_only".to_string(),
+                )
+            }),
+    }
+}
+
 fn change_profile(
     json: Value,
+    headers: &HeaderMap,
     users: &mut UserStorage,
     file_path: &str,
 ) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
@@ -357,10 +1016,15 @@ fn change_profile(
     let description = json["description"].as_str().unwrap_or("");
 
     if uid.is_empty() {
-        let code = "1";
-        let message = "UID cannot be empty";
-        let response_body = format!("code: {}, message: '{}'", code, message);
-        return Ok(bad_request(&response_body));
+        return Ok(bad_request("UID cannot be empty"));
+    }
+
+    let visibility = match parse_visibility(&json) {
+        Ok(visibility) => visibility,
+        Err(err) => return Ok(error_response(err)),
+    };
+    if let Err(err) = check_if_match(headers, &current_user_etags(users, uid)) {
+        return Ok(error_response(err));
     }
 
     match users.update_profile(
@@ -368,6 +1032,7 @@ fn change_profile(
         username.to_string(),
         profile_picture.to_string(),
         description.to_string(),
+        visibility,
         file_path,
     ) {
         Ok(_) => {
@@ -377,11 +1042,9 @@ fn change_profile(
                 .header("Content-Type", "text/plain")
                 .body(response_body)
                 .unwrap();
-            return Ok(response);
-        }
-        Err(_) => {
-            return Ok(bad_request("User not found"));
+            Ok(with_user_etag(response, users, uid))
         }
+        Err(err) => Ok(error_response(err)),
     }
 }
 
@@ -394,38 +1057,17 @@ fn add_pub_keys(
     let public_keys = json["public_keys"].as_array();
 
     if uid.is_empty() {
-        let code = "1";
-        let message = "UID cannot be empty";
-        let response_body = format!("code: {}, message: '{}'", code, message);
-        return Ok(bad_request(&response_body));
+        return Ok(bad_request("UID cannot be empty"));
     }
 
     if public_keys.is_none() {
-        let code = "1";
-        let message = "Public keys cannot be empty";
-        let response_body = format!("code: {}, message: '{}'", code, message);
-        return Ok(bad_request(&response_body));
-    }
-
-    let public_keys = public_keys.unwrap();
-
-    let pub_keys = public_keys
-        .iter()
-        .map(|key| {
-            let key = key.as_object().unwrap();
-            let algorithm = key["algorithm"].as_str().unwrap();
-            let signature_key = key["signature_key"].as_str().unwrap();
-            let exchange_key = key["exchange_key"].as_str().unwrap();
-            let rsa_key = key["rsa_key"].as_str().unwrap();
-
-            PubKey {
-                algorithm: algorithm.parse::<PubKeyAlgo>().unwrap(),
-                signature_key: signature_key.to_string(),
-                exchange_key: exchange_key.to_string(),
-                rsa_key: rsa_key.to_string(),
-            }
-        })
-        .collect();
+        return Ok(bad_request("Public keys cannot be empty"));
+    }
+
+    let pub_keys = match public_keys.unwrap().iter().map(parse_pubkey).collect() {
+        Ok(keys) => keys,
+        Err(err) => return Ok(error_response(err)),
+    };
 
     match users.add_pub_keys(uid.to_string(), pub_keys, file_path) {
         Ok(_) => {
@@ -435,14 +1077,9 @@ fn add_pub_keys(
                 .header("Content-Type", "text/plain")
                 .body(response_body)
                 .unwrap();
-            return Ok(response);
-        }
-        Err(_) => {
-            let code = "2";
-            let message = "User not found";
-            let response_body = format!("{{\"code\": {}, \"message\": \"{}\"}}", code, message);
-            return Ok(bad_request(&response_body));
+            Ok(response)
         }
+        Err(err) => Ok(error_response(err)),
     }
 }
 
@@ -459,40 +1096,76 @@ fn delete_user(
                 .header("Content-Type", "text/plain")
                 .body(response_body)
                 .unwrap();
-            return Ok(response);
-        }
-        Err(_) => {
-            return Ok(bad_request("User not found"));
+            Ok(response)
         }
+        Err(err) => Ok(error_response(err)),
     }
 }
 
 fn delete_pub_key_from_user(
     uid: String,
     public_key: String,
+    reason: Option<RevocationReason>,
     users: &mut UserStorage,
     file_path: &str,
 ) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
-    match users.delete_pub_key(uid, public_key, file_path) {
+    match users.delete_pub_key(uid, public_key, reason, file_path) {
         Ok(_) => {
-            let response_body = full("message: 'Public key deleted'");
+            let response_body = full("message: 'Public key revoked'");
             let response = Response::builder()
                 .status(StatusCode::OK)
                 .header("Content-Type", "text/plain")
                 .body(response_body)
                 .unwrap();
-            return Ok(response);
-        }
-        Err(err) => {
-            return Ok(bad_request(&err.to_string()));
+            Ok(response)
         }
+        Err(err) => Ok(error_response(err)),
     }
 }
 
-fn empty() -> BoxBody<Bytes, hyper::Error> {
-    Empty::<Bytes>::new()
-        .map_err(|never| match never {})
-        .boxed()
+/// Reads the uid and the percent decoded signature key of a device from the rest of the path.
+fn device_path(path_it: &mut std::path::Iter) -> Result<(String, String), ApiError> {
+    let invalid = |message: &str| ApiError::InvalidRequest(message.to_string());
+    let uid = match path_it.next() {
+        Some(uid) => uid.to_str().unwrap().to_string(),
+        None => return Err(invalid("UID cannot be empty")),
+    };
+    let signature_key = match path_it.next() {
+        Some(key) => key.to_str().unwrap(),
+        None => return Err(invalid("Signature key cannot be empty")),
+    };
+    match percent_decode_str(signature_key).decode_utf8() {
+        Ok(key) => Ok((uid, key.to_string())),
+        Err(_) => Err(invalid("Invalid signature key")),
+    }
+}
+
+/// Collects a request body, but stops reading as soon as it exceeds `max_size` bytes. Fails with
+/// `PayloadTooLarge` if the body is too large and with `InvalidRequest` if it could not be read.
+async fn collect_limited<B: Body>(body: B, max_size: usize) -> Result<Vec<u8>, ApiError>
+where
+    <B as Body>::Error: Debug,
+{
+    let mut body = pin!(body);
+    let mut data = Vec::new();
+    while let Some(frame) = body.frame().await {
+        let frame = frame.map_err(|err| {
+            tracing::debug!(error = ?err, "Could not read the request body");
+            ApiError::InvalidRequest("The request body could not be read".to_string())
+        })?;
+        if let Ok(mut chunk) = frame.into_data() {
+            if data.len() + chunk.remaining() > max_size {
+                return Err(ApiError::PayloadTooLarge { max_size });
+            }
+            while chunk.has_remaining() {
+                let bytes = chunk.chunk();
+                data.extend_from_slice(bytes);
+                let len = bytes.len();
+                chunk.advance(len);
+            }
+        }
+    }
+    Ok(data)
 }
 
 fn full<T: Into<Bytes>>(data: T) -> BoxBody<Bytes, hyper::Error> {
@@ -501,11 +1174,111 @@ fn full<T: Into<Bytes>>(data: T) -> BoxBody<Bytes, hyper::Error> {
         .boxed()
 }
 
-fn bad_request(message: &str) -> Response<BoxBody<Bytes, hyper::Error>> {
-    let body: BoxBody<Bytes, hyper::Error> = full(Bytes::from(message.to_string()));
+/// Whether a boolean query parameter like `?include_expired=true` is set.
+fn query_flag(query: Option<&str>, name: &str) -> bool {
+    query
+        .unwrap_or("")
+        .split('&')
+        .any(|pair| match pair.split_once('=') {
+            Some((key, value)) => key == name && value == "true",
+            None => pair == name,
+        })
+}
+
+/// The parameters of a change feed request.
+struct ChangesQuery {
+    since: u64,
+    limit: usize,
+    uids: Option<Vec<String>>,
+}
+
+/// Parses the `since`, `limit` and `uids` parameters of a change feed request. Without `since` the
+/// feed starts at the first change. `uids` is a comma separated list of percent encoded uids.
+fn parse_changes_query(query: Option<&str>) -> Result<ChangesQuery, ApiError> {
+    let mut changes_query = ChangesQuery {
+        since: 0,
+        limit: MAX_LIMIT,
+        uids: None,
+    };
+    for pair in query.unwrap_or("").split('&') {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        match key {
+            "since" => {
+                changes_query.since = value.parse().map_err(|_| {
+                    ApiError::InvalidRequest("since has to be a sequence number".to_string())
+                })?
+            }
+            "limit" => match value.parse::<usize>() {
+                Ok(value) if (1..=MAX_LIMIT).contains(&value) => changes_query.limit = value,
+                _ => {
+                    return Err(ApiError::InvalidRequest(format!(
+                        "limit has to be a number between 1 and {}",
+                        MAX_LIMIT
+                    )))
+                }
+            },
+            "uids" => {
+                changes_query.uids = Some(
+                    value
+                        .split(',')
+                        .filter(|uid| !uid.is_empty())
+                        .map(|uid| percent_decode_str(uid).decode_utf8_lossy().to_string())
+                        .collect(),
+                )
+            }
+            _ => {}
+        }
+    }
+    Ok(changes_query)
+}
+
+/// A JSON response with an ETag, or `304 Not Modified` if the client already has it.
+fn conditional_json<T: Serialize>(
+    value: &T,
+    headers: &HeaderMap,
+) -> Response<BoxBody<Bytes, hyper::Error>> {
+    let json = serde_json::to_string(value).unwrap();
+    let etag = etag(json.as_bytes());
+    if is_not_modified(headers, &etag) {
+        return not_modified(&etag);
+    }
     Response::builder()
-        .status(StatusCode::BAD_REQUEST)
-        .header("Content-Type", "text/plain")
-        .body(body)
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .header(ETAG, etag)
+        .body(full(Bytes::from(json)))
         .unwrap()
 }
+
+fn not_modified(etag: &str) -> Response<BoxBody<Bytes, hyper::Error>> {
+    Response::builder()
+        .status(StatusCode::NOT_MODIFIED)
+        .header(ETAG, etag)
+        .body(full(Bytes::new()))
+        .unwrap()
+}
+
+fn json_response<T: Serialize>(value: &T) -> Response<BoxBody<Bytes, hyper::Error>> {
+    let json = serde_json::to_string(value).unwrap();
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .body(full(Bytes::from(json)))
+        .unwrap()
+}
+
+fn not_found() -> Response<BoxBody<Bytes, hyper::Error>> {
+    ApiError::NotFound("Resource not found".to_string()).into_response()
+}
+
+fn user_not_found() -> ApiError {
+    ApiError::NotFound("User not found".to_string())
+}
+
+fn bad_request(message: &str) -> Response<BoxBody<Bytes, hyper::Error>> {
+    ApiError::InvalidRequest(message.to_string()).into_response()
+}
+
+fn error_response(err: impl Into<ApiError>) -> Response<BoxBody<Bytes, hyper::Error>> {
+    err.into().into_response()
+}
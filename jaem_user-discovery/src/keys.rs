@@ -0,0 +1,134 @@
+use std::{fmt::Display, str::FromStr};
+
+use anyhow::bail;
+use base64::{
+    engine::general_purpose::{STANDARD, STANDARD_NO_PAD, URL_SAFE, URL_SAFE_NO_PAD},
+    Engine as _,
+};
+use ed25519_dalek::VerifyingKey;
+use jaem_common::error::ApiError;
+use p256::pkcs8::DecodePublicKey;
+use rsa::{pkcs1::DecodeRsaPublicKey, traits::PublicKeyParts, RsaPublicKey};
+use serde::{Deserialize, Serialize};
+
+/// RSA keys with less bits than this are rejected.
+pub const MIN_RSA_BITS: usize = 2048;
+
+/// The kinds of keys that can be published. Keys are sent base64 encoded, P-256 and RSA keys may
+/// also be sent PEM encoded.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum PubKeyAlgo {
+    /// Ed25519 signing key, 32 bytes.
+    ED25519,
+    /// X25519 exchange key, 32 bytes.
+    X25519,
+    /// NIST P-256 key for ECDSA or ECDH, as SEC1 point or SubjectPublicKeyInfo.
+    #[serde(rename = "P-256")]
+    P256,
+    /// RSA encryption key with OAEP padding, as SubjectPublicKeyInfo or PKCS#1.
+    #[serde(rename = "RSA-OAEP")]
+    RsaOaep,
+}
+
+impl PubKeyAlgo {
+    pub fn can_sign(&self) -> bool {
+        matches!(self, Self::ED25519 | Self::P256)
+    }
+
+    pub fn can_exchange(&self) -> bool {
+        matches!(self, Self::X25519 | Self::P256)
+    }
+
+    /// The algorithm of the exchange key if a key only specifies its signature algorithm.
+    pub fn default_exchange_algorithm(&self) -> Self {
+        match self {
+            Self::P256 => Self::P256,
+            _ => Self::X25519,
+        }
+    }
+
+    /// Checks that the key is well formed for this algorithm.
+    pub fn validate(&self, key: &str) -> Result<(), anyhow::Error> {
+        let valid = match self {
+            Self::ED25519 => decode_base64(key)
+                .and_then(|key| <[u8; 32]>::try_from(key).ok())
+                .is_some_and(|key| VerifyingKey::from_bytes(&key).is_ok()),
+            Self::X25519 => decode_base64(key).is_some_and(|key| key.len() == 32),
+            Self::P256 => parse_p256(key).is_some(),
+            Self::RsaOaep => match parse_rsa(key) {
+                Some(key) if key.n().bits() < MIN_RSA_BITS => bail!(ApiError::InvalidRequest(
+                    format!("RSA keys need at least {} bits", MIN_RSA_BITS)
+                )),
+                Some(_) => true,
+                None => false,
+            },
+        };
+        if !valid {
+            bail!(ApiError::InvalidRequest(format!("Invalid {} key", self)));
+        }
+        Ok(())
+    }
+}
+
+impl Display for PubKeyAlgo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::ED25519 => "ED25519",
+            Self::X25519 => "X25519",
+            Self::P256 => "P-256",
+            Self::RsaOaep => "RSA-OAEP",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for PubKeyAlgo {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().replace('_', "-").as_str() {
+            "ED25519" => Ok(Self::ED25519),
+            "X25519" => Ok(Self::X25519),
+            "P-256" | "P256" => Ok(Self::P256),
+            "RSA-OAEP" => Ok(Self::RsaOaep),
+            _ => bail!(ApiError::InvalidRequest(format!(
+                "Unknown algorithm '{}', expected ED25519, X25519, P-256 or RSA-OAEP",
+                s
+            ))),
+        }
+    }
+}
+
+/// Decodes standard or URL safe base64, with or without padding.
+pub fn decode_base64(data: &str) -> Option<Vec<u8>> {
+    [STANDARD, URL_SAFE, STANDARD_NO_PAD, URL_SAFE_NO_PAD]
+        .iter()
+        .find_map(|engine| engine.decode(data).ok())
+}
+
+fn is_pem(key: &str) -> bool {
+    key.trim_start().starts_with("-----BEGIN")
+}
+
+fn parse_p256(key: &str) -> Option<p256::PublicKey> {
+    if is_pem(key) {
+        return p256::PublicKey::from_public_key_pem(key.trim()).ok();
+    }
+    let key = decode_base64(key)?;
+    p256::PublicKey::from_sec1_bytes(&key)
+        .or_else(|_| p256::PublicKey::from_public_key_der(&key))
+        .ok()
+}
+
+fn parse_rsa(key: &str) -> Option<RsaPublicKey> {
+    if is_pem(key) {
+        let key = key.trim();
+        return RsaPublicKey::from_public_key_pem(key)
+            .or_else(|_| RsaPublicKey::from_pkcs1_pem(key))
+            .ok();
+    }
+    let key = decode_base64(key)?;
+    RsaPublicKey::from_public_key_der(&key)
+        .or_else(|_| RsaPublicKey::from_pkcs1_der(&key))
+        .ok()
+}
@@ -1,3 +1,16 @@
+pub mod auth;
+pub mod changes;
+pub mod conditional;
+pub mod contact_discovery;
 pub mod handle_connection;
+pub mod keys;
+pub mod metrics;
+pub mod pagination;
+pub mod prekeys;
+pub mod profile_picture;
+pub mod rate_limit;
+pub mod search_index;
+pub mod server;
+pub mod transparency;
 pub mod user_data;
-
+pub mod username;
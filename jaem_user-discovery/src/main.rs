@@ -1,52 +1,43 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::sync::Arc;
 
-use hyper::{server::conn::http1, service::service_fn};
-use jaem_user_discovery::{handle_connection, user_data::UserStorage};
-use tokio::sync::Mutex;
-
-const USERS_FILE: &str = "users.json";
-const PORT: u16 = 3000;
-
-/*
- * Run Server on Port 3000
- * Used Address 0.0.0.0 for deploying with docker
- *
- * TODO: ConfigFile for Setting different Port, Address and USER_FILE path
-*/
+use jaem_common::{logging, shutdown::shutdown_signal};
+use jaem_config::{
+    loader::{warn_about_ignored, ConfigLoader},
+    reload::{LiveConfig, WATCH_INTERVAL},
+};
+use jaem_user_discovery::server;
 
 #[tokio::main]
 async fn main() {
-    let addr = SocketAddr::from(([0, 0, 0, 0], PORT));
-    let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
-
-    // Read users from file
-    let users = UserStorage::read_from_file("users.json").unwrap();
-
-    // Make user data mutex to avoid race conditions when accessing data
-    let user_mutex = Arc::new(Mutex::new(users));
-
-    // Main loop
-    loop {
-        // Listen on Port
-        let (stream, _) = listener.accept().await.unwrap();
-        let io = hyper_util::rt::TokioIo::new(stream);
-
-        // Clone the Arc to pass to new thread
-        let user_mutex = Arc::clone(&user_mutex);
-
-        // Spawn handle_connection task on new thread
-        tokio::task::spawn(async move {
-            if let Err(err) = http1::Builder::new()
-                .serve_connection(
-                    io,
-                    service_fn(|req| {
-                        handle_connection::handle_connection(req, user_mutex.clone(), USERS_FILE)
-                    }),
-                )
-                .await
-            {
-                eprintln!("{}", err);
-            }
-        });
+    // Settings are taken from the configuration file, environment variables and flags
+    let loader = match ConfigLoader::from_env() {
+        Ok(loader) => loader,
+        Err(err) => {
+            eprintln!("Could not load the configuration: {:#}", err);
+            std::process::exit(1);
+        }
+    };
+    let (config, ignored) = match loader.load_reporting_ignored() {
+        Ok(loaded) => loaded,
+        Err(err) => {
+            eprintln!("Could not load the configuration: {:#}", err);
+            std::process::exit(1);
+        }
+    };
+    let log_level = match logging::init(&config.logging) {
+        Ok(log_level) => log_level,
+        Err(err) => {
+            eprintln!("Could not set up logging: {:#}", err);
+            std::process::exit(1);
+        }
+    };
+    warn_about_ignored(&ignored);
+    let live_config = Arc::new(LiveConfig::new(loader, config));
+    log_level.follow(&live_config);
+    live_config.watch(WATCH_INTERVAL);
+
+    if let Err(err) = server::run(live_config, shutdown_signal()).await {
+        tracing::error!("{:#}", err);
+        std::process::exit(1);
     }
 }
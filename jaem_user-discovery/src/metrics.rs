@@ -0,0 +1,30 @@
+use std::sync::LazyLock;
+
+use jaem_common::metrics::HttpMetrics;
+use prometheus::{Histogram, HistogramOpts, IntGauge};
+
+/// The metrics of the user discovery, exposed under `/metrics`.
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+pub struct Metrics {
+    pub http: HttpMetrics,
+    pub users: IntGauge,
+    pub search_duration: Histogram,
+}
+
+impl Metrics {
+    fn new() -> Metrics {
+        let metrics = Metrics {
+            http: HttpMetrics::new("user_discovery"),
+            users: IntGauge::new("users", "Registered users").unwrap(),
+            search_duration: Histogram::with_opts(HistogramOpts::new(
+                "search_duration_seconds",
+                "Time it took to search users by name",
+            ))
+            .unwrap(),
+        };
+        metrics.http.register(metrics.users.clone());
+        metrics.http.register(metrics.search_duration.clone());
+        metrics
+    }
+}
@@ -0,0 +1,75 @@
+use anyhow::bail;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use jaem_common::error::ApiError;
+use percent_encoding::percent_decode_str;
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Number of users returned per page if the client does not ask for a different limit.
+pub const DEFAULT_LIMIT: usize = 20;
+
+/// Upper bound for the `limit` query parameter.
+pub const MAX_LIMIT: usize = 100;
+
+/// Pagination parameters taken from the query string, e.g. `?limit=20&cursor=...`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageRequest {
+    pub limit: usize,
+    pub cursor: Option<String>,
+}
+
+impl PageRequest {
+    /// Parses the pagination parameters of a query string. Returns `None` if the query contains
+    /// neither `limit` nor `cursor`, in which case the legacy page based listing is used.
+    pub fn from_query(query: Option<&str>) -> Result<Option<PageRequest>, anyhow::Error> {
+        let mut limit = None;
+        let mut cursor = None;
+        for pair in query.unwrap_or("").split('&') {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let value = percent_decode_str(value).decode_utf8_lossy();
+            match key {
+                "limit" => match value.parse::<usize>() {
+                    Ok(value) if (1..=MAX_LIMIT).contains(&value) => limit = Some(value),
+                    _ => bail!(ApiError::InvalidRequest(format!(
+                        "limit has to be a number between 1 and {}",
+                        MAX_LIMIT
+                    ))),
+                },
+                "cursor" if !value.is_empty() => cursor = Some(value.to_string()),
+                "cursor" => bail!(ApiError::InvalidRequest(
+                    "cursor cannot be empty".to_string()
+                )),
+                _ => {}
+            }
+        }
+
+        if limit.is_none() && cursor.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(PageRequest {
+            limit: limit.unwrap_or(DEFAULT_LIMIT),
+            cursor,
+        }))
+    }
+
+    /// Decodes the position stored in the cursor, if there is one.
+    pub fn position<T: DeserializeOwned>(&self) -> Result<Option<T>, anyhow::Error> {
+        match &self.cursor {
+            Some(cursor) => decode_cursor(cursor).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Encodes the position of the last item on a page as an opaque cursor. Clients are expected to
+/// pass the cursor back unchanged to get the next page.
+pub fn encode_cursor<T: Serialize>(position: &T) -> String {
+    URL_SAFE_NO_PAD.encode(serde_json::to_vec(position).unwrap_or_default())
+}
+
+pub fn decode_cursor<T: DeserializeOwned>(cursor: &str) -> Result<T, anyhow::Error> {
+    URL_SAFE_NO_PAD
+        .decode(cursor)
+        .ok()
+        .and_then(|json| serde_json::from_slice(&json).ok())
+        .ok_or_else(|| ApiError::InvalidRequest("Invalid cursor".to_string()).into())
+}
@@ -0,0 +1,169 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    net::IpAddr,
+    time::Duration,
+};
+
+use anyhow::bail;
+use ed25519_dalek::VerifyingKey;
+use jaem_common::error::ApiError;
+use serde::{Deserialize, Serialize};
+
+use crate::{auth::verify_signature, rate_limit::RateLimiter};
+
+/// Maximum number of unused one-time prekeys stored per device.
+pub const MAX_ONE_TIME_PREKEYS: usize = 100;
+
+/// How many prekey bundles a client can fetch per hour, over all devices.
+pub const BUNDLES_PER_CLIENT_PER_HOUR: u32 = 30;
+
+/// How many one-time prekeys of a device are handed out per hour, over all clients. Further
+/// bundles of the device come without a one-time prekey, so that nobody can use them up faster
+/// than the device replenishes them.
+pub const ONE_TIME_PREKEYS_PER_DEVICE_PER_HOUR: u32 = 20;
+
+/// A medium-term prekey, signed with the signature key of the device it belongs to.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct SignedPrekey {
+    pub key_id: u32,
+    pub public_key: String,
+    /// Base64 encoded ED25519 signature over `signed_prekey_message`.
+    pub signature: String,
+}
+
+/// A prekey that is handed out to exactly one contact.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct OneTimePrekey {
+    pub key_id: u32,
+    pub public_key: String,
+}
+
+/// The prekeys published for one device. Devices are identified by the signature key of their
+/// `PubKey`.
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct DevicePrekeys {
+    pub signed_prekey: Option<SignedPrekey>,
+    #[serde(default)]
+    pub one_time_prekeys: VecDeque<OneTimePrekey>,
+}
+
+/// Prekeys uploaded by the owner of a device. The signed prekey is only required for the first
+/// upload, later uploads may just replenish the one-time prekeys.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PrekeyUpload {
+    #[serde(default)]
+    pub signed_prekey: Option<SignedPrekey>,
+    #[serde(default)]
+    pub one_time_prekeys: Vec<OneTimePrekey>,
+}
+
+/// Limits how fast prekey bundles are handed out, per client and per device.
+#[derive(Debug)]
+pub struct BundleLimiter {
+    clients: RateLimiter<IpAddr>,
+    devices: RateLimiter<String>,
+}
+
+impl Default for BundleLimiter {
+    fn default() -> BundleLimiter {
+        let hour = Duration::from_secs(60 * 60);
+        BundleLimiter {
+            clients: RateLimiter::new(BUNDLES_PER_CLIENT_PER_HOUR, hour),
+            devices: RateLimiter::new(ONE_TIME_PREKEYS_PER_DEVICE_PER_HOUR, hour),
+        }
+    }
+}
+
+impl BundleLimiter {
+    /// Fails if the client fetched too many bundles.
+    pub fn check_client(&mut self, client: IpAddr) -> Result<(), ApiError> {
+        self.clients.check(client, 1)
+    }
+
+    /// Whether another one-time prekey of the device with the given signature key may be handed
+    /// out.
+    pub fn allows_one_time_prekey(&mut self, signature_key: &str) -> bool {
+        self.devices.check(signature_key.to_string(), 1).is_ok()
+    }
+}
+
+/// Everything a contact needs to set up a session with a device without it being online.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PrekeyBundle {
+    pub uid: String,
+    pub signature_key: String,
+    /// The long-term exchange key of the device.
+    pub identity_key: String,
+    pub signed_prekey: SignedPrekey,
+    /// `None` once the device ran out of one-time prekeys or handed out too many of them
+    /// recently.
+    pub one_time_prekey: Option<OneTimePrekey>,
+}
+
+/// Reported to the owner of a device, so that it knows when to upload new prekeys.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct PrekeyCount {
+    pub signed_prekey_id: Option<u32>,
+    pub one_time_prekeys: usize,
+}
+
+/// The message signed for a signed prekey: `jaem-prekey:{key_id}:{public_key}`.
+pub fn signed_prekey_message(key_id: u32, public_key: &str) -> Vec<u8> {
+    format!("jaem-prekey:{}:{}", key_id, public_key).into_bytes()
+}
+
+impl DevicePrekeys {
+    /// Replaces the signed prekey if a new one was uploaded and adds the one-time prekeys.
+    pub fn upload(
+        &mut self,
+        signature_key: &VerifyingKey,
+        upload: PrekeyUpload,
+    ) -> Result<(), anyhow::Error> {
+        if let Some(signed_prekey) = &upload.signed_prekey {
+            let message = signed_prekey_message(signed_prekey.key_id, &signed_prekey.public_key);
+            if !verify_signature(signature_key, &message, &signed_prekey.signature) {
+                bail!(ApiError::Forbidden(
+                    "Invalid signature of the signed prekey".to_string()
+                ));
+            }
+        } else if self.signed_prekey.is_none() {
+            bail!(ApiError::InvalidRequest(
+                "Signed prekey cannot be empty".to_string()
+            ));
+        }
+
+        let mut key_ids: HashSet<u32> = self.one_time_prekeys.iter().map(|k| k.key_id).collect();
+        for prekey in &upload.one_time_prekeys {
+            if prekey.public_key.is_empty() {
+                bail!(ApiError::InvalidRequest(
+                    "One-time prekeys cannot be empty".to_string()
+                ));
+            }
+            if !key_ids.insert(prekey.key_id) {
+                bail!(ApiError::Conflict(format!(
+                    "One-time prekey {} already exists",
+                    prekey.key_id
+                )));
+            }
+        }
+        if key_ids.len() > MAX_ONE_TIME_PREKEYS {
+            bail!(ApiError::InvalidRequest(format!(
+                "At most {} one-time prekeys can be stored per device",
+                MAX_ONE_TIME_PREKEYS
+            )));
+        }
+
+        if upload.signed_prekey.is_some() {
+            self.signed_prekey = upload.signed_prekey;
+        }
+        self.one_time_prekeys.extend(upload.one_time_prekeys);
+        Ok(())
+    }
+
+    pub fn count(&self) -> PrekeyCount {
+        PrekeyCount {
+            signed_prekey_id: self.signed_prekey.as_ref().map(|key| key.key_id),
+            one_time_prekeys: self.one_time_prekeys.len(),
+        }
+    }
+}
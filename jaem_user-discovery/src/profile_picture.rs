@@ -0,0 +1,324 @@
+use std::{
+    fs,
+    io::Cursor,
+    path::{Path, PathBuf},
+};
+
+use anyhow::bail;
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use image::{
+    codecs::{jpeg::JpegEncoder, webp::WebPEncoder},
+    DynamicImage, ImageDecoder, ImageError, ImageReader, ImageResult, Limits,
+};
+use jaem_common::error::ApiError;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use sha2::{Digest, Sha256};
+
+use crate::user_data::UserData;
+
+/// Characters of a uid that are kept as they are when the uid is used in a file name or URL.
+const UID_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'_');
+
+/// Edge lengths in pixels of the thumbnails generated for every profile picture. Thumbnails keep
+/// the aspect ratio of the original and fit into a square of this size.
+pub const THUMBNAIL_SIZES: [u32; 3] = [64, 128, 256];
+
+/// JPEG quality used when re-encoding pictures.
+const JPEG_QUALITY: u8 = 90;
+
+/// Maximum width and height in pixels of uploaded pictures. Small files can declare huge images,
+/// so the limits are enforced while decoding.
+pub const MAX_PICTURE_EDGE: u32 = 4096;
+
+/// Maximum number of bytes the decoder may allocate for a picture.
+const MAX_DECODE_ALLOC: u64 = 128 * 1024 * 1024;
+
+/// Image formats accepted as profile pictures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png,
+    Jpeg,
+    Webp,
+}
+
+impl ImageFormat {
+    /// Detects the format of an image by its magic bytes.
+    pub fn detect(data: &[u8]) -> Option<ImageFormat> {
+        if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Some(ImageFormat::Png)
+        } else if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            Some(ImageFormat::Jpeg)
+        } else if data.len() >= 12 && &data[0..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+            Some(ImageFormat::Webp)
+        } else {
+            None
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Png => "image/png",
+            Self::Jpeg => "image/jpeg",
+            Self::Webp => "image/webp",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::Jpeg => "jpg",
+            Self::Webp => "webp",
+        }
+    }
+
+    /// Decodes an image of this format within the size limits. The EXIF orientation, if any, is
+    /// applied to the pixels so that the image still looks the same once its metadata has been
+    /// dropped.
+    fn decode(&self, data: &[u8]) -> ImageResult<DynamicImage> {
+        let format = match self {
+            Self::Png => image::ImageFormat::Png,
+            Self::Jpeg => image::ImageFormat::Jpeg,
+            Self::Webp => image::ImageFormat::WebP,
+        };
+        let mut limits = Limits::default();
+        limits.max_image_width = Some(MAX_PICTURE_EDGE);
+        limits.max_image_height = Some(MAX_PICTURE_EDGE);
+        limits.max_alloc = Some(MAX_DECODE_ALLOC);
+        let mut reader = ImageReader::with_format(Cursor::new(data), format);
+        reader.limits(limits);
+        let mut decoder = reader.into_decoder()?;
+        let orientation = decoder.orientation()?;
+        let mut image = DynamicImage::from_decoder(decoder)?;
+        image.apply_orientation(orientation);
+        Ok(image)
+    }
+
+    /// Encodes an image in this format. Metadata like EXIF is not carried over.
+    fn encode(&self, image: &DynamicImage) -> Result<Vec<u8>, anyhow::Error> {
+        let mut buffer = Cursor::new(Vec::new());
+        match self {
+            Self::Png => image.write_to(&mut buffer, image::ImageFormat::Png)?,
+            Self::Jpeg => DynamicImage::ImageRgb8(image.to_rgb8())
+                .write_with_encoder(JpegEncoder::new_with_quality(&mut buffer, JPEG_QUALITY))?,
+            Self::Webp => DynamicImage::ImageRgba8(image.to_rgba8())
+                .write_with_encoder(WebPEncoder::new_lossless(&mut buffer))?,
+        }
+        Ok(buffer.into_inner())
+    }
+}
+
+/// A profile picture as it is served to clients.
+#[derive(Debug, Clone)]
+pub struct ProfilePicture {
+    pub data: Vec<u8>,
+    pub content_type: &'static str,
+    pub hash: String,
+}
+
+/// Stores profile pictures in a directory on disk. Each user has at most one picture, named after
+/// the uid of the user, and one thumbnail per size in `THUMBNAIL_SIZES` next to it.
+#[derive(Debug, Clone)]
+pub struct PictureStore {
+    pub directory: PathBuf,
+    pub max_size: usize,
+}
+
+impl Default for PictureStore {
+    fn default() -> Self {
+        Self {
+            directory: PathBuf::from("./src/profile_pictures/"),
+            max_size: 2 * 1024 * 1024,
+        }
+    }
+}
+
+/// A validated picture, re-encoded without metadata and with its thumbnails, that has not been
+/// written to disk yet.
+#[derive(Debug, Clone)]
+pub struct SanitizedPicture {
+    format: ImageFormat,
+    data: Vec<u8>,
+    thumbnails: Vec<(u32, Vec<u8>)>,
+}
+
+impl PictureStore {
+    /// Validates an image and re-encodes it to strip metadata like EXIF. Thumbnails are generated
+    /// from it. Nothing is written to disk, so the image can be checked before anything changes.
+    pub fn sanitize(&self, data: &[u8]) -> Result<SanitizedPicture, anyhow::Error> {
+        if data.len() > self.max_size {
+            bail!(ApiError::PayloadTooLarge {
+                max_size: self.max_size
+            });
+        }
+        let format = match ImageFormat::detect(data) {
+            Some(format) => format,
+            None => bail!(ApiError::InvalidRequest(
+                "Unsupported image format. Supported are PNG, JPEG and WebP".to_string()
+            )),
+        };
+        let image = match format.decode(data) {
+            Ok(image) => image,
+            Err(ImageError::Limits(_)) => bail!(ApiError::InvalidRequest(format!(
+                "Profile picture must be at most {}x{} pixels",
+                MAX_PICTURE_EDGE, MAX_PICTURE_EDGE
+            ))),
+            Err(_) => bail!(ApiError::InvalidRequest(
+                "Profile picture is not a valid image".to_string()
+            )),
+        };
+        let sanitized = format.encode(&image)?;
+        let mut thumbnails = Vec::with_capacity(THUMBNAIL_SIZES.len());
+        for size in THUMBNAIL_SIZES {
+            let thumbnail = if image.width() <= size && image.height() <= size {
+                sanitized.clone()
+            } else {
+                format.encode(&image.thumbnail(size, size))?
+            };
+            thumbnails.push((size, thumbnail));
+        }
+        Ok(SanitizedPicture {
+            format,
+            data: sanitized,
+            thumbnails,
+        })
+    }
+
+    /// Decodes a base64 encoded image, as it is sent in the JSON bodies of `create_user` and
+    /// `profile`, and sanitizes it.
+    pub fn sanitize_base64(&self, encoded: &str) -> Result<SanitizedPicture, anyhow::Error> {
+        let data = match STANDARD.decode(encoded.trim()) {
+            Ok(data) => data,
+            Err(_) => bail!(ApiError::InvalidRequest(
+                "Profile picture has to be a base64 encoded image".to_string()
+            )),
+        };
+        self.sanitize(&data)
+    }
+
+    /// Writes a sanitized picture and its thumbnails as the profile picture of the user, replacing
+    /// the previous picture. The path and hash of the picture are updated in the user data.
+    pub fn save(
+        &self,
+        user: &mut UserData,
+        picture: SanitizedPicture,
+    ) -> Result<(), anyhow::Error> {
+        fs::create_dir_all(&self.directory)?;
+        let path = self.directory.join(format!(
+            "{}.{}",
+            encode_uid(&user.uid),
+            picture.format.extension()
+        ));
+        fs::write(&path, &picture.data)?;
+        for (size, thumbnail) in &picture.thumbnails {
+            fs::write(thumbnail_path(&path, *size), thumbnail)?;
+        }
+        if Path::new(&user.profile_picture) != path {
+            self.remove(user);
+        }
+
+        user.profile_picture = path.to_string_lossy().to_string();
+        user.profile_picture_hash = Some(picture_hash(&picture.data));
+        Ok(())
+    }
+
+    /// Validates an image and stores it as the profile picture of the user, see `sanitize` and
+    /// `save`.
+    pub fn store(&self, user: &mut UserData, data: &[u8]) -> Result<(), anyhow::Error> {
+        let picture = self.sanitize(data)?;
+        self.save(user, picture)
+    }
+
+    /// Decodes a base64 encoded image and stores it, see `store`.
+    pub fn store_base64(&self, user: &mut UserData, encoded: &str) -> Result<(), anyhow::Error> {
+        let picture = self.sanitize_base64(encoded)?;
+        self.save(user, picture)
+    }
+
+    /// Reads the profile picture of a user. If a size is given, the thumbnail of that size is
+    /// returned instead. Pictures stored before thumbnails were introduced are returned in their
+    /// original size.
+    pub fn load(&self, user: &UserData, size: Option<u32>) -> Option<ProfilePicture> {
+        let thumbnail = match size {
+            Some(size) if !THUMBNAIL_SIZES.contains(&size) => return None,
+            Some(size) => fs::read(thumbnail_path(Path::new(&user.profile_picture), size)).ok(),
+            None => None,
+        };
+        let (data, hash) = match thumbnail {
+            Some(data) => {
+                let hash = picture_hash(&data);
+                (data, hash)
+            }
+            None => {
+                let data = fs::read(&user.profile_picture).ok()?;
+                let hash = match &user.profile_picture_hash {
+                    Some(hash) => hash.clone(),
+                    None => picture_hash(&data),
+                };
+                (data, hash)
+            }
+        };
+        let content_type = ImageFormat::detect(&data)
+            .map(|format| format.content_type())
+            .unwrap_or("application/octet-stream");
+        Some(ProfilePicture {
+            data,
+            content_type,
+            hash,
+        })
+    }
+
+    /// Reads the profile picture of a user so that it can be embedded in a JSON response. Images
+    /// are base64 encoded, pictures stored by older versions are returned as they are.
+    pub fn load_inline(&self, user: &UserData) -> String {
+        match fs::read(&user.profile_picture) {
+            Ok(data) if ImageFormat::detect(&data).is_some() => STANDARD.encode(data),
+            Ok(data) => String::from_utf8(data).unwrap_or("null".to_string()),
+            Err(_) => "null".to_string(),
+        }
+    }
+
+    /// Deletes the profile picture of a user and its thumbnails from disk.
+    pub fn remove(&self, user: &UserData) {
+        if user
+            .profile_picture
+            .starts_with(&*self.directory.to_string_lossy())
+        {
+            let path = Path::new(&user.profile_picture);
+            for size in THUMBNAIL_SIZES {
+                let _ = fs::remove_file(thumbnail_path(path, size));
+            }
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Hex encoded SHA-256 hash of a picture, used as its ETag.
+pub fn picture_hash(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// URL under which the profile picture of a user can be retrieved.
+pub fn picture_url(uid: &str) -> String {
+    format!("/user/{}/picture", encode_uid(uid))
+}
+
+/// Thumbnails are stored next to the original, e.g. `1234.64.png` for `1234.png`. Encoded uids
+/// never contain a `.`, so a thumbnail cannot have the name of another user's picture.
+fn thumbnail_path(original: &Path, size: u32) -> PathBuf {
+    let stem = original
+        .file_stem()
+        .map(|stem| stem.to_string_lossy())
+        .unwrap_or_default();
+    let file_name = match original.extension() {
+        Some(extension) => format!("{}.{}.{}", stem, size, extension.to_string_lossy()),
+        None => format!("{}.{}", stem, size),
+    };
+    original.with_file_name(file_name)
+}
+
+fn encode_uid(uid: &str) -> String {
+    utf8_percent_encode(uid, UID_ENCODE_SET).to_string()
+}
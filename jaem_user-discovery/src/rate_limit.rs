@@ -0,0 +1,75 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+use jaem_common::error::ApiError;
+
+/// Buckets are only pruned once more clients than this have been seen.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// Token bucket rate limiter with one bucket per client address, or per other key like a device.
+/// Every client may spend up to `capacity` tokens at once, which are refilled evenly over `period`.
+#[derive(Debug)]
+pub struct RateLimiter<K = IpAddr> {
+    capacity: f64,
+    per_second: f64,
+    buckets: HashMap<K, Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl<K: Eq + Hash> RateLimiter<K> {
+    pub fn new(capacity: u32, period: Duration) -> RateLimiter<K> {
+        RateLimiter {
+            capacity: capacity as f64,
+            per_second: capacity as f64 / period.as_secs_f64(),
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Takes `cost` tokens from the bucket of the client or fails with the number of seconds
+    /// after which enough tokens will be available.
+    pub fn check(&mut self, client: K, cost: u32) -> Result<(), ApiError> {
+        let now = Instant::now();
+        if self.buckets.len() > PRUNE_THRESHOLD {
+            self.prune(now);
+        }
+        let (capacity, per_second) = (self.capacity, self.per_second);
+        let bucket = self.buckets.entry(client).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+        bucket.tokens = (bucket.tokens
+            + now.duration_since(bucket.updated).as_secs_f64() * per_second)
+            .min(capacity);
+        bucket.updated = now;
+
+        let cost = cost as f64;
+        if cost > capacity {
+            return Err(ApiError::RateLimited { retry_after: None });
+        }
+        if bucket.tokens < cost {
+            let retry_after = ((cost - bucket.tokens) / per_second).ceil() as u64;
+            return Err(ApiError::RateLimited {
+                retry_after: Some(retry_after),
+            });
+        }
+        bucket.tokens -= cost;
+        Ok(())
+    }
+
+    /// Forgets clients whose buckets are full again.
+    fn prune(&mut self, now: Instant) {
+        let (capacity, per_second) = (self.capacity, self.per_second);
+        self.buckets.retain(|_, bucket| {
+            bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * per_second < capacity
+        });
+    }
+}
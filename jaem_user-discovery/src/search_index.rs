@@ -0,0 +1,225 @@
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+};
+
+use serde::{Deserialize, Serialize};
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+use crate::user_data::UserData;
+
+/// Minimum trigram similarity for a username to count as a fuzzy match.
+const SIMILARITY_THRESHOLD: f32 = 0.3;
+
+/// Queries shorter than this cannot be split into trigrams and are matched by scanning the
+/// normalized usernames instead. Fuzzy matching is disabled for them.
+const MIN_FUZZY_QUERY_LEN: usize = 3;
+
+/// How well a username matches a search query. The variants are ordered by relevance, so sorting
+/// by `MatchKind` puts exact matches first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum MatchKind {
+    Exact,
+    Prefix,
+    Substring,
+    Fuzzy,
+}
+
+/// A single search result pointing to the user with the given uid.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchHit {
+    pub uid: String,
+    pub kind: MatchKind,
+    pub score: f32,
+    /// The normalized username that matched.
+    pub name: String,
+}
+
+impl SearchHit {
+    /// Compares two hits by relevance. The order is total, so a hit can be used to find the
+    /// position after which the next page of results starts.
+    pub fn rank_cmp(&self, other: &SearchHit) -> Ordering {
+        self.kind
+            .cmp(&other.kind)
+            .then(other.score.total_cmp(&self.score))
+            .then(self.name.len().cmp(&other.name.len()))
+            .then(self.name.cmp(&other.name))
+            .then(self.uid.cmp(&other.uid))
+    }
+}
+
+/// In-memory trigram index over the usernames of all users. The index is not persisted and has to
+/// be kept in sync with the user storage whenever a username is added, changed or removed.
+#[derive(Debug, Default, Clone)]
+pub struct SearchIndex {
+    names: HashMap<String, String>,
+    trigrams: HashMap<String, HashSet<String>>,
+}
+
+impl SearchIndex {
+    /// Builds a new index from a list of users.
+    pub fn build(users: &[UserData]) -> SearchIndex {
+        let mut index = SearchIndex::default();
+        for user in users {
+            index.insert(&user.uid, &user.username);
+        }
+        index
+    }
+
+    /// Adds the username of a user to the index. An already indexed username of the same user is
+    /// replaced.
+    pub fn insert(&mut self, uid: &str, username: &str) {
+        self.remove(uid);
+        let name = normalize(username);
+        for gram in trigrams(&name) {
+            self.trigrams
+                .entry(gram)
+                .or_default()
+                .insert(uid.to_string());
+        }
+        self.names.insert(uid.to_string(), name);
+    }
+
+    /// Removes a user from the index.
+    pub fn remove(&mut self, uid: &str) {
+        let Some(name) = self.names.remove(uid) else {
+            return;
+        };
+        for gram in trigrams(&name) {
+            if let Some(uids) = self.trigrams.get_mut(&gram) {
+                uids.remove(uid);
+                if uids.is_empty() {
+                    self.trigrams.remove(&gram);
+                }
+            }
+        }
+    }
+
+    /// Returns all users matching the query ordered by relevance: exact matches first, followed by
+    /// prefix, substring and finally fuzzy matches. Ties are broken by similarity, then by name.
+    pub fn search(&self, query: &str) -> Vec<SearchHit> {
+        let query = normalize(query);
+        if query.is_empty() {
+            return Vec::new();
+        }
+
+        let mut hits: Vec<SearchHit> = if query.chars().count() < MIN_FUZZY_QUERY_LEN {
+            self.names
+                .iter()
+                .filter_map(|(uid, name)| {
+                    let kind = exact_kind(&query, name)?;
+                    Some(SearchHit {
+                        uid: uid.clone(),
+                        kind,
+                        score: 1.0,
+                        name: name.clone(),
+                    })
+                })
+                .collect()
+        } else {
+            let query_grams = trigrams(&query);
+            let candidates: HashSet<&String> = query_grams
+                .iter()
+                .filter_map(|gram| self.trigrams.get(gram))
+                .flatten()
+                .collect();
+
+            candidates
+                .into_iter()
+                .filter_map(|uid| {
+                    let name = self.names.get(uid)?;
+                    let score = similarity(&query_grams, &trigrams(name));
+                    let kind = match exact_kind(&query, name) {
+                        Some(kind) => kind,
+                        None if is_fuzzy_match(&query, name, score) => MatchKind::Fuzzy,
+                        None => return None,
+                    };
+                    Some(SearchHit {
+                        uid: uid.clone(),
+                        kind,
+                        score,
+                        name: name.clone(),
+                    })
+                })
+                .collect()
+        };
+
+        hits.sort_by(SearchHit::rank_cmp);
+        hits
+    }
+}
+
+/// Normalizes a string for searching. Compatibility characters are decomposed, diacritics are
+/// stripped, the result is lowercased and runs of whitespace are collapsed into a single space.
+pub fn normalize(input: &str) -> String {
+    let folded: String = input
+        .nfkd()
+        .filter(|c| !is_combining_mark(*c))
+        .flat_map(char::to_lowercase)
+        .collect();
+    folded.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Splits a normalized string into trigrams. The string is padded with a space on both ends, so
+/// that beginnings and ends of names carry more weight.
+fn trigrams(name: &str) -> HashSet<String> {
+    let padded: Vec<char> = format!(" {} ", name).chars().collect();
+    padded
+        .windows(3)
+        .map(|window| window.iter().collect())
+        .collect()
+}
+
+fn similarity(a: &HashSet<String>, b: &HashSet<String>) -> f32 {
+    let shared = a.intersection(b).count();
+    let total = a.len() + b.len() - shared;
+    if total == 0 {
+        return 0.0;
+    }
+    shared as f32 / total as f32
+}
+
+fn exact_kind(query: &str, name: &str) -> Option<MatchKind> {
+    if name == query {
+        Some(MatchKind::Exact)
+    } else if name.starts_with(query) {
+        Some(MatchKind::Prefix)
+    } else if name.contains(query) {
+        Some(MatchKind::Substring)
+    } else {
+        None
+    }
+}
+
+/// A name matches fuzzily if it is similar enough as a whole or, for single word queries, if one
+/// of its words (or the beginning of one) is only a few typos away from the query.
+fn is_fuzzy_match(query: &str, name: &str, score: f32) -> bool {
+    if score >= SIMILARITY_THRESHOLD {
+        return true;
+    }
+    if query.contains(' ') {
+        return false;
+    }
+
+    let query_len = query.chars().count();
+    let allowed_typos = if query_len <= 5 { 1 } else { 2 };
+    name.split(' ').any(|word| {
+        let word_prefix: String = word.chars().take(query_len).collect();
+        levenshtein(query, word) <= allowed_typos
+            || levenshtein(query, &word_prefix) <= allowed_typos
+    })
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
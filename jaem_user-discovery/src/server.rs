@@ -0,0 +1,161 @@
+use std::{future::Future, sync::Arc, time::Duration};
+
+use anyhow::Context;
+use hyper::service::service_fn;
+use hyper_util::{
+    rt::TokioExecutor,
+    server::{conn::auto, graceful::GracefulShutdown},
+};
+use jaem_common::{health::ReadinessCache, shutdown::drain_connections, tls::TlsTermination};
+use jaem_config::reload::{LiveConfig, WATCH_INTERVAL};
+use tokio::sync::Mutex;
+
+use crate::{
+    handle_connection::{self, RemoteAddr},
+    transparency::load_or_create_signing_key,
+    user_data::UserStorage,
+};
+
+/*
+ * Run the server on the address and port from the configuration until shutdown completes.
+ * Use address 0.0.0.0 for deploying with docker
+ *
+ * On shutdown no new connections are accepted, open connections get shutdown_timeout seconds to
+ * finish and the users are saved once more.
+*/
+pub async fn run(
+    live_config: Arc<LiveConfig>,
+    shutdown: impl Future<Output = ()>,
+) -> Result<(), anyhow::Error> {
+    let ud_config = live_config
+        .get()
+        .user_discovery_config
+        .clone()
+        .unwrap_or_default();
+
+    // the address was validated when the configuration was loaded
+    let addr = ud_config.socket_addr()?;
+    let listener = tokio::net::TcpListener::bind(&addr)
+        .await
+        .with_context(|| format!("Could not listen on {}", addr))?;
+    let users_file: Arc<str> = ud_config
+        .storage_path
+        .to_str()
+        .context("Please use valid UTF-8 for file and directory names.")?
+        .into();
+
+    // Read users from file
+    let mut users = UserStorage::read_from_file(&users_file)
+        .with_context(|| format!("Could not read the users from {}", users_file))?;
+    users.apply_config(&ud_config);
+    users.set_log_signing_key(
+        load_or_create_signing_key(&ud_config.log_signing_key_path)
+            .context("Could not load the signing key of the key transparency log.")?,
+    );
+
+    // Make user data mutex to avoid race conditions when accessing data
+    let user_mutex = Arc::new(Mutex::new(users));
+
+    // Serve HTTPS if configured, reloading certificates when they change
+    let tls = Arc::new(TlsTermination::new(ud_config.tls.as_ref())?);
+    tls.watch(WATCH_INTERVAL);
+
+    // Apply changes to the configuration file without a restart
+    let reloaded_users = Arc::clone(&user_mutex);
+    let reloaded_tls = Arc::clone(&tls);
+    live_config.on_reload(move |config, _| {
+        if let Some(ud_config) = &config.user_discovery_config {
+            reloaded_users.blocking_lock().apply_config(ud_config);
+            if let Err(err) = reloaded_tls.configure(ud_config.tls.as_ref()) {
+                tracing::warn!("Keeping the current TLS settings: {:#}", err);
+            }
+        }
+    });
+
+    // Probes share the outcome of the readiness checks for a few seconds
+    let readiness_cache = Arc::new(ReadinessCache::default());
+
+    // Main loop
+    let connections = GracefulShutdown::new();
+    tokio::pin!(shutdown);
+    loop {
+        // Listen on Port
+        let (stream, remote_addr) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    tracing::warn!("Could not accept a connection: {}", err);
+                    continue;
+                }
+            },
+            _ = &mut shutdown => break,
+        };
+
+        // Clone the Arcs to pass to new thread
+        let live_config = Arc::clone(&live_config);
+        let user_mutex = Arc::clone(&user_mutex);
+        let users_file = Arc::clone(&users_file);
+        let tls = Arc::clone(&tls);
+        let readiness_cache = Arc::clone(&readiness_cache);
+        let connections = connections.watcher();
+
+        // Spawn handle_connection task on new thread
+        tokio::task::spawn(async move {
+            let stream = match tls.accept(stream).await {
+                Ok(stream) => stream,
+                Err(err) => {
+                    tracing::debug!("TLS handshake failed: {}", err);
+                    return;
+                }
+            };
+            let admin_access = stream.admin_access();
+            let io = hyper_util::rt::TokioIo::new(stream);
+            // HTTP/2 is detected by its preface, both with prior knowledge (h2c) and after ALPN
+            let builder = auto::Builder::new(TokioExecutor::new());
+            let connection = builder.serve_connection(
+                io,
+                service_fn(move |mut req| {
+                    let trusted_proxies = live_config
+                        .get()
+                        .user_discovery_config
+                        .as_ref()
+                        .map(|config| config.trusted_proxies.clone())
+                        .unwrap_or_default();
+                    let client =
+                        RemoteAddr::resolve(remote_addr.ip(), req.headers(), &trusted_proxies);
+                    req.extensions_mut().insert(client);
+                    req.extensions_mut().insert(admin_access);
+                    req.extensions_mut().insert(Arc::clone(&readiness_cache));
+                    let user_mutex = user_mutex.clone();
+                    let users_file = users_file.clone();
+                    async move {
+                        handle_connection::handle_connection(req, user_mutex, &users_file).await
+                    }
+                }),
+            );
+            if let Err(err) = connections.watch(connection).await {
+                tracing::debug!("Connection closed with an error: {}", err);
+            }
+        });
+    }
+
+    // Stop accepting connections and let the open ones finish
+    drop(listener);
+    let shutdown_timeout = live_config
+        .get()
+        .user_discovery_config
+        .as_ref()
+        .map_or(ud_config.shutdown_timeout, |config| config.shutdown_timeout);
+    if !drain_connections(connections, Duration::from_secs(shutdown_timeout)).await {
+        tracing::warn!(
+            "Closing connections that did not finish within {} seconds",
+            shutdown_timeout
+        );
+    }
+
+    // Requests that are still running finish their changes before the users are saved
+    let users = user_mutex.lock().await;
+    users
+        .save_to_file(&users_file)
+        .with_context(|| format!("Could not save the users to {}", users_file))
+}
@@ -0,0 +1,460 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Context};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use jaem_common::error::ApiError;
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+use sha2::{Digest, Sha256};
+
+use crate::user_data::PubKey;
+
+/// A SHA-256 hash of a leaf or an inner node of the Merkle tree.
+pub type Hash = [u8; 32];
+
+/// Changes to the public keys of a user that are recorded in the log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyOperation {
+    AddKeys,
+    /// Keys used to be removed instead of revoked. Only found in older entries.
+    RemoveKey,
+    RevokeKey,
+    DeleteUser,
+}
+
+/// A single leaf of the key transparency log.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogEntry {
+    pub uid: String,
+    pub operation: KeyOperation,
+    /// The keys that were added or removed.
+    pub keys: Vec<PubKey>,
+    /// Hash of all keys of the user after the change, see `key_set_hash`.
+    pub key_set_hash: String,
+    pub timestamp: u64,
+}
+
+/// The root of the log at a given size, signed by the service. The timestamp is the time of the
+/// last change, so the tree head of a given size never changes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedTreeHead {
+    pub tree_size: u64,
+    pub timestamp: u64,
+    /// Base64 encoded root hash.
+    pub root_hash: String,
+    /// Base64 encoded ed25519 signature over `tree_head_message`.
+    pub signature: String,
+}
+
+/// Proves that the latest log entry of a user is contained in the tree described by the tree
+/// head.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InclusionProof {
+    pub leaf_index: u64,
+    /// Base64 encoded bytes of the leaf, i.e. the JSON encoded entry.
+    pub leaf_input: String,
+    pub entry: LogEntry,
+    /// Base64 encoded hashes from the leaf up to the root.
+    pub audit_path: Vec<String>,
+    pub tree_head: SignedTreeHead,
+}
+
+/// Proves that the tree of size `first` is a prefix of the tree of size `second`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConsistencyProof {
+    pub first: u64,
+    pub second: u64,
+    /// Base64 encoded hashes as described in RFC 6962, section 2.1.2.
+    pub proof: Vec<String>,
+}
+
+/// Append-only Merkle log of all changes to the public keys of users. Leaves and inner nodes are
+/// hashed as described in RFC 6962. Every leaf is persisted as the exact JSON that was hashed, so
+/// that later changes to `LogEntry` or `PubKey` cannot change the hashes of existing leaves.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyLog {
+    #[serde(rename = "entries")]
+    leaf_inputs: Vec<Box<RawValue>>,
+    #[serde(skip)]
+    entries: Vec<LogEntry>,
+    #[serde(skip)]
+    leaves: Vec<Hash>,
+    // index of the latest entry of every uid
+    #[serde(skip)]
+    latest: HashMap<String, usize>,
+    #[serde(skip, default = "random_signing_key")]
+    signing_key: SigningKey,
+}
+
+/// Logs without a configured signing key sign their tree heads with a random key.
+impl Default for KeyLog {
+    fn default() -> Self {
+        Self {
+            leaf_inputs: Vec::new(),
+            entries: Vec::new(),
+            leaves: Vec::new(),
+            latest: HashMap::new(),
+            signing_key: random_signing_key(),
+        }
+    }
+}
+
+impl KeyLog {
+    /// Parses the entries and hashes the leaves after the log has been loaded from disk. The leaves
+    /// are hashed as they were stored, never re-encoded.
+    pub fn rebuild(&mut self) -> Result<(), anyhow::Error> {
+        self.entries = self
+            .leaf_inputs
+            .iter()
+            .map(|input| serde_json::from_str(input.get()))
+            .collect::<Result<_, _>>()?;
+        self.leaves = self
+            .leaf_inputs
+            .iter()
+            .map(|input| leaf_hash(input.get().as_bytes()))
+            .collect();
+        self.latest = self
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| (entry.uid.clone(), i))
+            .collect();
+        Ok(())
+    }
+
+    pub fn set_signing_key(&mut self, signing_key: SigningKey) {
+        self.signing_key = signing_key;
+    }
+
+    pub fn verifying_key(&self) -> VerifyingKey {
+        self.signing_key.verifying_key()
+    }
+
+    /// Whether the log contains an entry for the given user.
+    pub fn contains(&self, uid: &str) -> bool {
+        self.latest.contains_key(uid)
+    }
+
+    pub fn size(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Appends a change to the keys of a user. `key_set` are all keys of the user after the
+    /// change.
+    pub fn append(
+        &mut self,
+        uid: &str,
+        operation: KeyOperation,
+        keys: Vec<PubKey>,
+        key_set: &[PubKey],
+    ) {
+        let entry = LogEntry {
+            uid: uid.to_string(),
+            operation,
+            keys,
+            key_set_hash: key_set_hash(key_set),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+        };
+        let input = RawValue::from_string(serde_json::to_string(&entry).unwrap()).unwrap();
+        self.leaves.push(leaf_hash(input.get().as_bytes()));
+        self.leaf_inputs.push(input);
+        self.latest.insert(entry.uid.clone(), self.entries.len());
+        self.entries.push(entry);
+    }
+
+    /// Signs the root of the log at its current size.
+    pub fn tree_head(&self) -> SignedTreeHead {
+        let tree_size = self.size();
+        let timestamp = self.entries.last().map_or(0, |entry| entry.timestamp);
+        let root = merkle_root(&self.leaves);
+        let signature =
+            self.signing_key
+                .sign(&tree_head_message(tree_size as u64, timestamp, &root));
+        SignedTreeHead {
+            tree_size: tree_size as u64,
+            timestamp,
+            root_hash: STANDARD.encode(root),
+            signature: STANDARD.encode(signature.to_bytes()),
+        }
+    }
+
+    /// Proves the inclusion of the latest entry of a user, which contains the hash of the current
+    /// key set of the user.
+    pub fn inclusion_proof(&self, uid: &str) -> Option<InclusionProof> {
+        let index = *self.latest.get(uid)?;
+        let entry = self.entries[index].clone();
+        Some(InclusionProof {
+            leaf_index: index as u64,
+            leaf_input: STANDARD.encode(self.leaf_inputs[index].get()),
+            entry,
+            audit_path: inclusion_path(index, &self.leaves)
+                .iter()
+                .map(|hash| STANDARD.encode(hash))
+                .collect(),
+            tree_head: self.tree_head(),
+        })
+    }
+
+    /// Proves that the log at size `first` is a prefix of the log at size `second`.
+    pub fn consistency_proof(
+        &self,
+        first: u64,
+        second: u64,
+    ) -> Result<ConsistencyProof, anyhow::Error> {
+        if first > second || second > self.size() as u64 {
+            bail!(ApiError::InvalidRequest(format!(
+                "Tree sizes have to satisfy 0 <= first <= second <= {}",
+                self.size()
+            )));
+        }
+        let proof = match first {
+            0 => Vec::new(),
+            _ => consistency_path(first as usize, &self.leaves[..second as usize], true),
+        };
+        Ok(ConsistencyProof {
+            first,
+            second,
+            proof: proof.iter().map(|hash| STANDARD.encode(hash)).collect(),
+        })
+    }
+}
+
+/// Hashes the complete key set of a user, including expired and revoked keys. The keys are hashed
+/// in the order they are returned by `user_by_uid?include_expired=true`, encoded as JSON. Expiry
+/// does not change the hash, since it is not logged. Returned hex encoded. The hash is only
+/// computed when an entry is appended and stored with it, so it is never recomputed for old
+/// entries.
+pub fn key_set_hash(keys: &[PubKey]) -> String {
+    Sha256::digest(serde_json::to_vec(keys).unwrap())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// The message signed for a tree head: the tree size and timestamp as big endian u64 followed by
+/// the root hash.
+pub fn tree_head_message(tree_size: u64, timestamp: u64, root: &Hash) -> Vec<u8> {
+    let mut message = Vec::with_capacity(48);
+    message.extend_from_slice(&tree_size.to_be_bytes());
+    message.extend_from_slice(&timestamp.to_be_bytes());
+    message.extend_from_slice(root);
+    message
+}
+
+/// Checks the signature of a tree head.
+pub fn verify_tree_head(tree_head: &SignedTreeHead, key: &VerifyingKey) -> bool {
+    let (Some(root), Some(signature)) = (
+        decode_hash(&tree_head.root_hash),
+        STANDARD
+            .decode(&tree_head.signature)
+            .ok()
+            .and_then(|signature| Signature::from_slice(&signature).ok()),
+    ) else {
+        return false;
+    };
+    key.verify(
+        &tree_head_message(tree_head.tree_size, tree_head.timestamp, &root),
+        &signature,
+    )
+    .is_ok()
+}
+
+pub fn decode_hash(encoded: &str) -> Option<Hash> {
+    STANDARD.decode(encoded).ok()?.try_into().ok()
+}
+
+pub fn leaf_hash(data: &[u8]) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([0x00]);
+    hasher.update(data);
+    hasher.finalize().into()
+}
+
+fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([0x01]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// Largest power of two smaller than `n`, for `n > 1`.
+fn split_point(n: usize) -> usize {
+    let mut k = 1;
+    while k << 1 < n {
+        k <<= 1;
+    }
+    k
+}
+
+/// The Merkle tree hash of a list of leaf hashes. The hash of the empty tree is the hash of the
+/// empty string.
+pub fn merkle_root(leaves: &[Hash]) -> Hash {
+    match leaves.len() {
+        0 => Sha256::digest([]).into(),
+        1 => leaves[0],
+        n => {
+            let k = split_point(n);
+            node_hash(&merkle_root(&leaves[..k]), &merkle_root(&leaves[k..]))
+        }
+    }
+}
+
+fn inclusion_path(index: usize, leaves: &[Hash]) -> Vec<Hash> {
+    if leaves.len() <= 1 {
+        return Vec::new();
+    }
+    let k = split_point(leaves.len());
+    let (mut path, sibling) = if index < k {
+        (
+            inclusion_path(index, &leaves[..k]),
+            merkle_root(&leaves[k..]),
+        )
+    } else {
+        (
+            inclusion_path(index - k, &leaves[k..]),
+            merkle_root(&leaves[..k]),
+        )
+    };
+    path.push(sibling);
+    path
+}
+
+fn consistency_path(first: usize, leaves: &[Hash], complete: bool) -> Vec<Hash> {
+    let n = leaves.len();
+    if first == n {
+        return match complete {
+            true => Vec::new(),
+            false => vec![merkle_root(leaves)],
+        };
+    }
+    let k = split_point(n);
+    if first <= k {
+        let mut path = consistency_path(first, &leaves[..k], complete);
+        path.push(merkle_root(&leaves[k..]));
+        path
+    } else {
+        let mut path = consistency_path(first - k, &leaves[k..], false);
+        path.push(merkle_root(&leaves[..k]));
+        path
+    }
+}
+
+/// Verifies an inclusion proof as described in RFC 9162, section 2.1.3.2.
+pub fn verify_inclusion(leaf: &Hash, index: u64, size: u64, path: &[Hash], root: &Hash) -> bool {
+    if index >= size {
+        return false;
+    }
+    let (mut fnode, mut snode) = (index, size - 1);
+    let mut hash = *leaf;
+    for sibling in path {
+        if snode == 0 {
+            return false;
+        }
+        if fnode & 1 == 1 || fnode == snode {
+            hash = node_hash(sibling, &hash);
+            while fnode & 1 == 0 && fnode != 0 {
+                fnode >>= 1;
+                snode >>= 1;
+            }
+        } else {
+            hash = node_hash(&hash, sibling);
+        }
+        fnode >>= 1;
+        snode >>= 1;
+    }
+    snode == 0 && &hash == root
+}
+
+/// Verifies a consistency proof as described in RFC 9162, section 2.1.4.2.
+pub fn verify_consistency(
+    first: u64,
+    second: u64,
+    first_root: &Hash,
+    second_root: &Hash,
+    proof: &[Hash],
+) -> bool {
+    if first > second {
+        return false;
+    }
+    if first == second {
+        return proof.is_empty() && first_root == second_root;
+    }
+    if first == 0 {
+        return proof.is_empty();
+    }
+
+    let mut path = proof.to_vec();
+    if first.is_power_of_two() {
+        path.insert(0, *first_root);
+    }
+    let Some((start, rest)) = path.split_first() else {
+        return false;
+    };
+    let (mut fnode, mut snode) = (first - 1, second - 1);
+    while fnode & 1 == 1 {
+        fnode >>= 1;
+        snode >>= 1;
+    }
+    let (mut first_hash, mut second_hash) = (*start, *start);
+    for hash in rest {
+        if snode == 0 {
+            return false;
+        }
+        if fnode & 1 == 1 || fnode == snode {
+            first_hash = node_hash(hash, &first_hash);
+            second_hash = node_hash(hash, &second_hash);
+            while fnode & 1 == 0 && fnode != 0 {
+                fnode >>= 1;
+                snode >>= 1;
+            }
+        } else {
+            second_hash = node_hash(&second_hash, hash);
+        }
+        fnode >>= 1;
+        snode >>= 1;
+    }
+    snode == 0 && &first_hash == first_root && &second_hash == second_root
+}
+
+/// Reads the signing key of the log from a file containing its 32 byte seed. If the file does not
+/// exist, a new key is generated and written to it. Any other error is returned, since a new key
+/// would invalidate all tree heads signed so far.
+pub fn load_or_create_signing_key(path: &Path) -> Result<SigningKey, anyhow::Error> {
+    match fs::read(path) {
+        Ok(seed) => match <[u8; 32]>::try_from(seed.as_slice()) {
+            Ok(seed) => Ok(SigningKey::from_bytes(&seed)),
+            Err(_) => bail!("{} does not contain a valid signing key", path.display()),
+        },
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+            Err(err).with_context(|| format!("Could not read {}", path.display()))
+        }
+        Err(_) => {
+            let signing_key = random_signing_key();
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(path, signing_key.to_bytes())?;
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+            }
+            Ok(signing_key)
+        }
+    }
+}
+
+fn random_signing_key() -> SigningKey {
+    SigningKey::generate(&mut rand::rngs::OsRng)
+}
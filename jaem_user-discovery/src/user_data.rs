@@ -1,29 +1,109 @@
 use std::{
-    fs::{self, OpenOptions},
-    os::unix::fs::FileExt,
-    path::Path,
-    str::FromStr,
+    collections::{BTreeMap, HashMap, HashSet},
+    net::IpAddr,
+    ops::Bound,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
 };
 
-use anyhow::anyhow;
+use anyhow::bail;
+use ed25519_dalek::SigningKey;
+use hyper::{HeaderMap, Method};
+use jaem_common::{error::ApiError, logging::Redacted, metrics::AuthFailure};
+use jaem_config::{ContactDiscoveryLimits, UserDiscoveryConfig, UsernameRules};
 use percent_encoding::{percent_decode, percent_decode_str};
 use serde::{Deserialize, Serialize};
 
-const PROFILE_PICTURE_ROOT: &str = "./src/profile_pictures/";
+pub use crate::keys::PubKeyAlgo;
 
-#[derive(Debug, Serialize, Deserialize)]
+use crate::{
+    auth::{
+        decode_verifying_key, verify_request, verify_signature, UsedSignatures, MAX_REQUEST_AGE,
+    },
+    changes::{ChangeKind, ChangeLog, ChangePage},
+    contact_discovery::{
+        ContactIndex, ContactMatch, DiscoveryParameters, DiscoveryRequest, IdentifierKind,
+    },
+    metrics::METRICS,
+    pagination::{encode_cursor, PageRequest},
+    prekeys::{BundleLimiter, DevicePrekeys, PrekeyBundle, PrekeyCount, PrekeyUpload},
+    profile_picture::{picture_hash, picture_url, PictureStore, ProfilePicture},
+    search_index::{MatchKind, SearchHit, SearchIndex},
+    transparency::{KeyLog, KeyOperation},
+    username::{fold_case, skeleton, validate_username},
+};
+
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct UserStorage {
     pub users: Vec<UserData>,
+    #[serde(skip)]
+    search_index: SearchIndex,
+    #[serde(skip)]
+    username_rules: UsernameRules,
+    // maps the key of every username, see `username_key`, to the uid of its owner
+    #[serde(skip)]
+    username_index: HashMap<String, String>,
+    #[serde(skip)]
+    pictures: PictureStore,
+    // maps every uid to the position of the user in `users`, ordered by uid
+    #[serde(skip)]
+    uid_index: BTreeMap<String, usize>,
+    #[serde(default)]
+    key_log: KeyLog,
+    #[serde(skip)]
+    listing_disabled: bool,
+    #[serde(default)]
+    contact_index: ContactIndex,
+    #[serde(default)]
+    changes: ChangeLog,
+    // maximum age of signed requests in seconds, `MAX_REQUEST_AGE` if not set
+    #[serde(skip)]
+    max_request_age: Option<u64>,
+    #[serde(skip)]
+    used_signatures: UsedSignatures,
+    #[serde(skip)]
+    bundle_limiter: BundleLimiter,
+}
+
+/// One page of search results together with the number of users that matched in total.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SearchResults {
+    pub total: usize,
+    pub users: Vec<ReturnUserData>,
+}
+
+/// A page of users returned by the cursor based listings. `next_cursor` is `None` on the last
+/// page.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UserPage {
+    pub users: Vec<ReturnUserData>,
+    pub next_cursor: Option<String>,
+    pub total: usize,
+}
+
+/// Maximum number of uids in a batch lookup.
+pub const MAX_BATCH_LOOKUP: usize = 100;
+
+/// The result of a batch lookup: the users that were found and the uids that were not.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UserBatch {
+    pub users: Vec<ReturnUserData>,
+    pub missing: Vec<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ReturnUserData {
-    id: usize,
+    // position of the user in a page based listing, not set in cursor based listings
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<usize>,
     pub uid: String,
     pub username: String,
     pub public_keys: Vec<PubKey>,
+    /// URL of the profile picture or "null" if the user has none.
     pub profile_picture: String,
+    pub profile_picture_hash: Option<String>,
     pub description: String,
+    pub version: u64,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -32,29 +112,209 @@ pub struct UserData {
     pub username: String,
     pub public_keys: Vec<PubKey>,
     pub profile_picture: String,
+    #[serde(default)]
+    pub profile_picture_hash: Option<String>,
     pub description: String,
+    #[serde(default)]
+    pub visibility: Visibility,
+    // prekeys of every device, keyed by the signature key of the device. Never sent to clients
+    // as part of the user data.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub prekeys: BTreeMap<String, DevicePrekeys>,
+    // incremented on every change to the user, see `ChangeKind`
+    #[serde(default)]
+    pub version: u64,
+}
+
+/// Who can find a user. Users can always be looked up by their uid.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Visibility {
+    /// Listed in `GET /users` and found by every search.
+    #[default]
+    Public,
+    /// Only found by searches and lookups for the exact username.
+    ExactUsername,
+    /// Only found by uid.
+    UidOnly,
+}
+
+impl Visibility {
+    pub fn is_listed(&self) -> bool {
+        *self == Self::Public
+    }
+
+    /// Whether the user shows up for a search result of the given kind.
+    pub fn is_found_by(&self, kind: MatchKind) -> bool {
+        match self {
+            Self::Public => true,
+            Self::ExactUsername => kind == MatchKind::Exact,
+            Self::UidOnly => false,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct PubKey {
+    /// Algorithm of the signature key.
     pub algorithm: PubKeyAlgo,
     pub signature_key: String,
+    /// Algorithm of the exchange key if it differs from the default for `algorithm`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exchange_algorithm: Option<PubKeyAlgo>,
     pub exchange_key: String,
-    pub rsa_key: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rsa_key: Option<String>,
+    // All timestamps are seconds since the UNIX epoch. Keys published before they were introduced
+    // have no creation time.
+    #[serde(default)]
+    pub created_at: Option<u64>,
+    #[serde(default)]
+    pub expires_at: Option<u64>,
+    #[serde(default)]
+    pub revoked_at: Option<u64>,
+    #[serde(default)]
+    pub revocation_reason: Option<RevocationReason>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub enum PubKeyAlgo {
-    ED25519,
+/// Why a key was revoked, signed with the revoked key itself so that contacts can tell that the
+/// reason was given by the owner of the key.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct RevocationReason {
+    pub reason: String,
+    /// Base64 encoded ED25519 signature over `revocation_message`.
+    pub signature: String,
 }
 
-impl FromStr for PubKeyAlgo {
-    type Err = anyhow::Error;
+impl PubKey {
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    pub fn is_revoked(&self) -> bool {
+        self.revoked_at.is_some()
+    }
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "ED25519" => Ok(PubKeyAlgo::ED25519),
-            _ => Err(anyhow!("Invalid algorithm")),
+    pub fn exchange_algorithm(&self) -> PubKeyAlgo {
+        self.exchange_algorithm
+            .unwrap_or(self.algorithm.default_exchange_algorithm())
+    }
+
+    /// Checks that the algorithms fit the purpose of each key and that the keys are well formed.
+    pub fn validate(&self) -> Result<(), anyhow::Error> {
+        if !self.algorithm.can_sign() {
+            bail!(ApiError::InvalidRequest(format!(
+                "{} cannot be used for signature keys",
+                self.algorithm
+            )));
+        }
+        self.algorithm.validate(&self.signature_key)?;
+        let exchange_algorithm = self.exchange_algorithm();
+        if !exchange_algorithm.can_exchange() {
+            bail!(ApiError::InvalidRequest(format!(
+                "{} cannot be used for exchange keys",
+                exchange_algorithm
+            )));
+        }
+        exchange_algorithm.validate(&self.exchange_key)?;
+        if let Some(rsa_key) = &self.rsa_key {
+            PubKeyAlgo::RsaOaep.validate(rsa_key)?;
+        }
+        Ok(())
+    }
+
+    /// Checks that the revocation reason was signed with this key.
+    fn verify_revocation(&self, reason: &RevocationReason) -> Result<(), anyhow::Error> {
+        let Some(key) = decode_verifying_key(&self.signature_key) else {
+            METRICS.http.auth_failure(AuthFailure::BadKey);
+            bail!(ApiError::InvalidRequest(
+                "Revocation reasons can only be signed by ED25519 keys".to_string()
+            ));
+        };
+        let message = revocation_message(&self.signature_key, &reason.reason);
+        if !verify_signature(&key, &message, &reason.signature) {
+            METRICS.http.auth_failure(AuthFailure::BadSignature);
+            bail!(ApiError::Forbidden(
+                "Invalid signature of the revocation reason".to_string()
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// The message signed for a revocation reason: `jaem-revocation:{signature_key}:{reason}`.
+pub fn revocation_message(signature_key: &str, reason: &str) -> Vec<u8> {
+    format!("jaem-revocation:{}:{}", signature_key, reason).into_bytes()
+}
+
+/// Keys of a user as they are returned in lookups. Revoked keys are kept so that contacts notice
+/// the revocation, expired keys are left out unless asked for.
+fn visible_keys(keys: &[PubKey], include_expired: bool) -> Vec<PubKey> {
+    let now = now();
+    keys.iter()
+        .filter(|key| include_expired || !key.is_expired(now))
+        .cloned()
+        .collect()
+}
+
+/// Finds a key of the user that is neither revoked nor expired.
+fn active_key<'a>(user: &'a UserData, signature_key: &str) -> Result<&'a PubKey, anyhow::Error> {
+    let now = now();
+    match user
+        .public_keys
+        .iter()
+        .find(|key| key.signature_key == signature_key)
+    {
+        Some(key) if !key.is_revoked() && !key.is_expired(now) => Ok(key),
+        Some(_) => bail!(ApiError::Conflict("Key is revoked or expired".to_string())),
+        None => bail!(ApiError::NotFound("Key not found".to_string())),
+    }
+}
+
+/// Validates newly published keys, sets their creation time and makes sure they are not expired
+/// already.
+fn stamp_new_keys(keys: &mut [PubKey]) -> Result<(), anyhow::Error> {
+    let now = now();
+    for key in keys.iter_mut() {
+        key.validate()?;
+        if key.is_expired(now) {
+            bail!(ApiError::InvalidRequest(
+                "Keys must not expire in the past".to_string()
+            ));
+        }
+        key.created_at = Some(now);
+        key.revoked_at = None;
+        key.revocation_reason = None;
+    }
+    Ok(())
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+impl ReturnUserData {
+    fn new(id: Option<usize>, user: &UserData) -> ReturnUserData {
+        Self::with_keys(id, user, false)
+    }
+
+    fn with_keys(id: Option<usize>, user: &UserData, include_expired: bool) -> ReturnUserData {
+        let profile_picture = match user.profile_picture_hash {
+            Some(_) => picture_url(&user.uid),
+            None => "null".to_string(),
+        };
+        ReturnUserData {
+            id,
+            uid: user.uid.clone(),
+            username: user.username.clone(),
+            public_keys: visible_keys(&user.public_keys, include_expired),
+            profile_picture,
+            profile_picture_hash: user.profile_picture_hash.clone(),
+            description: user.description.clone(),
+            version: user.version,
         }
     }
 }
@@ -75,16 +335,31 @@ impl UserStorage {
             .users
             .binary_search_by_key(&user_data.uid, |user| user.uid.clone())
         {
-            Ok(_) => {
-                let code = 1;
-                let message = "User already exists";
-                let response_body = format!("{{\"code\": {}, \"message\": \"{}\"}}", code, message);
-                Err(anyhow!(response_body))
-            }
+            Ok(_) => Err(ApiError::Conflict("User already exists".to_string()).into()),
             Err(i) => {
-                self.generate_profile_picture(user_data);
+                user_data.username = validate_username(&self.username_rules, &user_data.username)?;
+                stamp_new_keys(&mut user_data.public_keys)?;
+                self.check_username_available(&user_data.username, &user_data.uid)?;
+
+                self.generate_profile_picture(user_data)?;
                 self.check_description(user_data);
+                self.search_index
+                    .insert(&user_data.uid, &user_data.username);
+                let key = self.username_key(&user_data.username);
+                self.username_index.insert(key, user_data.uid.clone());
+                self.contact_index
+                    .insert(&user_data.uid, &user_data.username);
+                user_data.version = 0;
                 self.users.insert(i, user_data.clone());
+                self.rebuild_uid_index();
+                self.record_change(i, ChangeKind::Create, None);
+                user_data.version = self.users[i].version;
+                self.key_log.append(
+                    &user_data.uid,
+                    KeyOperation::AddKeys,
+                    user_data.public_keys.clone(),
+                    &user_data.public_keys,
+                );
 
                 self.save_to_file(file_path)?;
                 Ok(())
@@ -92,27 +367,97 @@ impl UserStorage {
         }
     }
 
-    fn generate_profile_picture(&self, user: &mut UserData) {
+    /// Stores the base64 encoded profile picture sent along with a new user.
+    fn generate_profile_picture(&self, user: &mut UserData) -> Result<(), anyhow::Error> {
         if user.profile_picture.is_empty() {
             user.profile_picture = "null".to_string();
+            user.profile_picture_hash = None;
+            return Ok(());
+        }
+        let encoded = std::mem::take(&mut user.profile_picture);
+        self.pictures.store_base64(user, &encoded)
+    }
+
+    /// Replaces the directory profile pictures are stored in and their maximum size in bytes.
+    pub fn set_picture_store(&mut self, directory: PathBuf, max_size: usize) {
+        self.pictures = PictureStore {
+            directory,
+            max_size,
+        };
+    }
+
+    /// The maximum size of a profile picture in bytes.
+    pub fn max_picture_size(&self) -> usize {
+        self.pictures.max_size
+    }
+
+    /// The directory profile pictures are stored in.
+    pub fn picture_directory(&self) -> &Path {
+        &self.pictures.directory
+    }
+
+    /// Validates an image and stores it as the profile picture of the user with the given uid.
+    pub fn set_profile_picture(
+        &mut self,
+        uid: String,
+        data: &[u8],
+        file_path: &str,
+    ) -> Result<(), anyhow::Error> {
+        let i = self.user_position(&uid)?;
+        self.pictures.store(&mut self.users[i], data)?;
+        self.record_change(i, ChangeKind::Update, None);
+        self.save_to_file(file_path)
+    }
+
+    /// Reads the profile picture of the user with the given uid, or one of its thumbnails if a
+    /// size is given.
+    pub fn get_profile_picture(&self, uid: String, size: Option<u32>) -> Option<ProfilePicture> {
+        let user = self.users.iter().find(|user| user.uid == uid)?;
+        self.pictures.load(user, size)
+    }
+
+    /// Replaces the rules usernames are validated against and rebuilds the username index.
+    pub fn set_username_rules(&mut self, rules: UsernameRules) {
+        self.username_rules = rules;
+        self.rebuild_username_index();
+    }
+
+    fn rebuild_username_index(&mut self) {
+        self.username_index.clear();
+        for user in &self.users {
+            let key = self.username_key(&user.username);
+            self.username_index
+                .entry(key)
+                .or_insert_with(|| user.uid.clone());
+        }
+    }
+
+    /// The form in which usernames are compared for uniqueness: the skeleton if confusable
+    /// usernames are rejected, otherwise the case folded username.
+    fn username_key(&self, username: &str) -> String {
+        if self.username_rules.reject_confusables {
+            skeleton(username)
         } else {
-            let file_path = format!("{}{}", PROFILE_PICTURE_ROOT, user.uid.clone() + ".png");
-            let file_path = Path::new(&file_path);
+            fold_case(username)
+        }
+    }
 
-            // Extract the parent directory from the file path
-            if let Some(parent_dir) = file_path.parent() {
-                // Create the parent directory and any missing ancestors
-                fs::create_dir_all(parent_dir).unwrap();
+    /// Fails if usernames have to be unique and another user already owns the username or one
+    /// that could be confused with it.
+    fn check_username_available(&self, username: &str, uid: &str) -> Result<(), anyhow::Error> {
+        if !self.username_rules.unique {
+            return Ok(());
+        }
+        match self.username_index.get(&self.username_key(username)) {
+            Some(owner) if owner != uid => {
+                if self.username_rules.reject_confusables {
+                    bail!(ApiError::Conflict(
+                        "Username is already taken or too similar to an existing one".to_string()
+                    ))
+                }
+                bail!(ApiError::Conflict("Username is already taken".to_string()))
             }
-
-            let file = OpenOptions::new()
-                .create(true)
-                .write(true)
-                .truncate(true)
-                .open(&file_path)
-                .unwrap();
-            let _ = file.write_at(user.profile_picture.as_bytes(), 0);
-            user.profile_picture = file_path.to_str().unwrap().to_string();
+            _ => Ok(()),
         }
     }
 
@@ -128,6 +473,7 @@ impl UserStorage {
         username: String,
         profile_picture: String,
         description: String,
+        visibility: Option<Visibility>,
         file_path: &str,
     ) -> Result<(), anyhow::Error> {
         match self
@@ -135,37 +481,51 @@ impl UserStorage {
             .binary_search_by_key(&uid, |user| user.uid.clone())
         {
             Ok(i) => {
-                let user = &mut self.users[i];
-                if !username.is_empty() {
+                // everything is validated first, so that a bad request leaves the user unchanged
+                let username = if username.is_empty() {
+                    None
+                } else {
+                    let username = validate_username(&self.username_rules, &username)?;
+                    self.check_username_available(&username, &uid)?;
+                    Some(username)
+                };
+                if !profile_picture.is_empty() {
+                    let picture = self.pictures.sanitize_base64(&profile_picture)?;
+                    self.pictures.save(&mut self.users[i], picture)?;
+                }
+
+                let previous_visibility = self.users[i].visibility;
+                if let Some(username) = username {
+                    let old_key = self.username_key(&self.users[i].username);
+                    let new_key = self.username_key(&username);
+                    let user = &mut self.users[i];
+                    if self.username_index.get(&old_key) == Some(&user.uid) {
+                        self.username_index.remove(&old_key);
+                    }
+                    self.username_index.insert(new_key, user.uid.clone());
+                    self.search_index.insert(&user.uid, &username);
+                    self.contact_index.remove(&user.uid, &user.username);
+                    self.contact_index.insert(&user.uid, &username);
                     user.username = username;
                 }
+                let user = &mut self.users[i];
                 if !description.is_empty() {
                     user.description = description;
                 }
-
-                if !profile_picture.is_empty() {
-                    let file_path =
-                        format!("{}{}", PROFILE_PICTURE_ROOT, user.uid.clone() + ".png");
-                    let file = std::fs::File::create(&file_path).unwrap();
-                    let _ = file.write_at(profile_picture.as_bytes(), 0);
-                    user.profile_picture = file_path;
+                if let Some(visibility) = visibility {
+                    user.visibility = visibility;
                 }
+                self.record_change(i, ChangeKind::Update, Some(previous_visibility));
                 let _ = self.save_to_file(file_path);
                 Ok(())
             }
-            Err(_) => {
-                let code = 1;
-                let message = "User not found";
-                let response_body =
-                    format!("{{\"code\": {}, \"message\": \"{}\"}}", code, message).to_string();
-                Err(anyhow!(response_body))
-            }
+            Err(_) => Err(ApiError::NotFound("User not found".to_string()).into()),
         }
     }
     pub fn add_pub_keys(
         &mut self,
         uid: String,
-        pub_keys: Vec<PubKey>,
+        mut pub_keys: Vec<PubKey>,
         file_path: &str,
     ) -> Result<(), anyhow::Error> {
         match self
@@ -173,194 +533,627 @@ impl UserStorage {
             .binary_search_by_key(&uid, |user| user.uid.clone())
         {
             Ok(i) => {
-                for key in pub_keys {
-                    self.users[i].add_pub_key(key);
-                    self.save_to_file(file_path)?;
+                stamp_new_keys(&mut pub_keys)?;
+                for key in pub_keys.iter() {
+                    self.users[i].add_pub_key(key.clone());
                 }
+                self.key_log.append(
+                    &uid,
+                    KeyOperation::AddKeys,
+                    pub_keys,
+                    &self.users[i].public_keys,
+                );
+                self.record_change(i, ChangeKind::KeyAdd, None);
+                self.save_to_file(file_path)?;
             }
             Err(_) => {
-                return Err(anyhow!("User not found"));
+                return Err(ApiError::NotFound("User not found".to_string()).into());
             }
         }
         Ok(())
     }
 
     pub fn delete_entry(&mut self, uid: String, file_path: &str) -> Result<(), anyhow::Error> {
-        //Delete the profile picture image
-
-        let pp_file_path = format!("{}{}", PROFILE_PICTURE_ROOT, uid.clone() + ".png");
-        let _ = std::fs::remove_file(&pp_file_path);
-
         match self
             .users
             .binary_search_by_key(&uid, |user| user.uid.clone())
         {
             Ok(i) => {
-                self.users.remove(i);
+                let user = self.users.remove(i);
+                self.rebuild_uid_index();
+                self.key_log.append(
+                    &user.uid,
+                    KeyOperation::DeleteUser,
+                    user.public_keys.clone(),
+                    &[],
+                );
+                self.changes.append(
+                    &user.uid,
+                    ChangeKind::Delete,
+                    user.version + 1,
+                    user.visibility,
+                    None,
+                    now(),
+                );
+                //Delete the profile picture image
+                self.pictures.remove(&user);
+                self.search_index.remove(&user.uid);
+                self.contact_index.remove(&user.uid, &user.username);
+                let key = self.username_key(&user.username);
+                if self.username_index.get(&key) == Some(&user.uid) {
+                    self.username_index.remove(&key);
+                }
                 let _ = self.save_to_file(file_path);
                 Ok(())
             }
-            Err(_) => Err(anyhow!("User not found")),
+            Err(_) => Err(ApiError::NotFound("User not found".to_string()).into()),
         }
     }
 
+    /// Revokes a key of a user. The key is kept, so that contacts who cached it can tell that it
+    /// must no longer be used.
     pub fn delete_pub_key(
         &mut self,
         uid: String,
         signature_key: String,
+        reason: Option<RevocationReason>,
         file_path: &str,
     ) -> Result<(), anyhow::Error> {
-        match self
-            .users
-            .binary_search_by_key(&uid, |user| user.uid.clone())
+        let i = self.user_position(&uid)?;
+        let user = &mut self.users[i];
+        let Ok(decoded_pub_key) = percent_decode_str(&signature_key).decode_utf8() else {
+            bail!(ApiError::InvalidRequest(
+                "Invalid signature key".to_string()
+            ));
+        };
+        tracing::debug!(%uid, key = %Redacted(decoded_pub_key.as_bytes()), "Revoking a key");
+        // keys are kept in the order they were added, not sorted
+        let Some(j) = user
+            .public_keys
+            .iter()
+            .position(|key| key.signature_key == decoded_pub_key)
+        else {
+            bail!(ApiError::NotFound("Key not found".to_string()));
+        };
+        let key = &mut user.public_keys[j];
+        if key.is_revoked() {
+            bail!(ApiError::Conflict("Key is already revoked".to_string()));
+        }
+        if let Some(reason) = &reason {
+            key.verify_revocation(reason)?;
+        }
+        key.revoked_at = Some(now());
+        key.revocation_reason = reason;
+        let revoked = key.clone();
+        user.prekeys.remove(&revoked.signature_key);
+        self.key_log.append(
+            &uid,
+            KeyOperation::RevokeKey,
+            vec![revoked],
+            &user.public_keys,
+        );
+        self.record_change(i, ChangeKind::KeyRemove, None);
+        self.save_to_file(file_path)
+    }
+
+    /// Stores prekeys for the device with the given signature key and returns how many one-time
+    /// prekeys are left.
+    pub fn upload_prekeys(
+        &mut self,
+        uid: String,
+        signature_key: String,
+        upload: PrekeyUpload,
+        file_path: &str,
+    ) -> Result<PrekeyCount, anyhow::Error> {
+        let i = self.user_position(&uid)?;
+        let user = &mut self.users[i];
+        active_key(user, &signature_key)?;
+        let Some(verifying_key) = decode_verifying_key(&signature_key) else {
+            bail!(ApiError::InvalidRequest(
+                "Prekeys can only be signed by ED25519 keys".to_string()
+            ));
+        };
+        let mut device = user
+            .prekeys
+            .get(&signature_key)
+            .cloned()
+            .unwrap_or_default();
+        device.upload(&verifying_key, upload)?;
+        let count = device.count();
+        user.prekeys.insert(signature_key, device);
+        self.save_to_file(file_path)?;
+        Ok(count)
+    }
+
+    /// The number of prekeys left for the device with the given signature key.
+    pub fn prekey_count(
+        &self,
+        uid: String,
+        signature_key: String,
+    ) -> Result<PrekeyCount, anyhow::Error> {
+        let user = &self.users[self.user_position(&uid)?];
+        active_key(user, &signature_key)?;
+        Ok(user
+            .prekeys
+            .get(&signature_key)
+            .map(DevicePrekeys::count)
+            .unwrap_or(PrekeyCount {
+                signed_prekey_id: None,
+                one_time_prekeys: 0,
+            }))
+    }
+
+    /// Hands out a prekey bundle for the device with the given signature key, which consumes one
+    /// of its one-time prekeys. Bundles are rate limited per client, and one-time prekeys per
+    /// device, see `BundleLimiter`.
+    pub fn take_prekey_bundle(
+        &mut self,
+        uid: String,
+        signature_key: String,
+        client: IpAddr,
+        file_path: &str,
+    ) -> Result<PrekeyBundle, anyhow::Error> {
+        self.bundle_limiter.check_client(client)?;
+        let i = self.user_position(&uid)?;
+        let user = &mut self.users[i];
+        let key = active_key(user, &signature_key)?.clone();
+        let Some(device) = user.prekeys.get_mut(&signature_key) else {
+            bail!(ApiError::NotFound("No prekeys published".to_string()));
+        };
+        let Some(signed_prekey) = device.signed_prekey.clone() else {
+            bail!(ApiError::NotFound("No prekeys published".to_string()));
+        };
+        let one_time_prekey = if !device.one_time_prekeys.is_empty()
+            && self.bundle_limiter.allows_one_time_prekey(&signature_key)
         {
-            Ok(i) => {
-                let user = &mut self.users[i];
-                let decoded_pub_key = percent_decode_str(&signature_key)
-                    .decode_utf8()
-                    .expect("Failed to decode public key");
-                println!("{}", decoded_pub_key);
-                match user
-                    .public_keys
-                    .binary_search_by_key(&decoded_pub_key, |user| {
-                        user.signature_key.clone().into()
-                    }) {
-                    Ok(j) => {
-                        user.public_keys.remove(j);
-                        self.save_to_file(file_path)?;
-                        Ok(())
-                    }
-                    Err(_) => Err(anyhow!("Key not found")),
-                }
-            }
-            Err(_) => Err(anyhow!("User not found")),
+            device.one_time_prekeys.pop_front()
+        } else {
+            None
+        };
+        let consumed = one_time_prekey.is_some();
+        let bundle = PrekeyBundle {
+            uid,
+            signature_key,
+            identity_key: key.exchange_key,
+            signed_prekey,
+            one_time_prekey,
+        };
+        if consumed {
+            self.save_to_file(file_path)?;
         }
+        Ok(bundle)
     }
 
     pub fn get_users(&self, page: usize, page_size: usize) -> Vec<ReturnUserData> {
         let start = page * page_size;
-        let end = match start + page_size {
-            end if end < self.users.len() => end,
-            _ => self.users.len(),
-        };
-
-        let return_users = self.users.get(start..end).unwrap_or(&[]).to_vec();
-        let mut id = start;
-        return_users
+        self.users
             .iter()
-            .map(|user| {
-                let file_data = std::fs::read(&user.profile_picture).unwrap_or_default();
-                let return_user = ReturnUserData {
-                    id,
-                    uid: user.uid.clone(),
-                    username: user.username.clone(),
-                    public_keys: user.public_keys.clone(),
-                    profile_picture: String::from_utf8(file_data).unwrap(),
-                    description: user.description.clone(),
-                };
-                id += 1;
-                return_user
-            })
+            .filter(|user| user.visibility.is_listed())
+            .skip(start)
+            .take(page_size)
+            .enumerate()
+            .map(|(offset, user)| ReturnUserData::new(Some(start + offset), user))
             .collect()
     }
 
+    /// Lists users ordered by uid, starting after the uid stored in the cursor. Users created
+    /// while a client is paging through the list do not shift the following pages.
+    pub fn get_users_page(&self, page: &PageRequest) -> Result<UserPage, anyhow::Error> {
+        let after = page.position::<String>()?;
+        let range = match &after {
+            Some(uid) => (Bound::Excluded(uid.as_str()), Bound::Unbounded),
+            None => (Bound::Unbounded, Bound::Unbounded),
+        };
+        let mut users: Vec<&UserData> = self
+            .uid_index
+            .range::<str, _>(range)
+            .map(|(_, &i)| &self.users[i])
+            .filter(|user| user.visibility.is_listed())
+            .take(page.limit + 1)
+            .collect();
+
+        let next_cursor = if users.len() > page.limit {
+            users.truncate(page.limit);
+            users.last().map(|user| encode_cursor(&user.uid))
+        } else {
+            None
+        };
+
+        Ok(UserPage {
+            users: users
+                .into_iter()
+                .map(|user| ReturnUserData::new(None, user))
+                .collect(),
+            next_cursor,
+            total: self
+                .users
+                .iter()
+                .filter(|user| user.visibility.is_listed())
+                .count(),
+        })
+    }
+
+    /// Looks up a user by username. Usernames are compared after NFKC normalization and without
+    /// regard to case.
     pub fn get_entry(&self, username: String) -> Option<UserData> {
-        match self
-            .users
-            .binary_search_by_key(&username, |user| user.username.clone())
-        {
-            Ok(i) => {
-                let user = self.users[i].clone();
-                let file_data = std::fs::read(&user.profile_picture).unwrap();
-                let return_user = UserData {
-                    uid: user.uid,
-                    username: user.username,
-                    public_keys: user.public_keys,
-                    profile_picture: String::from_utf8(file_data).unwrap(),
-                    description: user.description,
-                };
-                return Some(return_user);
-            }
-            Err(_) => return None,
+        let username = fold_case(&username);
+        let user = if self.username_rules.unique {
+            let uid = self.username_index.get(&self.username_key(&username))?;
+            self.users.iter().find(|user| &user.uid == uid)?
+        } else {
+            self.users
+                .iter()
+                .find(|user| fold_case(&user.username) == username)?
+        };
+        if fold_case(&user.username) != username || user.visibility == Visibility::UidOnly {
+            return None;
         }
+
+        Some(self.with_inline_picture(user))
     }
 
+    /// Whether usernames are unique and can therefore be used to identify a user.
+    pub fn has_unique_usernames(&self) -> bool {
+        self.username_rules.unique
+    }
+
+    /// Searches users by username. Matches are ranked by relevance (exact, prefix, substring,
+    /// then fuzzy) and the requested page is returned together with the total number of matches.
     pub fn get_entries_by_pattern(
         &self,
         pattern: String,
         page: usize,
         page_size: usize,
-    ) -> Option<Vec<ReturnUserData>> {
-        let mut id = page * page_size;
-        let decoded_pattern = percent_decode(&pattern.as_bytes())
-            .decode_utf8()
-            .unwrap()
-            .to_lowercase();
-        let result: Vec<ReturnUserData> = self
-            .users
+    ) -> SearchResults {
+        let hits = self.search_hits(&pattern);
+
+        let start = page * page_size;
+        let users = hits
             .iter()
-            .filter(|user| user.username.to_lowercase().contains(&decoded_pattern))
-            .map(|user| {
-                let file_data = std::fs::read(&user.profile_picture).unwrap_or("null".into());
-                let return_user = ReturnUserData {
-                    id,
-                    uid: user.uid.clone(),
-                    username: user.username.clone(),
-                    public_keys: user.public_keys.clone(),
-                    profile_picture: String::from_utf8(file_data).unwrap_or("null".to_string()),
-                    description: user.description.clone(),
-                };
-                id += 1;
-                return_user
-            })
+            .skip(start)
+            .take(page_size)
+            .filter_map(|hit| self.user_by_index(&hit.uid))
+            .enumerate()
+            .map(|(offset, user)| ReturnUserData::new(Some(start + offset), user))
             .collect();
-        let start = page * page_size;
-        let end = match start + page_size {
-            end if end < result.len() => end,
-            _ => result.len(),
+
+        SearchResults {
+            total: hits.len(),
+            users,
+        }
+    }
+
+    /// Searches users by username like `get_entries_by_pattern`, but pages through the results
+    /// with a cursor that stores the rank of the last returned match.
+    pub fn search_page(
+        &self,
+        pattern: String,
+        page: &PageRequest,
+    ) -> Result<UserPage, anyhow::Error> {
+        let hits = self.search_hits(&pattern);
+
+        let start = match page.position::<SearchHit>()? {
+            Some(after) => hits.partition_point(|hit| hit.rank_cmp(&after).is_le()),
+            None => 0,
+        };
+        let page_hits = hits.get(start..).unwrap_or(&[]);
+        let page_hits = &page_hits[..page_hits.len().min(page.limit)];
+        let next_cursor = match page_hits.last() {
+            Some(last) if start + page_hits.len() < hits.len() => Some(encode_cursor(last)),
+            _ => None,
         };
 
-        let result = result.get(start..end).unwrap_or(&[]).to_vec();
+        Ok(UserPage {
+            users: page_hits
+                .iter()
+                .filter_map(|hit| self.user_by_index(&hit.uid))
+                .map(|user| ReturnUserData::new(None, user))
+                .collect(),
+            next_cursor,
+            total: hits.len(),
+        })
+    }
 
-        return Some(result);
+    /// Searches the index and leaves out users that do not want to be found by a match.
+    fn search_hits(&self, pattern: &str) -> Vec<SearchHit> {
+        let decoded_pattern = percent_decode(pattern.as_bytes()).decode_utf8_lossy();
+        self.search_index
+            .search(&decoded_pattern)
+            .into_iter()
+            .filter(|hit| {
+                self.user_by_index(&hit.uid)
+                    .is_some_and(|user| user.visibility.is_found_by(hit.kind))
+            })
+            .collect()
     }
 
-    pub fn get_entry_by_uid(&self, uid: String) -> Option<UserData> {
-        for user in &self.users {
-            if user.uid == uid {
-                let file_data = std::fs::read(&user.profile_picture).unwrap_or("null".into());
-                let return_user = UserData {
-                    uid: user.uid.clone(),
-                    username: user.username.clone(),
-                    public_keys: user.public_keys.clone(),
-                    profile_picture: String::from_utf8(file_data).unwrap_or("null".to_string()),
-                    description: user.description.clone(),
-                };
-                println!("{:?}", return_user);
-                return Some(return_user);
+    /// Looks up the users whose salted username or uid hash starts with one of the given hashes.
+    /// Users who can only be found by uid are not matched by their username.
+    pub fn discover_contacts(
+        &mut self,
+        request: &DiscoveryRequest,
+        client: IpAddr,
+    ) -> Result<Vec<ContactMatch>, anyhow::Error> {
+        let prefixes = self.contact_index.decode_request(request, client)?;
+        let mut matches = Vec::new();
+        for (hash, prefix) in request.hashes.iter().zip(prefixes) {
+            let users: Vec<ReturnUserData> = self
+                .contact_index
+                .lookup(&prefix)
+                .filter_map(|(uid, kind)| {
+                    let user = self.user_by_index(uid)?;
+                    match kind {
+                        IdentifierKind::Username if user.visibility == Visibility::UidOnly => None,
+                        _ => Some(ReturnUserData::new(None, user)),
+                    }
+                })
+                .collect();
+            if !users.is_empty() {
+                matches.push(ContactMatch {
+                    hash: hash.clone(),
+                    users,
+                });
+            }
+        }
+        Ok(matches)
+    }
+
+    pub fn discovery_parameters(&self) -> DiscoveryParameters {
+        self.contact_index.parameters()
+    }
+
+    /// Replaces the limits of contact discovery.
+    pub fn set_contact_discovery_limits(&mut self, limits: ContactDiscoveryLimits) {
+        self.contact_index.set_limits(limits);
+    }
+
+    /// Enables or disables listing all users with `GET /users`.
+    pub fn set_listing_enabled(&mut self, enabled: bool) {
+        self.listing_disabled = !enabled;
+    }
+
+    pub fn listing_enabled(&self) -> bool {
+        !self.listing_disabled
+    }
+
+    /// Applies the settings of the configuration that can change while the service is running.
+    pub fn apply_config(&mut self, config: &UserDiscoveryConfig) {
+        self.set_username_rules(config.username_rules.clone());
+        self.set_picture_store(
+            config.profile_picture_directory.clone(),
+            config.max_profile_picture_size,
+        );
+        self.set_listing_enabled(config.allow_listing);
+        self.set_contact_discovery_limits(config.contact_discovery.clone());
+        self.set_max_request_age(config.auth_clock_skew);
+    }
+
+    /// Sets how many seconds the timestamp of a signed request may differ from the server time.
+    pub fn set_max_request_age(&mut self, seconds: u64) {
+        self.max_request_age = Some(seconds);
+    }
+
+    pub fn max_request_age(&self) -> u64 {
+        self.max_request_age.unwrap_or(MAX_REQUEST_AGE)
+    }
+
+    /// Verifies that a request was signed by the device with the given signature key and was not
+    /// sent before, see `verify_request`.
+    pub fn authenticate(
+        &mut self,
+        signature_key: &str,
+        method: &Method,
+        path: &str,
+        headers: &HeaderMap,
+        body: &[u8],
+    ) -> Result<(), anyhow::Error> {
+        let max_age = self.max_request_age();
+        verify_request(
+            signature_key,
+            method,
+            path,
+            headers,
+            body,
+            max_age,
+            &mut self.used_signatures,
+        )
+    }
+
+    /// Up to `limit` changes after the change with the given sequence number, either to listed
+    /// users or to the given users.
+    pub fn changes_since(
+        &self,
+        since: u64,
+        limit: usize,
+        uids: Option<&[String]>,
+    ) -> Result<ChangePage, anyhow::Error> {
+        let uids = match uids {
+            Some(uids) if uids.len() > MAX_BATCH_LOOKUP => {
+                bail!(ApiError::InvalidRequest(format!(
+                    "At most {} users can be followed at once",
+                    MAX_BATCH_LOOKUP
+                )))
             }
+            Some(uids) => Some(uids.iter().cloned().collect::<HashSet<_>>()),
+            None => None,
+        };
+        Ok(self.changes.since(since, limit, uids.as_ref()))
+    }
+
+    /// Bumps the version of the user at the given position and adds the change to the change log.
+    /// `previous_visibility` is the visibility before the change, if it may have changed.
+    fn record_change(
+        &mut self,
+        i: usize,
+        kind: ChangeKind,
+        previous_visibility: Option<Visibility>,
+    ) {
+        let user = &mut self.users[i];
+        user.version += 1;
+        let previous_visibility =
+            previous_visibility.filter(|&previous| previous != user.visibility);
+        self.changes.append(
+            &user.uid,
+            kind,
+            user.version,
+            user.visibility,
+            previous_visibility,
+            now(),
+        );
+    }
+
+    fn user_position(&self, uid: &str) -> Result<usize, anyhow::Error> {
+        match self.uid_index.get(uid) {
+            Some(&i) => Ok(i),
+            None => bail!(ApiError::NotFound("User not found".to_string())),
+        }
+    }
+
+    /// The version of the user with the given uid.
+    pub fn user_version(&self, uid: &str) -> Option<u64> {
+        self.user_by_index(uid).map(|user| user.version)
+    }
+
+    /// The number of keys of the user with the given uid that have expired, which are left out
+    /// unless expired keys are requested.
+    pub fn expired_keys(&self, uid: &str) -> usize {
+        let now = now();
+        self.user_by_index(uid).map_or(0, |user| {
+            user.public_keys
+                .iter()
+                .filter(|key| key.is_expired(now))
+                .count()
+        })
+    }
+
+    fn user_by_index(&self, uid: &str) -> Option<&UserData> {
+        self.uid_index.get(uid).map(|&i| &self.users[i])
+    }
+
+    /// Replaces the key the tree heads of the key transparency log are signed with.
+    pub fn set_log_signing_key(&mut self, signing_key: SigningKey) {
+        self.key_log.set_signing_key(signing_key);
+    }
+
+    pub fn key_log(&self) -> &KeyLog {
+        &self.key_log
+    }
+
+    /// The storage is rewritten on every change anyway, so the index is simply rebuilt as well.
+    fn rebuild_uid_index(&mut self) {
+        self.uid_index = self
+            .users
+            .iter()
+            .enumerate()
+            .map(|(i, user)| (user.uid.clone(), i))
+            .collect();
+    }
+
+    pub fn get_entry_by_uid(&self, uid: String, include_expired: bool) -> Option<UserData> {
+        let user = self.user_by_index(&uid)?;
+        let mut return_user = self.with_inline_picture(user);
+        return_user.public_keys = visible_keys(&user.public_keys, include_expired);
+        Some(return_user)
+    }
+
+    /// Looks up several users at once. Unlike `get_entry_by_uid`, profile pictures are returned as
+    /// URLs. Duplicate uids are only looked up once.
+    pub fn get_entries_by_uids(
+        &self,
+        uids: &[String],
+        include_expired: bool,
+    ) -> Result<UserBatch, anyhow::Error> {
+        if uids.len() > MAX_BATCH_LOOKUP {
+            bail!(ApiError::InvalidRequest(format!(
+                "At most {} users can be looked up at once",
+                MAX_BATCH_LOOKUP
+            )));
+        }
+        let mut batch = UserBatch {
+            users: Vec::new(),
+            missing: Vec::new(),
+        };
+        let mut seen = HashSet::new();
+        for uid in uids.iter().filter(|uid| seen.insert(uid.as_str())) {
+            match self.user_by_index(uid) {
+                Some(user) => {
+                    batch
+                        .users
+                        .push(ReturnUserData::with_keys(None, user, include_expired))
+                }
+                None => batch.missing.push(uid.clone()),
+            }
+        }
+        Ok(batch)
+    }
+
+    /// Copies the user data and replaces the path of the profile picture with the picture itself.
+    /// Expired keys are left out.
+    fn with_inline_picture(&self, user: &UserData) -> UserData {
+        UserData {
+            profile_picture: self.pictures.load_inline(user),
+            public_keys: visible_keys(&user.public_keys, false),
+            prekeys: BTreeMap::new(),
+            ..user.clone()
         }
-        None
     }
 
     pub fn read_from_file(file_path: &str) -> Result<UserStorage, anyhow::Error> {
         let file = match std::fs::File::open(file_path) {
             Ok(file) => file,
             Err(_) => {
-                let default_storage = UserStorage { users: Vec::new() };
+                let default_storage = UserStorage::default();
                 default_storage.save_to_file(file_path)?;
                 std::fs::File::open(file_path)?
             }
         };
         let reader = std::io::BufReader::new(file);
-        let storage = serde_json::from_reader(reader)?;
+        let mut storage: UserStorage = serde_json::from_reader(reader)?;
+        storage.search_index = SearchIndex::build(&storage.users);
+        for user in storage.users.iter_mut() {
+            if user.profile_picture_hash.is_none() {
+                user.profile_picture_hash = std::fs::read(&user.profile_picture)
+                    .ok()
+                    .map(|data| picture_hash(&data));
+            }
+        }
+        storage.rebuild_username_index();
+        storage.rebuild_uid_index();
+        for user in &storage.users {
+            storage.contact_index.insert(&user.uid, &user.username);
+        }
+
+        // keys published before the transparency log existed are logged once
+        storage.key_log.rebuild()?;
+        let unlogged: Vec<usize> = (0..storage.users.len())
+            .filter(|&i| !storage.key_log.contains(&storage.users[i].uid))
+            .collect();
+        for &i in &unlogged {
+            let user = &storage.users[i];
+            storage.key_log.append(
+                &user.uid,
+                KeyOperation::AddKeys,
+                user.public_keys.clone(),
+                &user.public_keys,
+            );
+        }
+        if !unlogged.is_empty() || storage.contact_index.has_new_salt() {
+            storage.save_to_file(file_path)?;
+        }
         Ok(storage)
     }
 
+    /// Writes the users to a temporary file next to `file_path` and renames it, so that a failed
+    /// or interrupted save leaves the previous file intact.
     pub fn save_to_file(&self, file_path: &str) -> Result<(), anyhow::Error> {
-        let file = std::fs::File::create(file_path)?;
-        let writer = std::io::BufWriter::new(file);
-        serde_json::to_writer(writer, self)?;
+        let temp_path = format!("{}.tmp", file_path);
+        let file = std::fs::File::create(&temp_path)?;
+        let mut writer = std::io::BufWriter::new(file);
+        serde_json::to_writer(&mut writer, self)?;
+        // Report write errors instead of losing them when the writer is dropped
+        let file = writer.into_inner().map_err(|err| err.into_error())?;
+        file.sync_all()?;
+        std::fs::rename(&temp_path, file_path)?;
         Ok(())
     }
 }
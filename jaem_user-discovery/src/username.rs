@@ -0,0 +1,145 @@
+use anyhow::bail;
+use jaem_common::error::ApiError;
+use jaem_config::{CharacterClass, UsernameRules};
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+/// Scripts whose letters are commonly mistaken for one another.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Script {
+    Latin,
+    Greek,
+    Cyrillic,
+}
+
+/// Normalizes a username to NFKC, trims surrounding whitespace and checks it against the given
+/// rules. Returns the normalized username that should be stored.
+pub fn validate_username(rules: &UsernameRules, username: &str) -> Result<String, anyhow::Error> {
+    let username: String = username.nfkc().collect::<String>().trim().to_string();
+    let length = username.chars().count();
+
+    if length < rules.min_length {
+        bail!(ApiError::InvalidRequest(format!(
+            "Username must be at least {} characters long",
+            rules.min_length
+        )));
+    }
+    if length > rules.max_length {
+        bail!(ApiError::InvalidRequest(format!(
+            "Username must be at most {} characters long",
+            rules.max_length
+        )));
+    }
+    if let Some(c) = username.chars().find(|c| !is_allowed(rules, *c)) {
+        bail!(ApiError::InvalidRequest(format!(
+            "Username contains the character '{}' which is not allowed",
+            c
+        )));
+    }
+    if rules.reject_confusables && is_mixed_script(&username) {
+        bail!(ApiError::InvalidRequest(
+            "Username mixes characters of different scripts".to_string()
+        ));
+    }
+
+    Ok(username)
+}
+
+/// Normalizes a username to NFKC and lowercases it, so that usernames can be compared without
+/// regard to case.
+pub fn fold_case(username: &str) -> String {
+    username
+        .nfkc()
+        .flat_map(char::to_lowercase)
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+/// Reduces a username to a form in which lookalike characters are mapped to the same character.
+/// Two usernames with the same skeleton are likely to be confused by a human.
+pub fn skeleton(username: &str) -> String {
+    let folded: String = username
+        .nfkd()
+        .filter(|c| !is_combining_mark(*c))
+        .flat_map(char::to_lowercase)
+        .map(confusable_base)
+        .collect();
+    folded.replace("rn", "m").replace("vv", "w")
+}
+
+fn is_allowed(rules: &UsernameRules, c: char) -> bool {
+    if rules.allowed_symbols.contains(c) {
+        return true;
+    }
+    rules.allowed_classes.iter().any(|class| match class {
+        CharacterClass::Letters => c.is_alphabetic(),
+        CharacterClass::Digits => c.is_numeric(),
+        CharacterClass::Whitespace => c == ' ',
+        CharacterClass::Punctuation => c.is_ascii_punctuation(),
+    })
+}
+
+fn script_of(c: char) -> Option<Script> {
+    match c as u32 {
+        0x0041..=0x005A | 0x0061..=0x007A | 0x00C0..=0x024F => Some(Script::Latin),
+        0x0370..=0x03FF => Some(Script::Greek),
+        0x0400..=0x052F => Some(Script::Cyrillic),
+        _ => None,
+    }
+}
+
+fn is_mixed_script(username: &str) -> bool {
+    let mut scripts = username.chars().filter_map(script_of);
+    match scripts.next() {
+        Some(first) => scripts.any(|script| script != first),
+        None => false,
+    }
+}
+
+/// Maps a lowercase character to the Latin character it is most likely confused with.
+fn confusable_base(c: char) -> char {
+    match c {
+        // Cyrillic
+        'а' => 'a',
+        'в' => 'b',
+        'е' | 'ё' => 'e',
+        'һ' => 'h',
+        'і' | 'ӏ' => 'l',
+        'ј' => 'j',
+        'к' => 'k',
+        'м' => 'm',
+        'н' => 'h',
+        'о' => 'o',
+        'р' => 'p',
+        'с' => 'c',
+        'ѕ' => 's',
+        'т' => 't',
+        'у' => 'y',
+        'х' => 'x',
+        'ԁ' => 'd',
+        'ԛ' => 'q',
+        'ԝ' => 'w',
+        // Greek
+        'α' => 'a',
+        'β' => 'b',
+        'ε' => 'e',
+        'η' => 'n',
+        'ι' => 'l',
+        'κ' => 'k',
+        'ν' => 'v',
+        'ο' => 'o',
+        'ρ' => 'p',
+        'τ' => 't',
+        'υ' => 'u',
+        'χ' => 'x',
+        // Latin lookalikes and digits
+        'ı' => 'l',
+        'i' => 'l',
+        '1' | '|' => 'l',
+        '0' => 'o',
+        '5' => 's',
+        'ɡ' => 'g',
+        '_' | '-' | '.' => ' ',
+        c => c,
+    }
+}
@@ -1,12 +1,10 @@
 use std::{
-    error::Error,
     fs,
     sync::{Arc, OnceLock},
 };
 
 use ctor::dtor;
-use http_body_util::{combinators::BoxBody, BodyExt};
-use hyper::{body::Bytes, Response};
+use http_body_util::BodyExt;
 use hyper::{Method, Request, StatusCode};
 use jaem_user_discovery::user_data::UserStorage;
 use serde_json::Value;
@@ -66,7 +64,7 @@ async fn get_users_default_page_success() {
 
     assert_eq!(username, "admin");
     assert_eq!(description, "Administrator");
-    assert_eq!(profile_pic, "Im an Image\n");
+    assert_eq!(profile_pic, "/user/0/picture");
 
     let last_user = json[size - 1].as_object().unwrap();
     let last_username = last_user.get("username").unwrap().as_str().unwrap();
@@ -75,7 +73,7 @@ async fn get_users_default_page_success() {
 
     assert_eq!(last_username, "User 17");
     assert_eq!(last_description, "Additional User");
-    assert_eq!(last_profile_pic, "Hello Im profile picture 1017\n");
+    assert_eq!(last_profile_pic, "/user/1017/picture");
 }
 
 #[tokio::test]
@@ -111,7 +109,7 @@ async fn get_users_from_10_to_14_success() {
 
     assert_eq!(username, "User 8");
     assert_eq!(description, "Additional User");
-    assert_eq!(profile_pic, "1008\n");
+    assert_eq!(profile_pic, "/user/1008/picture");
 
     let last_user = json[4].as_object().unwrap();
     let last_username = last_user.get("username").unwrap().as_str().unwrap();
@@ -120,7 +118,7 @@ async fn get_users_from_10_to_14_success() {
 
     assert_eq!(last_username, "User 12");
     assert_eq!(last_description, "Additional User");
-    assert_eq!(last_profile_pic, "1012\n");
+    assert_eq!(last_profile_pic, "/user/1012/picture");
 }
 
 /// Test GET request to search by name
@@ -188,11 +186,133 @@ async fn filter_by_no_name_bad_request() {
     assert_eq!(response.status(), StatusCode::BAD_REQUEST);
 }
 
+#[tokio::test]
+async fn search_ranks_exact_and_prefix_matches_first() {
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(format!("{}/search_users/User%201", BASE_URI))
+        .body("".to_string())
+        .unwrap();
+
+    let users = get_users();
+    let response = jaem_user_discovery::handle_connection::handle_connection(
+        request,
+        users.clone(),
+        "temp_users.json",
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let total = response.headers()["X-Total-Count"]
+        .to_str()
+        .unwrap()
+        .parse::<usize>()
+        .unwrap();
+    let body = response.collect().await.unwrap().to_bytes();
+    let binding = serde_json::from_slice::<Value>(&body).unwrap();
+    let json = binding.as_array().unwrap();
+
+    // "User 1" itself, "User 10" to "User 19" and fuzzy matches like "User 2"
+    assert!(total > 11);
+    assert_eq!(json.len(), 20);
+    assert_eq!(json[0]["username"].as_str().unwrap(), "User 1");
+    for user in &json[1..11] {
+        assert!(user["username"].as_str().unwrap().starts_with("User 1"));
+    }
+}
+
+#[tokio::test]
+async fn search_tolerates_typos_and_diacritics() {
+    for pattern in ["admn", "%C3%84DMIN"] {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(format!("{}/search_users/{}", BASE_URI, pattern))
+            .body("".to_string())
+            .unwrap();
+
+        let users = get_users();
+        let response = jaem_user_discovery::handle_connection::handle_connection(
+            request,
+            users.clone(),
+            "temp_users.json",
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.collect().await.unwrap().to_bytes();
+        let binding = serde_json::from_slice::<Value>(&body).unwrap();
+        let json = binding.as_array().unwrap();
+
+        assert_eq!(json[0]["username"].as_str().unwrap(), "admin");
+    }
+}
+
+/// Test GET request for a single user
+#[tokio::test]
+async fn get_unknown_user_not_found() {
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(format!("{}/user_by_uid/unknown_user", BASE_URI))
+        .body("".to_string())
+        .unwrap();
+
+    let users = get_users();
+
+    let response = jaem_user_discovery::handle_connection::handle_connection(
+        request,
+        users.clone(),
+        "temp_users.json",
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(response.headers()["Content-Type"], "application/json");
+
+    let body = response.collect().await.unwrap().to_bytes();
+    let json = serde_json::from_slice::<Value>(&body).unwrap();
+    assert_eq!(json["code"], "not_found");
+    assert_eq!(json["message"], "User not found");
+}
+
+#[tokio::test]
+async fn get_users_by_uid_success() {
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(format!("{}/users_by_uid", BASE_URI))
+        .body(r#"{"uids":["66", "unknown_user", "0", "66"]}"#.to_string())
+        .unwrap();
+
+    let users = get_users();
+
+    let response = jaem_user_discovery::handle_connection::handle_connection(
+        request,
+        users.clone(),
+        "temp_users.json",
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = response.collect().await.unwrap().to_bytes();
+    let json = serde_json::from_slice::<Value>(&body).unwrap();
+    let found: Vec<&str> = json["users"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|user| user["uid"].as_str().unwrap())
+        .collect();
+    assert_eq!(found, ["66", "0"]);
+    assert_eq!(json["users"][1]["profile_picture"], "/user/0/picture");
+    assert_eq!(json["missing"], serde_json::json!(["unknown_user"]));
+}
+
 /// Test POST requests by adding user
 
 #[tokio::test]
 async fn add_user_success() {
-    let body = r#"{"uid":"12", "username":"Hello", "public_keys":[{"algorithm":"ED25519", "signature_key":"test_sig","exchange_key":"test_ex","rsa_key":"test_rsa"}]}"#;
+    let body = r#"{"uid":"12", "username":"Hello", "public_keys":[{"algorithm":"ED25519", "signature_key":"iojj3XQJ8ZX9UtstPLpdcspnCb8dlBIb83SIAbQPb1w=","exchange_key":"CQkJCQkJCQkJCQkJCQkJCQkJCQkJCQkJCQkJCQkJCQk="}]}"#;
     let request = Request::builder()
         .method(Method::POST)
         .uri(format!("{}/create_user", BASE_URI))
@@ -276,7 +396,7 @@ async fn add_user_without_body() {
 /// Test DELETE request
 
 #[tokio::test]
-async fn delete_non_existing_pub_key_bad_request() {
+async fn delete_non_existing_pub_key_not_found() {
     let request = Request::builder()
         .method(Method::DELETE)
         .uri(format!("{}/user/my_user/my_key", BASE_URI))
@@ -292,7 +412,7 @@ async fn delete_non_existing_pub_key_bad_request() {
     )
     .await
     .unwrap();
-    assert_eq!(response.status(), StatusCode::BAD_REQUEST)
+    assert_eq!(response.status(), StatusCode::NOT_FOUND)
 }
 
 #[tokio::test]
@@ -312,7 +432,7 @@ async fn delete_pub_key_success() {
     )
     .await
     .unwrap();
-    assert_eq!(response.status(), StatusCode::BAD_REQUEST)
+    assert_eq!(response.status(), StatusCode::NOT_FOUND)
 }
 
 #[tokio::test]
@@ -332,7 +452,7 @@ async fn delete_user_success() {
     )
     .await
     .unwrap();
-    assert_eq!(response.status(), StatusCode::BAD_REQUEST)
+    assert_eq!(response.status(), StatusCode::NOT_FOUND)
 }
 
 #[tokio::test]
@@ -356,7 +476,7 @@ async fn delete_pub_key_bad_request() {
 }
 
 #[tokio::test]
-async fn delete_non_existing_user_bad_request() {
+async fn delete_non_existing_user_not_found() {
     let request = Request::builder()
         .method(Method::DELETE)
         .uri(format!("{}/user/test%20user", BASE_URI))
@@ -372,5 +492,5 @@ async fn delete_non_existing_user_bad_request() {
     )
     .await
     .unwrap();
-    assert_eq!(response.status(), StatusCode::BAD_REQUEST)
+    assert_eq!(response.status(), StatusCode::NOT_FOUND)
 }
@@ -0,0 +1,213 @@
+mod common;
+
+use std::{fs, sync::Arc};
+
+use common::{create_user, now, pub_key, request, KEY_A, KEY_B};
+use hyper::{Method, StatusCode};
+use jaem_user_discovery::{changes::CHANGE_RETENTION_SECS, user_data::UserStorage};
+use serde_json::{json, Value};
+use tokio::sync::Mutex;
+
+fn kinds(page: &Value) -> Vec<(&str, &str, u64)> {
+    page["changes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|change| {
+            (
+                change["uid"].as_str().unwrap(),
+                change["kind"].as_str().unwrap(),
+                change["version"].as_u64().unwrap(),
+            )
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn changes_are_returned_after_the_cursor() {
+    let file_path = "temp_changes_01.json";
+    let users = Arc::new(Mutex::new(UserStorage::default()));
+
+    create_user(
+        users.clone(),
+        file_path,
+        json!({ "uid": "1", "username": "Alice", "public_keys": [pub_key(KEY_B)] }),
+    )
+    .await;
+    let body = json!({ "uid": "1", "public_keys": [pub_key(KEY_A)] });
+    let (status, _) = request(
+        users.clone(),
+        file_path,
+        Method::POST,
+        "add_pub_key",
+        &body.to_string(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, page) = request(users.clone(), file_path, Method::GET, "changes", "").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(kinds(&page), [("1", "create", 1), ("1", "key_add", 2)]);
+    assert_eq!(page["cursor"], 2);
+    assert_eq!(page["has_more"], false);
+
+    let body = json!({ "uid": "1", "description": "Hello" });
+    request(
+        users.clone(),
+        file_path,
+        Method::PATCH,
+        "profile",
+        &body.to_string(),
+    )
+    .await;
+    let path = format!(
+        "user/1/{}",
+        percent_encoding::utf8_percent_encode(KEY_A, percent_encoding::NON_ALPHANUMERIC)
+    );
+    let (status, _) = request(users.clone(), file_path, Method::DELETE, &path, "").await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = request(users.clone(), file_path, Method::DELETE, "user/1", "").await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (_, page) = request(
+        users.clone(),
+        file_path,
+        Method::GET,
+        "changes?since=2&limit=2",
+        "",
+    )
+    .await;
+    assert_eq!(kinds(&page), [("1", "update", 3), ("1", "key_remove", 4)]);
+    assert_eq!(page["has_more"], true);
+    let (_, page) = request(
+        users.clone(),
+        file_path,
+        Method::GET,
+        &format!("changes?since={}", page["cursor"]),
+        "",
+    )
+    .await;
+    assert_eq!(kinds(&page), [("1", "delete", 5)]);
+
+    // the change log survives a restart
+    let storage = UserStorage::read_from_file(file_path).unwrap();
+    assert_eq!(storage.changes_since(0, 10, None).unwrap().changes.len(), 5);
+
+    let (status, _) = request(
+        users.clone(),
+        file_path,
+        Method::GET,
+        "changes?since=first",
+        "",
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // Clean up
+    fs::remove_file(file_path).unwrap();
+}
+
+#[tokio::test]
+async fn changes_of_hidden_users_are_skipped() {
+    let file_path = "temp_changes_02.json";
+    let users = Arc::new(Mutex::new(UserStorage::default()));
+    for (uid, visibility) in [("1", "uid_only"), ("2", "public")] {
+        let user = json!({
+            "uid": uid,
+            "username": format!("Anna {}", uid),
+            "public_keys": [],
+            "visibility": visibility,
+        });
+        create_user(users.clone(), file_path, user).await;
+    }
+
+    let (_, page) = request(users.clone(), file_path, Method::GET, "changes", "").await;
+    assert_eq!(kinds(&page), [("2", "create", 1)]);
+    assert_eq!(page["cursor"], 2);
+
+    // hidden changes do not count towards the limit
+    let (_, page) = request(users.clone(), file_path, Method::GET, "changes?limit=1", "").await;
+    assert_eq!(kinds(&page), [("2", "create", 1)]);
+    assert_eq!(page["has_more"], false);
+
+    // contacts can follow hidden users they know
+    let (_, page) = request(users.clone(), file_path, Method::GET, "changes?uids=1", "").await;
+    assert_eq!(kinds(&page), [("1", "create", 1)]);
+    assert_eq!(page["cursor"], 2);
+
+    // a user who hides themselves is announced once
+    let body = json!({ "uid": "2", "visibility": "uid_only" });
+    let (status, _) = request(
+        users.clone(),
+        file_path,
+        Method::PATCH,
+        "profile",
+        &body.to_string(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let body = json!({ "uid": "2", "description": "Hidden" });
+    request(
+        users.clone(),
+        file_path,
+        Method::PATCH,
+        "profile",
+        &body.to_string(),
+    )
+    .await;
+    let (_, page) = request(users.clone(), file_path, Method::GET, "changes?since=2", "").await;
+    assert_eq!(kinds(&page), [("2", "update", 2)]);
+    let (_, page) = request(
+        users.clone(),
+        file_path,
+        Method::GET,
+        "changes?since=2&uids=1,2",
+        "",
+    )
+    .await;
+    assert_eq!(kinds(&page), [("2", "update", 2), ("2", "update", 3)]);
+
+    users.lock().await.set_listing_enabled(false);
+    let (status, _) = request(users.clone(), file_path, Method::GET, "changes", "").await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = request(users.clone(), file_path, Method::GET, "changes?uids=2", "").await;
+    assert_eq!(status, StatusCode::OK);
+
+    // Clean up
+    fs::remove_file(file_path).unwrap();
+}
+
+#[tokio::test]
+async fn old_changes_are_discarded() {
+    let file_path = "temp_changes_03.json";
+    let old = now() - CHANGE_RETENTION_SECS - 60;
+    let storage = json!({
+        "users": [],
+        "changes": {"events": [
+            {"sequence": 1, "uid": "1", "kind": "create", "version": 1, "timestamp": old, "visibility": "public"},
+            {"sequence": 2, "uid": "1", "kind": "delete", "version": 2, "timestamp": old, "visibility": "public"},
+        ]},
+    });
+    fs::write(file_path, storage.to_string()).unwrap();
+    let users = Arc::new(Mutex::new(UserStorage::read_from_file(file_path).unwrap()));
+
+    create_user(
+        users.clone(),
+        file_path,
+        json!({ "uid": "2", "username": "Bob", "public_keys": [] }),
+    )
+    .await;
+
+    // sequence numbers keep counting, clients that missed discarded changes have to resync
+    let (_, page) = request(users.clone(), file_path, Method::GET, "changes", "").await;
+    assert_eq!(kinds(&page), [("2", "create", 1)]);
+    assert_eq!(page["changes"][0]["sequence"], 3);
+    assert_eq!(page["cursor"], 3);
+    assert_eq!(page["resync"], true);
+    let (_, page) = request(users.clone(), file_path, Method::GET, "changes?since=2", "").await;
+    assert_eq!(kinds(&page), [("2", "create", 1)]);
+    assert_eq!(page["resync"], false);
+
+    // Clean up
+    fs::remove_file(file_path).unwrap();
+}
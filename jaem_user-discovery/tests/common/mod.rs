@@ -0,0 +1,148 @@
+//! Helpers shared by the integration tests, which call `handle_connection` directly.
+#![allow(dead_code)]
+
+use std::{
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use ed25519_dalek::{Signer, SigningKey};
+use http_body_util::{BodyExt, Full};
+use hyper::{body::Bytes, HeaderMap, Method, Request, StatusCode};
+use jaem_user_discovery::{
+    auth::{request_message, SIGNATURE_HEADER, TIMESTAMP_HEADER},
+    handle_connection::handle_connection,
+    user_data::UserStorage,
+};
+use serde_json::{json, Value};
+use tokio::sync::Mutex;
+
+pub const BASE_URI: &str = "http://127.0.0.1:8080";
+
+// ED25519 public keys without known private keys
+pub const KEY_A: &str = "7UkoxijRwsbq6QM4kFmVYSlZJzpcY/k2NsFGFKyHN9E=";
+pub const KEY_B: &str = "iojj3XQJ8ZX9UtstPLpdcspnCb8dlBIb83SIAbQPb1w=";
+pub const EXCHANGE_KEY: &str = "CQkJCQkJCQkJCQkJCQkJCQkJCQkJCQkJCQkJCQkJCQk=";
+
+/// The parts of a response the tests look at.
+pub struct Reply {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+impl Reply {
+    /// The body as JSON, `Value::Null` if it is not JSON.
+    pub fn json(&self) -> Value {
+        serde_json::from_slice(&self.body).unwrap_or(Value::Null)
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8(self.body.to_vec()).unwrap()
+    }
+}
+
+/// A request for a path relative to the service, e.g. `user_by_uid/1`.
+pub fn build(method: Method, path: &str, body: impl AsRef<[u8]>) -> Request<Full<Bytes>> {
+    Request::builder()
+        .method(method)
+        .uri(format!("{}/{}", BASE_URI, path))
+        .body(Full::new(Bytes::copy_from_slice(body.as_ref())))
+        .unwrap()
+}
+
+/// A request signed with the key of a device at the given time.
+pub fn build_signed(
+    method: Method,
+    path: &str,
+    body: impl AsRef<[u8]>,
+    signing_key: &SigningKey,
+    timestamp: u64,
+) -> Request<Full<Bytes>> {
+    let message = request_message(&method, &format!("/{}", path), timestamp, body.as_ref());
+    let signature = STANDARD.encode(signing_key.sign(&message).to_bytes());
+    let mut request = build(method, path, body);
+    let headers = request.headers_mut();
+    headers.insert(TIMESTAMP_HEADER, timestamp.into());
+    headers.insert(SIGNATURE_HEADER, signature.parse().unwrap());
+    request
+}
+
+pub async fn send(
+    users: Arc<Mutex<UserStorage>>,
+    file_path: &str,
+    request: Request<Full<Bytes>>,
+) -> Reply {
+    let response = handle_connection(request, users, file_path).await.unwrap();
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = response.collect().await.unwrap().to_bytes();
+    Reply {
+        status,
+        headers,
+        body,
+    }
+}
+
+pub async fn request(
+    users: Arc<Mutex<UserStorage>>,
+    file_path: &str,
+    method: Method,
+    path: &str,
+    body: &str,
+) -> (StatusCode, Value) {
+    let reply = send(users, file_path, build(method, path, body)).await;
+    (reply.status, reply.json())
+}
+
+/// Like `request`, but signed with the key of a device if one is given.
+pub async fn signed_request(
+    users: Arc<Mutex<UserStorage>>,
+    file_path: &str,
+    method: Method,
+    path: &str,
+    body: &str,
+    signing_key: Option<&SigningKey>,
+) -> (StatusCode, Value) {
+    let request = match signing_key {
+        Some(signing_key) => build_signed(method, path, body, signing_key, now()),
+        None => build(method, path, body),
+    };
+    let reply = send(users, file_path, request).await;
+    (reply.status, reply.json())
+}
+
+/// Creates a user, which has to succeed.
+pub async fn create_user(users: Arc<Mutex<UserStorage>>, file_path: &str, user: Value) {
+    let (status, body) = request(
+        users,
+        file_path,
+        Method::POST,
+        "create_user",
+        &user.to_string(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+}
+
+/// An ED25519 key as it is sent to the service.
+pub fn pub_key(signature_key: &str) -> Value {
+    json!({
+        "algorithm": "ED25519",
+        "signature_key": signature_key,
+        "exchange_key": EXCHANGE_KEY,
+    })
+}
+
+/// The base64 encoded public key of a signing key.
+pub fn public_key(signing_key: &SigningKey) -> String {
+    STANDARD.encode(signing_key.verifying_key().as_bytes())
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
@@ -0,0 +1,267 @@
+mod common;
+
+use std::{fs, path::PathBuf, sync::Arc, time::Duration};
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use common::{build, now, pub_key, send, KEY_A, KEY_B};
+use hyper::{body::Bytes, HeaderMap, Method, StatusCode};
+use jaem_user_discovery::user_data::UserStorage;
+use serde_json::json;
+use tokio::sync::Mutex;
+
+// A transparent PNG image of 1x1 pixels
+const PNG_IMAGE: &str =
+    "iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAADUlEQVR42mNkYPhfDwAChwGA60e6kgAAAABJRU5ErkJggg==";
+
+/// Sends a request with the given headers.
+async fn request(
+    users: Arc<Mutex<UserStorage>>,
+    file_path: &str,
+    method: Method,
+    path: &str,
+    headers: &[(&'static str, &str)],
+    body: Vec<u8>,
+) -> (StatusCode, HeaderMap, Bytes) {
+    let mut request = build(method, path, body);
+    for (name, value) in headers {
+        request.headers_mut().insert(*name, value.parse().unwrap());
+    }
+    let reply = send(users, file_path, request).await;
+    (reply.status, reply.headers, reply.body)
+}
+
+async fn create_user(users: Arc<Mutex<UserStorage>>, file_path: &str, uid: &str) {
+    let user = json!({ "uid": uid, "username": format!("User {}", uid), "public_keys": [] });
+    common::create_user(users, file_path, user).await;
+}
+
+fn etag(headers: &HeaderMap) -> String {
+    headers["ETag"].to_str().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn unchanged_resources_are_not_sent_again() {
+    let file_path = "temp_conditional_requests_01.json";
+    let picture_dir = "./temp_conditional_requests_01";
+    let mut storage = UserStorage::default();
+    storage.set_picture_store(PathBuf::from(picture_dir), 1024);
+    let users = Arc::new(Mutex::new(storage));
+    create_user(users.clone(), file_path, "1").await;
+
+    for path in ["user_by_uid/1", "users", "users?limit=5"] {
+        let (status, headers, _) =
+            request(users.clone(), file_path, Method::GET, path, &[], vec![]).await;
+        assert_eq!(status, StatusCode::OK);
+        let tag = etag(&headers);
+        let (status, headers, body) = request(
+            users.clone(),
+            file_path,
+            Method::GET,
+            path,
+            &[("If-None-Match", &tag)],
+            vec![],
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_MODIFIED, "{}", path);
+        assert_eq!(etag(&headers), tag);
+        assert!(body.is_empty());
+    }
+
+    // listings change when users are added
+    let (_, headers, _) =
+        request(users.clone(), file_path, Method::GET, "users", &[], vec![]).await;
+    create_user(users.clone(), file_path, "2").await;
+    let (status, _, _) = request(
+        users.clone(),
+        file_path,
+        Method::GET,
+        "users",
+        &[("If-None-Match", &etag(&headers))],
+        vec![],
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let png = STANDARD.decode(PNG_IMAGE).unwrap();
+    let (status, _, _) = request(
+        users.clone(),
+        file_path,
+        Method::PUT,
+        "user/1/picture",
+        &[],
+        png,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, headers, _) = request(
+        users.clone(),
+        file_path,
+        Method::GET,
+        "user/1/picture",
+        &[],
+        vec![],
+    )
+    .await;
+    let (status, _, _) = request(
+        users.clone(),
+        file_path,
+        Method::GET,
+        "user/1/picture",
+        &[("If-None-Match", &format!("W/{}, \"other\"", etag(&headers)))],
+        vec![],
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_MODIFIED);
+
+    // Clean up
+    fs::remove_file(file_path).unwrap();
+    fs::remove_dir_all(picture_dir).unwrap();
+}
+
+#[tokio::test]
+async fn concurrent_profile_updates_are_detected() {
+    let file_path = "temp_conditional_requests_02.json";
+    let users = Arc::new(Mutex::new(UserStorage::default()));
+    create_user(users.clone(), file_path, "1").await;
+
+    let (_, headers, _) = request(
+        users.clone(),
+        file_path,
+        Method::GET,
+        "user_by_uid/1",
+        &[],
+        vec![],
+    )
+    .await;
+    let tag = etag(&headers);
+    // the ETag is built from the version and the keys that are included
+    assert_eq!(tag, "\"1-1-0\"");
+    let (_, headers, _) = request(
+        users.clone(),
+        file_path,
+        Method::GET,
+        "user_by_uid/1?include_expired=true",
+        &[],
+        vec![],
+    )
+    .await;
+    assert_eq!(etag(&headers), "\"1-1-all\"");
+
+    let update = |description: &str| json!({ "uid": "1", "description": description });
+    let (status, headers, _) = request(
+        users.clone(),
+        file_path,
+        Method::PATCH,
+        "profile",
+        &[("If-Match", &tag)],
+        update("First device").to_string().into_bytes(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let new_tag = etag(&headers);
+    assert_ne!(new_tag, tag);
+
+    // the second device still has the old version
+    let (status, _, body) = request(
+        users.clone(),
+        file_path,
+        Method::PATCH,
+        "profile",
+        &[("If-Match", &tag)],
+        update("Second device").to_string().into_bytes(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::PRECONDITION_FAILED);
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["code"], "precondition_failed");
+
+    // the returned ETag is the one of the updated user
+    let (status, _, _) = request(
+        users.clone(),
+        file_path,
+        Method::GET,
+        "user_by_uid/1",
+        &[("If-None-Match", &new_tag)],
+        vec![],
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_MODIFIED);
+    let user = users
+        .lock()
+        .await
+        .get_entry_by_uid("1".to_string(), false)
+        .unwrap();
+    assert_eq!(user.description, "First device");
+
+    // Clean up
+    fs::remove_file(file_path).unwrap();
+}
+
+#[tokio::test]
+async fn expiring_keys_change_the_etag() {
+    let file_path = "temp_conditional_requests_03.json";
+    let users = Arc::new(Mutex::new(UserStorage::default()));
+    let mut expiring = pub_key(KEY_A);
+    expiring["expires_at"] = json!(now() + 1);
+    let user = json!({
+        "uid": "1",
+        "username": "Expiring",
+        "public_keys": [pub_key(KEY_B), expiring],
+    });
+    common::create_user(users.clone(), file_path, user).await;
+
+    let (_, headers, body) = request(
+        users.clone(),
+        file_path,
+        Method::GET,
+        "user_by_uid/1",
+        &[],
+        vec![],
+    )
+    .await;
+    let tag = etag(&headers);
+    let user: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(user["public_keys"].as_array().unwrap().len(), 2);
+
+    tokio::time::sleep(Duration::from_secs(2)).await;
+
+    // the version is the same, but the expired key is left out now
+    let (status, headers, body) = request(
+        users.clone(),
+        file_path,
+        Method::GET,
+        "user_by_uid/1",
+        &[("If-None-Match", &tag)],
+        vec![],
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_ne!(etag(&headers), tag);
+    let user: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(user["public_keys"].as_array().unwrap().len(), 1);
+
+    // both representations can be used to update the user
+    let (_, headers, _) = request(
+        users.clone(),
+        file_path,
+        Method::GET,
+        "user_by_uid/1?include_expired=true",
+        &[],
+        vec![],
+    )
+    .await;
+    let update = json!({ "uid": "1", "description": "Updated" });
+    let (status, _, _) = request(
+        users.clone(),
+        file_path,
+        Method::PATCH,
+        "profile",
+        &[("If-Match", &etag(&headers))],
+        update.to_string().into_bytes(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    // Clean up
+    fs::remove_file(file_path).unwrap();
+}
@@ -0,0 +1,203 @@
+mod common;
+
+use std::{
+    fs,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use common::{build, send};
+use hyper::{header::HeaderValue, HeaderMap, Method, StatusCode};
+use jaem_config::ContactDiscoveryLimits;
+use jaem_user_discovery::{
+    contact_discovery::{identifier_hash, IdentifierKind},
+    handle_connection::{RemoteAddr, FORWARDED_FOR_HEADER},
+    user_data::UserStorage,
+};
+use serde_json::{json, Value};
+use tokio::sync::Mutex;
+
+/// Sends a request from the given client address.
+async fn request(
+    users: Arc<Mutex<UserStorage>>,
+    file_path: &str,
+    method: Method,
+    path: &str,
+    body: &str,
+    remote_addr: &str,
+) -> (StatusCode, HeaderMap, Value) {
+    let mut request = build(method, path, body);
+    let remote_addr: SocketAddr = remote_addr.parse().unwrap();
+    request
+        .extensions_mut()
+        .insert(RemoteAddr(remote_addr.ip()));
+    let reply = send(users, file_path, request).await;
+    (reply.status, reply.headers.clone(), reply.json())
+}
+
+async fn discover(
+    users: Arc<Mutex<UserStorage>>,
+    file_path: &str,
+    hashes: &[String],
+    remote_addr: &str,
+) -> (StatusCode, HeaderMap, Value) {
+    let body = json!({ "hashes": hashes }).to_string();
+    request(
+        users,
+        file_path,
+        Method::POST,
+        "contact_discovery",
+        &body,
+        remote_addr,
+    )
+    .await
+}
+
+async fn storage_with_users(file_path: &str) -> Arc<Mutex<UserStorage>> {
+    let users = Arc::new(Mutex::new(UserStorage::default()));
+    for (uid, username, visibility) in [("1", "Alice", "public"), ("2", "Bob", "uid_only")] {
+        let body = json!({
+            "uid": uid,
+            "username": username,
+            "public_keys": [],
+            "visibility": visibility,
+        });
+        let (status, _, _) = request(
+            users.clone(),
+            file_path,
+            Method::POST,
+            "create_user",
+            &body.to_string(),
+            "127.0.0.1:1000",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+    }
+    users
+}
+
+fn truncated_hash(salt: &[u8], identifier: &str, kind: IdentifierKind, length: usize) -> String {
+    STANDARD.encode(&identifier_hash(salt, identifier, kind)[..length])
+}
+
+#[tokio::test]
+async fn find_contacts_by_hash() {
+    let file_path = "temp_contact_discovery_01.json";
+    let users = storage_with_users(file_path).await;
+
+    let (status, _, parameters) = request(
+        users.clone(),
+        file_path,
+        Method::GET,
+        "contact_discovery",
+        "",
+        "127.0.0.1:1000",
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let salt = STANDARD
+        .decode(parameters["salt"].as_str().unwrap())
+        .unwrap();
+
+    let hashes = [
+        truncated_hash(&salt, "ALICE", IdentifierKind::Username, 6),
+        truncated_hash(&salt, "Bob", IdentifierKind::Username, 6),
+        truncated_hash(&salt, "2", IdentifierKind::Uid, 6),
+        truncated_hash(&salt, "Carol", IdentifierKind::Username, 6),
+    ];
+    let (status, _, matches) = discover(users.clone(), file_path, &hashes, "127.0.0.1:1000").await;
+    assert_eq!(status, StatusCode::OK);
+    let matches = matches.as_array().unwrap();
+    // Bob can only be found by uid
+    assert_eq!(matches.len(), 2);
+    assert_eq!(matches[0]["hash"], hashes[0]);
+    assert_eq!(matches[0]["users"][0]["username"], "Alice");
+    assert_eq!(matches[1]["hash"], hashes[2]);
+    assert_eq!(matches[1]["users"][0]["username"], "Bob");
+
+    // the salt survives a restart
+    let storage = UserStorage::read_from_file(file_path).unwrap();
+    assert_eq!(
+        storage.discovery_parameters().salt,
+        parameters["salt"].as_str().unwrap()
+    );
+
+    // hashes that are too short would match too many users
+    let short = [truncated_hash(&salt, "Alice", IdentifierKind::Username, 2)];
+    let (status, _, _) = discover(users.clone(), file_path, &short, "127.0.0.1:1000").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // Clean up
+    fs::remove_file(file_path).unwrap();
+}
+
+#[tokio::test]
+async fn contact_discovery_is_rate_limited() {
+    let file_path = "temp_contact_discovery_02.json";
+    let users = storage_with_users(file_path).await;
+    users
+        .lock()
+        .await
+        .set_contact_discovery_limits(ContactDiscoveryLimits {
+            max_batch_size: 3,
+            hashes_per_hour: 4,
+            min_hash_length: 4,
+        });
+
+    let hashes: Vec<String> = (0..3u8).map(|i| STANDARD.encode([i; 8])).collect();
+    let (status, _, _) = discover(users.clone(), file_path, &hashes, "10.0.0.1:1000").await;
+    assert_eq!(status, StatusCode::OK);
+
+    let too_many: Vec<String> = (0..4u8).map(|i| STANDARD.encode([i; 8])).collect();
+    let (status, _, _) = discover(users.clone(), file_path, &too_many, "10.0.0.2:1000").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, headers, body) =
+        discover(users.clone(), file_path, &hashes[..2], "10.0.0.1:2000").await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(body["code"], "rate_limited");
+    let retry_after: u64 = headers["Retry-After"].to_str().unwrap().parse().unwrap();
+    assert!(retry_after > 0 && retry_after <= 900);
+
+    // other clients have their own budget
+    let (status, _, _) = discover(users.clone(), file_path, &hashes, "10.0.0.2:1000").await;
+    assert_eq!(status, StatusCode::OK);
+
+    // Clean up
+    fs::remove_file(file_path).unwrap();
+}
+
+#[tokio::test]
+async fn clients_are_identified_by_their_address() {
+    let file_path = "temp_contact_discovery_03.json";
+    let users = storage_with_users(file_path).await;
+
+    // requests without a client address cannot be rate limited
+    let body = json!({ "hashes": [STANDARD.encode([0; 8])] }).to_string();
+    let request = build(Method::POST, "contact_discovery", body);
+    let reply = send(users.clone(), file_path, request).await;
+    assert_eq!(reply.status, StatusCode::INTERNAL_SERVER_ERROR);
+
+    let proxy: IpAddr = "10.0.0.1".parse().unwrap();
+    let client: IpAddr = "192.0.2.7".parse().unwrap();
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        FORWARDED_FOR_HEADER,
+        HeaderValue::from_static("198.51.100.1, 192.0.2.7, 10.0.0.1"),
+    );
+    // only trusted proxies can name the client
+    assert_eq!(RemoteAddr::resolve(proxy, &headers, &[]), RemoteAddr(proxy));
+    // the last address that is not a trusted proxy is the client, earlier ones may be forged
+    assert_eq!(
+        RemoteAddr::resolve(proxy, &headers, &[proxy]),
+        RemoteAddr(client)
+    );
+    assert_eq!(
+        RemoteAddr::resolve(proxy, &HeaderMap::new(), &[proxy]),
+        RemoteAddr(proxy)
+    );
+
+    // Clean up
+    fs::remove_file(file_path).unwrap();
+}
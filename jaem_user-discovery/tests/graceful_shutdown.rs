@@ -0,0 +1,80 @@
+use std::{fs, path::PathBuf, sync::Arc, time::Duration};
+
+use jaem_config::{
+    loader::ConfigLoader, reload::LiveConfig, JaemConfig, LoggingConfig, UserDiscoveryConfig,
+};
+use jaem_user_discovery::server;
+use serde_json::json;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::oneshot,
+};
+
+const ADDRESS: &str = "127.0.0.1:8096";
+
+async fn connect() -> TcpStream {
+    for _ in 0..50 {
+        if let Ok(stream) = TcpStream::connect(ADDRESS).await {
+            return stream;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("The server did not start");
+}
+
+#[tokio::test]
+async fn requests_in_flight_finish_before_shutdown() {
+    let file_path = "temp_graceful_shutdown_01.json";
+    let key_path = "temp_graceful_shutdown_01_key";
+    let config = JaemConfig {
+        message_delivery_config: None,
+        logging: LoggingConfig::default(),
+        user_discovery_config: Some(UserDiscoveryConfig {
+            address: "127.0.0.1".to_string(),
+            port: 8096,
+            storage_path: PathBuf::from(file_path),
+            log_signing_key_path: PathBuf::from(key_path),
+            ..Default::default()
+        }),
+    };
+    let loader = ConfigLoader::new(Vec::new(), Vec::new()).unwrap();
+    let live_config = Arc::new(LiveConfig::new(loader, config));
+    let (stop, stopped) = oneshot::channel::<()>();
+    let server = tokio::spawn(server::run(live_config, async {
+        let _ = stopped.await;
+    }));
+
+    // start a request, but only send its body after the shutdown began
+    let body = json!({ "uid": "1", "username": "Alice", "public_keys": [] }).to_string();
+    let mut stream = connect().await;
+    stream
+        .write_all(
+            format!(
+                "POST /create_user HTTP/1.1\r\nHost: {}\r\nContent-Length: {}\r\n\r\n",
+                ADDRESS,
+                body.len()
+            )
+            .as_bytes(),
+        )
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    stop.send(()).unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // no new connections are accepted
+    assert!(TcpStream::connect(ADDRESS).await.is_err());
+
+    stream.write_all(body.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+
+    server.await.unwrap().unwrap();
+    assert!(fs::read_to_string(file_path).unwrap().contains("Alice"));
+
+    // Clean up
+    fs::remove_file(file_path).unwrap();
+    fs::remove_file(key_path).unwrap();
+}
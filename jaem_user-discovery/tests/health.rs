@@ -0,0 +1,90 @@
+mod common;
+
+use std::{fs, sync::Arc, time::Duration};
+
+use common::{build, request, send};
+use hyper::{Method, StatusCode};
+use jaem_common::health::ReadinessCache;
+use jaem_user_discovery::user_data::UserStorage;
+use serde_json::Value;
+use tokio::sync::Mutex;
+
+async fn probe(users: Arc<Mutex<UserStorage>>, file_path: &str, path: &str) -> (StatusCode, Value) {
+    request(users, file_path, Method::GET, path, "").await
+}
+
+#[tokio::test]
+async fn healthz_and_readyz_answer() {
+    let test_dir = "health_tests01";
+    let file_path = format!("{}/users.json", test_dir);
+    fs::create_dir_all(test_dir).unwrap();
+    let mut storage = UserStorage::default();
+    storage.set_picture_store(format!("{}/pictures", test_dir).into(), 1024);
+    let users = Arc::new(Mutex::new(storage));
+
+    let (status, body) = probe(users.clone(), &file_path, "healthz").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "ok");
+
+    let (status, body) = probe(users.clone(), &file_path, "readyz").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "ready");
+    assert_eq!(body["checks"]["user_store"], "ok");
+    assert_eq!(body["checks"]["storage"], "ok");
+    // the picture directory is created, the probe files are removed again
+    assert_eq!(
+        fs::read_dir(format!("{}/pictures", test_dir))
+            .unwrap()
+            .count(),
+        0
+    );
+
+    fs::remove_dir_all(test_dir).unwrap();
+}
+
+#[tokio::test]
+async fn not_ready_while_the_users_are_locked() {
+    let users = Arc::new(Mutex::new(UserStorage::default()));
+    let locked = users.clone();
+    let guard = locked.lock().await;
+
+    let (status, body) = tokio::time::timeout(
+        Duration::from_secs(10),
+        probe(users.clone(), "temp_health_02.json", "readyz"),
+    )
+    .await
+    .unwrap();
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["status"], "not_ready");
+    assert!(body["checks"]["user_store"]
+        .as_str()
+        .unwrap()
+        .contains("locked"));
+    // the process is alive nonetheless
+    let (status, _) = probe(users.clone(), "temp_health_02.json", "healthz").await;
+    assert_eq!(status, StatusCode::OK);
+    drop(guard);
+}
+
+#[tokio::test]
+async fn readiness_is_cached_between_probes() {
+    let users = Arc::new(Mutex::new(UserStorage::default()));
+    let cache = Arc::new(ReadinessCache::default());
+    let probe = |users: Arc<Mutex<UserStorage>>| {
+        let mut request = build(Method::GET, "readyz", "");
+        request.extensions_mut().insert(Arc::clone(&cache));
+        send(users, "temp_health_03.json", request)
+    };
+
+    let reply = probe(users.clone()).await;
+    assert_eq!(reply.status, StatusCode::OK);
+
+    // the locked users are not noticed until the cached outcome expires
+    let locked = users.clone();
+    let guard = locked.lock().await;
+    let reply = tokio::time::timeout(Duration::from_millis(500), probe(users.clone()))
+        .await
+        .unwrap();
+    assert_eq!(reply.status, StatusCode::OK);
+    drop(guard);
+}
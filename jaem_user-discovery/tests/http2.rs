@@ -0,0 +1,70 @@
+use std::{fs, path::PathBuf, sync::Arc, time::Duration};
+
+use http_body_util::{BodyExt, Empty};
+use hyper::{body::Bytes, client::conn::http2, Request, StatusCode, Version};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use jaem_config::{
+    loader::ConfigLoader, reload::LiveConfig, JaemConfig, LoggingConfig, UserDiscoveryConfig,
+};
+use jaem_user_discovery::server;
+use tokio::{net::TcpStream, sync::oneshot};
+
+const ADDRESS: &str = "127.0.0.1:8097";
+
+#[tokio::test]
+async fn plaintext_http2_is_served() {
+    let file_path = "temp_http2_01.json";
+    let key_path = "temp_http2_01_key";
+    let config = JaemConfig {
+        message_delivery_config: None,
+        logging: LoggingConfig::default(),
+        user_discovery_config: Some(UserDiscoveryConfig {
+            address: "127.0.0.1".to_string(),
+            port: 8097,
+            storage_path: PathBuf::from(file_path),
+            log_signing_key_path: PathBuf::from(key_path),
+            ..Default::default()
+        }),
+    };
+    let loader = ConfigLoader::new(Vec::new(), Vec::new()).unwrap();
+    let live_config = Arc::new(LiveConfig::new(loader, config));
+    let (stop, stopped) = oneshot::channel::<()>();
+    let server = tokio::spawn(server::run(live_config, async {
+        let _ = stopped.await;
+    }));
+
+    let mut stream = None;
+    for _ in 0..50 {
+        if let Ok(connected) = TcpStream::connect(ADDRESS).await {
+            stream = Some(connected);
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    // HTTP/2 with prior knowledge, several requests on one connection
+    let (mut sender, connection) =
+        http2::handshake(TokioExecutor::new(), TokioIo::new(stream.unwrap()))
+            .await
+            .unwrap();
+    tokio::spawn(connection);
+    for _ in 0..3 {
+        let request = Request::builder()
+            .uri(format!("http://{}/users", ADDRESS))
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+        let response = sender.send_request(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.version(), Version::HTTP_2);
+        let body = response.collect().await.unwrap().to_bytes();
+        assert_eq!(body.as_ref(), b"[]");
+    }
+
+    drop(sender);
+    stop.send(()).unwrap();
+    server.await.unwrap().unwrap();
+
+    // Clean up
+    fs::remove_file(file_path).unwrap();
+    fs::remove_file(key_path).unwrap();
+}
@@ -0,0 +1,134 @@
+use std::{fs, sync::Arc};
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use http_body_util::BodyExt;
+use hyper::{Method, Request, StatusCode};
+use jaem_user_discovery::{handle_connection::handle_connection, user_data::UserStorage};
+use serde_json::{json, Value};
+use tokio::sync::Mutex;
+
+const BASE_URI: &str = "http://127.0.0.1:8080";
+const ED25519_KEY: &str = "iojj3XQJ8ZX9UtstPLpdcspnCb8dlBIb83SIAbQPb1w=";
+const X25519_KEY: &str = "CQkJCQkJCQkJCQkJCQkJCQkJCQkJCQkJCQkJCQkJCQk=";
+const RSA_KEY: &str = "-----BEGIN PUBLIC KEY-----
+MIIBIjANBgkqhkiG9w0BAQEFAAOCAQ8AMIIBCgKCAQEAyK5UzIN5JGfTEL9qFwvA
+da4YjTPq6a2jLNVcpzoFw+81GSCazFNfrqwNWYkMlJT3C9ox9Uh38JoXE7nr/R72
+LjtFfR01impkgP/a4Odu575VCi7SaRBETQgFvQc+pS8MmeiZKd9rvqFCjQTpPi2a
+MU1rV1y9HhlEjWjafJ5wfUWiT41baOGYVE6R+twIuqldPv2vu95j+CfpYdSjFEc6
+8nUAUXr13x15/KKraA4rS2shQ4kVO8PWfhETXne89mC4UqgZtfuozBKQ7GkW7Mle
+etJRrZcT7GVhSQERLDxv0HpJyUVzecdIOwBvotudScT492GOE/K+4oloyDiqyg3F
+JwIDAQAB
+-----END PUBLIC KEY-----
+";
+const RSA_1024_KEY: &str = "MIGfMA0GCSqGSIb3DQEBAQUAA4GNADCBiQKBgQC3l4y8vuJhGNJ+VgrujgiBh4tAviM8EGDJ4P79GRrj9m1qHuRm3+tKS5Zu42SDuNyFYYx5pzYpyfep8q+G1c0we+V31SbTGGJCa+ZZ147vTYvae3xGmir2FSfaN8HKgYV86ye0yLEy5RCCtjKh9fAFKd/Noc4FSX6KSSotuBUE3wIDAQAB";
+
+async fn add_keys(
+    users: Arc<Mutex<UserStorage>>,
+    file_path: &str,
+    key: Value,
+) -> (StatusCode, Value) {
+    let body = json!({"uid": "1", "public_keys": [key]});
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(format!("{}/add_pub_key", BASE_URI))
+        .body(body.to_string())
+        .unwrap();
+    let response = handle_connection(request, users, file_path).await.unwrap();
+    let status = response.status();
+    let body = response.collect().await.unwrap().to_bytes();
+    (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+}
+
+async fn storage_with_user(file_path: &str) -> Arc<Mutex<UserStorage>> {
+    let users = Arc::new(Mutex::new(UserStorage::default()));
+    let body = json!({"uid": "1", "username": "Keys", "public_keys": []});
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(format!("{}/create_user", BASE_URI))
+        .body(body.to_string())
+        .unwrap();
+    let response = handle_connection(request, users.clone(), file_path)
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    users
+}
+
+#[tokio::test]
+async fn valid_keys_are_accepted() {
+    let file_path = "temp_key_algorithms_01.json";
+    let users = storage_with_user(file_path).await;
+
+    // an empty RSA key is treated like a missing one
+    let key = json!({"algorithm": "Ed25519", "signature_key": ED25519_KEY, "exchange_key": X25519_KEY, "rsa_key": ""});
+    assert_eq!(
+        add_keys(users.clone(), file_path, key).await.0,
+        StatusCode::OK
+    );
+
+    let p256_key = p256::SecretKey::from_slice(&[1; 32]).unwrap().public_key();
+    let key = json!({
+        "algorithm": "P-256",
+        "signature_key": STANDARD.encode(p256_key.to_sec1_bytes()),
+        "exchange_key": STANDARD.encode(p256_key.to_sec1_bytes()),
+        "rsa_key": RSA_KEY,
+    });
+    assert_eq!(
+        add_keys(users.clone(), file_path, key).await.0,
+        StatusCode::OK
+    );
+
+    let keys = &users.lock().await.users[0].public_keys;
+    assert_eq!(keys[0].exchange_algorithm().to_string(), "X25519");
+    assert!(keys[0].rsa_key.is_none());
+    assert_eq!(keys[1].exchange_algorithm().to_string(), "P-256");
+
+    // Clean up
+    fs::remove_file(file_path).unwrap();
+}
+
+#[tokio::test]
+async fn invalid_keys_are_rejected() {
+    let file_path = "temp_key_algorithms_02.json";
+    let users = storage_with_user(file_path).await;
+
+    let invalid_keys = [
+        (
+            json!({"algorithm": "DSA", "signature_key": ED25519_KEY, "exchange_key": X25519_KEY}),
+            "Unknown algorithm 'DSA', expected ED25519, X25519, P-256 or RSA-OAEP",
+        ),
+        (
+            json!({"algorithm": "ED25519", "signature_key": "test_sig", "exchange_key": X25519_KEY}),
+            "Invalid ED25519 key",
+        ),
+        (
+            json!({"algorithm": "ED25519", "signature_key": ED25519_KEY, "exchange_key": "AAAA"}),
+            "Invalid X25519 key",
+        ),
+        (
+            json!({"algorithm": "X25519", "signature_key": X25519_KEY, "exchange_key": X25519_KEY}),
+            "X25519 cannot be used for signature keys",
+        ),
+        (
+            json!({"algorithm": "ED25519", "signature_key": ED25519_KEY, "exchange_algorithm": "ED25519", "exchange_key": ED25519_KEY}),
+            "ED25519 cannot be used for exchange keys",
+        ),
+        (
+            json!({"algorithm": "ED25519", "signature_key": ED25519_KEY, "exchange_key": X25519_KEY, "rsa_key": "rsa"}),
+            "Invalid RSA-OAEP key",
+        ),
+        (
+            json!({"algorithm": "ED25519", "signature_key": ED25519_KEY, "exchange_key": X25519_KEY, "rsa_key": RSA_1024_KEY}),
+            "RSA keys need at least 2048 bits",
+        ),
+    ];
+    for (key, message) in invalid_keys {
+        let (status, body) = add_keys(users.clone(), file_path, key).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["message"], message);
+    }
+    assert!(users.lock().await.users[0].public_keys.is_empty());
+
+    // Clean up
+    fs::remove_file(file_path).unwrap();
+}
@@ -0,0 +1,127 @@
+mod common;
+
+use std::{fs, sync::Arc};
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use common::{now, pub_key, request, EXCHANGE_KEY, KEY_A, KEY_B as OTHER_KEY};
+use ed25519_dalek::{Signer, SigningKey};
+use hyper::{Method, StatusCode};
+use jaem_user_discovery::user_data::{revocation_message, UserStorage};
+use serde_json::json;
+use tokio::sync::Mutex;
+
+#[tokio::test]
+async fn revoked_keys_stay_visible() {
+    let file_path = "temp_key_revocation_01.json";
+    let users = Arc::new(Mutex::new(UserStorage::default()));
+
+    let signing_key = SigningKey::from_bytes(&[7; 32]);
+    let public_key = STANDARD.encode(signing_key.verifying_key().as_bytes());
+    let body = format!(
+        r#"{{"uid":"1", "username":"Revoker", "public_keys":[
+            {{"algorithm":"ED25519", "signature_key":"{}","exchange_key":"{}"}},
+            {{"algorithm":"ED25519", "signature_key":"{}","exchange_key":"{}","expires_at":{}}}
+        ]}}"#,
+        public_key,
+        EXCHANGE_KEY,
+        OTHER_KEY,
+        EXCHANGE_KEY,
+        now() + 3600
+    );
+    let (status, _) = request(users.clone(), file_path, Method::POST, "create_user", &body).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // a reason that was not signed by the key itself is rejected
+    let encoded_key = urlencoding::encode(&public_key).to_string();
+    let forged = format!(
+        r#"{{"reason":"compromised","signature":"{}"}}"#,
+        STANDARD.encode([0; 64])
+    );
+    let path = format!("user/1/{}", encoded_key);
+    let (status, _) = request(users.clone(), file_path, Method::DELETE, &path, &forged).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let signature = signing_key.sign(&revocation_message(&public_key, "compromised"));
+    let reason = format!(
+        r#"{{"reason":"compromised","signature":"{}"}}"#,
+        STANDARD.encode(signature.to_bytes())
+    );
+    let (status, _) = request(users.clone(), file_path, Method::DELETE, &path, &reason).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = request(users.clone(), file_path, Method::DELETE, &path, "").await;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    let (_, user) = request(users.clone(), file_path, Method::GET, "user_by_uid/1", "").await;
+    let keys = user["public_keys"].as_array().unwrap();
+    assert_eq!(keys.len(), 2);
+    assert!(keys[0]["created_at"].as_u64().is_some());
+    assert!(keys[0]["revoked_at"].as_u64().is_some());
+    assert_eq!(keys[0]["revocation_reason"]["reason"], "compromised");
+    assert!(keys[1]["revoked_at"].is_null());
+
+    // keys are found regardless of the order they were added in
+    let body = json!({"uid": "1", "public_keys": [pub_key(KEY_A)]}).to_string();
+    let (status, _) = request(users.clone(), file_path, Method::POST, "add_pub_key", &body).await;
+    assert_eq!(status, StatusCode::OK);
+    let path = format!("user/1/{}", urlencoding::encode(KEY_A));
+    let (status, _) = request(users.clone(), file_path, Method::DELETE, &path, "").await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, user) = request(users.clone(), file_path, Method::GET, "user_by_uid/1", "").await;
+    assert_eq!(user["public_keys"][2]["signature_key"], KEY_A);
+    assert!(user["public_keys"][2]["revoked_at"].as_u64().is_some());
+
+    // keys cannot be published with an expiry date in the past
+    let body = format!(
+        r#"{{"uid":"1", "public_keys":[{{"algorithm":"ED25519", "signature_key":"{}","exchange_key":"{}","expires_at":{}}}]}}"#,
+        OTHER_KEY,
+        EXCHANGE_KEY,
+        now() - 1
+    );
+    let (status, _) = request(users.clone(), file_path, Method::POST, "add_pub_key", &body).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // keys that are not valid UTF-8 once decoded are bad requests
+    let (status, _) = request(users.clone(), file_path, Method::DELETE, "user/1/%FF", "").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let path = format!("user/2/{}", urlencoding::encode(KEY_A));
+    let (status, _) = request(users.clone(), file_path, Method::DELETE, &path, "").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // Clean up
+    fs::remove_file(file_path).unwrap();
+}
+
+#[tokio::test]
+async fn expired_keys_are_filtered() {
+    let file_path = "temp_key_revocation_02.json";
+    let storage = format!(
+        r#"{{"users":[{{"uid":"1","username":"Expired","profile_picture":"null","description":"","public_keys":[
+            {{"algorithm":"ED25519","signature_key":"current","exchange_key":"ex","rsa_key":"rsa"}},
+            {{"algorithm":"ED25519","signature_key":"expired","exchange_key":"ex","rsa_key":"rsa","created_at":1,"expires_at":{}}}
+        ]}}]}}"#,
+        now() - 60
+    );
+    fs::write(file_path, storage).unwrap();
+    let users = Arc::new(Mutex::new(UserStorage::read_from_file(file_path).unwrap()));
+
+    let (_, user) = request(users.clone(), file_path, Method::GET, "user_by_uid/1", "").await;
+    assert_eq!(user["public_keys"].as_array().unwrap().len(), 1);
+    assert_eq!(user["public_keys"][0]["signature_key"], "current");
+
+    let (_, listing) = request(users.clone(), file_path, Method::GET, "users", "").await;
+    assert_eq!(listing[0]["public_keys"].as_array().unwrap().len(), 1);
+
+    let (_, user) = request(
+        users.clone(),
+        file_path,
+        Method::GET,
+        "user_by_uid/1?include_expired=true",
+        "",
+    )
+    .await;
+    assert_eq!(user["public_keys"].as_array().unwrap().len(), 2);
+
+    // Clean up
+    fs::remove_file(file_path).unwrap();
+}
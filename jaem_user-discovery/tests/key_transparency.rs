@@ -0,0 +1,222 @@
+mod common;
+
+use std::{fs, path::Path, sync::Arc};
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use common::{pub_key, request, KEY_A, KEY_B};
+use ed25519_dalek::VerifyingKey;
+use hyper::{Method, StatusCode};
+use jaem_user_discovery::{
+    transparency::{
+        decode_hash, key_set_hash, leaf_hash, load_or_create_signing_key, merkle_root,
+        verify_consistency, verify_inclusion, verify_tree_head, ConsistencyProof, Hash,
+        InclusionProof, KeyLog, KeyOperation, SignedTreeHead,
+    },
+    user_data::{PubKey, UserStorage},
+};
+use tokio::sync::Mutex;
+
+fn decode_path(path: &[String]) -> Vec<Hash> {
+    path.iter().map(|hash| decode_hash(hash).unwrap()).collect()
+}
+
+#[tokio::test]
+async fn key_changes_are_provable() {
+    let file_path = "temp_key_transparency_01.json";
+    let users = Arc::new(Mutex::new(UserStorage::default()));
+
+    for uid in ["1", "2", "3"] {
+        let body = format!(
+            r#"{{"uid":"{}", "username":"Log User", "public_keys":[{}]}}"#,
+            uid,
+            pub_key(KEY_A)
+        );
+        let (status, _) =
+            request(users.clone(), file_path, Method::POST, "create_user", &body).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    let (_, key) = request(users.clone(), file_path, Method::GET, "log/key", "").await;
+    let key: [u8; 32] = STANDARD
+        .decode(key["key"].as_str().unwrap())
+        .unwrap()
+        .try_into()
+        .unwrap();
+    let key = VerifyingKey::from_bytes(&key).unwrap();
+
+    let (_, old_head) = request(users.clone(), file_path, Method::GET, "log/tree_head", "").await;
+    let old_head: SignedTreeHead = serde_json::from_value(old_head).unwrap();
+    assert_eq!(old_head.tree_size, 3);
+    assert!(verify_tree_head(&old_head, &key));
+
+    // add and remove keys of user 2
+    let body = format!(r#"{{"uid":"2", "public_keys":[{}]}}"#, pub_key(KEY_B));
+    let (status, _) = request(users.clone(), file_path, Method::POST, "add_pub_key", &body).await;
+    assert_eq!(status, StatusCode::OK);
+    let path = format!("user/2/{}", urlencoding::encode(KEY_A));
+    let (status, _) = request(users.clone(), file_path, Method::DELETE, &path, "").await;
+    assert_eq!(status, StatusCode::OK);
+
+    // the latest entry of user 2 commits to the keys the service returns for it
+    let (_, proof) = request(users.clone(), file_path, Method::GET, "log/inclusion/2", "").await;
+    let proof: InclusionProof = serde_json::from_value(proof).unwrap();
+    assert_eq!(proof.entry.operation, KeyOperation::RevokeKey);
+    assert_eq!(proof.tree_head.tree_size, 5);
+    assert!(verify_tree_head(&proof.tree_head, &key));
+
+    let (_, user) = request(users.clone(), file_path, Method::GET, "user_by_uid/2", "").await;
+    let keys: Vec<PubKey> = serde_json::from_value(user["public_keys"].clone()).unwrap();
+    assert_eq!(proof.entry.key_set_hash, key_set_hash(&keys));
+
+    let leaf = leaf_hash(&STANDARD.decode(&proof.leaf_input).unwrap());
+    assert!(verify_inclusion(
+        &leaf,
+        proof.leaf_index,
+        proof.tree_head.tree_size,
+        &decode_path(&proof.audit_path),
+        &decode_hash(&proof.tree_head.root_hash).unwrap(),
+    ));
+
+    // the new tree head extends the old one
+    let (_, consistency) = request(
+        users.clone(),
+        file_path,
+        Method::GET,
+        "log/consistency/3/5",
+        "",
+    )
+    .await;
+    let consistency: ConsistencyProof = serde_json::from_value(consistency).unwrap();
+    assert!(verify_consistency(
+        3,
+        5,
+        &decode_hash(&old_head.root_hash).unwrap(),
+        &decode_hash(&proof.tree_head.root_hash).unwrap(),
+        &decode_path(&consistency.proof),
+    ));
+
+    let (status, _) = request(
+        users.clone(),
+        file_path,
+        Method::GET,
+        "log/consistency/5/9",
+        "",
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = request(users.clone(), file_path, Method::GET, "log/inclusion/7", "").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // Clean up
+    fs::remove_file(file_path).unwrap();
+}
+
+#[test]
+fn proofs_verify_for_all_tree_sizes() {
+    let mut log = KeyLog::default();
+    let mut roots = Vec::new();
+    for i in 0..12 {
+        log.append(&i.to_string(), KeyOperation::AddKeys, Vec::new(), &[]);
+        roots.push(decode_hash(&log.tree_head().root_hash).unwrap());
+    }
+
+    for second in 1..=12u64 {
+        let second_root = roots[second as usize - 1];
+        for first in 1..=second {
+            let proof = log.consistency_proof(first, second).unwrap();
+            assert!(verify_consistency(
+                first,
+                second,
+                &roots[first as usize - 1],
+                &second_root,
+                &decode_path(&proof.proof),
+            ));
+            // a proof must not verify against a different tree
+            if first < second {
+                assert!(!verify_consistency(
+                    first,
+                    second,
+                    &roots[first as usize],
+                    &second_root,
+                    &decode_path(&proof.proof),
+                ));
+            }
+        }
+    }
+
+    for uid in 0..12 {
+        let proof = log.inclusion_proof(&uid.to_string()).unwrap();
+        let leaf = leaf_hash(&STANDARD.decode(&proof.leaf_input).unwrap());
+        assert!(verify_inclusion(
+            &leaf,
+            proof.leaf_index,
+            12,
+            &decode_path(&proof.audit_path),
+            &roots[11],
+        ));
+        assert!(!verify_inclusion(
+            &leaf,
+            (proof.leaf_index + 1) % 12,
+            12,
+            &decode_path(&proof.audit_path),
+            &roots[11],
+        ));
+    }
+}
+
+#[test]
+fn old_leaves_keep_their_hashes() {
+    let file_path = "temp_key_transparency_02.json";
+    // entries as they were written before keys had timestamps and an optional RSA key
+    let entries = [
+        format!(
+            r#"{{"uid":"1","operation":"add_keys","keys":[{{"algorithm":"ED25519","signature_key":"{}","exchange_key":"{}","rsa_key":""}}],"key_set_hash":"00","timestamp":1700000000}}"#,
+            KEY_A, KEY_B
+        ),
+        r#"{"uid":"1","operation":"remove_key","keys":[],"key_set_hash":"01","timestamp":1700000100}"#
+            .to_string(),
+    ];
+    fs::write(
+        file_path,
+        format!(
+            r#"{{"users":[],"key_log":{{"entries":[{}]}}}}"#,
+            entries.join(",")
+        ),
+    )
+    .unwrap();
+    let root = merkle_root(&[
+        leaf_hash(entries[0].as_bytes()),
+        leaf_hash(entries[1].as_bytes()),
+    ]);
+
+    let storage = UserStorage::read_from_file(file_path).unwrap();
+    let tree_head = storage.key_log().tree_head();
+    assert_eq!(tree_head.tree_size, 2);
+    assert_eq!(decode_hash(&tree_head.root_hash).unwrap(), root);
+    let proof = storage.key_log().inclusion_proof("1").unwrap();
+    assert_eq!(
+        STANDARD.decode(&proof.leaf_input).unwrap(),
+        entries[1].as_bytes()
+    );
+
+    // saving and loading again does not re-encode the entries either
+    storage.save_to_file(file_path).unwrap();
+    let storage = UserStorage::read_from_file(file_path).unwrap();
+    let tree_head = storage.key_log().tree_head();
+    assert_eq!(decode_hash(&tree_head.root_hash).unwrap(), root);
+
+    // Clean up
+    fs::remove_file(file_path).unwrap();
+}
+
+#[test]
+fn unreadable_signing_keys_are_not_replaced() {
+    // a directory cannot be read as a file
+    let path = Path::new("./temp_key_transparency_03");
+    fs::create_dir_all(path).unwrap();
+    assert!(load_or_create_signing_key(path).is_err());
+    assert!(path.is_dir());
+
+    // Clean up
+    fs::remove_dir_all(path).unwrap();
+}
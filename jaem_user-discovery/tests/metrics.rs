@@ -0,0 +1,65 @@
+mod common;
+
+use std::{fs, sync::Arc};
+
+use common::{build, build_signed, create_user, now, pub_key, public_key, request, send};
+use ed25519_dalek::SigningKey;
+use hyper::{Method, StatusCode};
+use jaem_common::tls::AdminAccess;
+use jaem_user_discovery::user_data::UserStorage;
+use serde_json::json;
+use tokio::sync::Mutex;
+
+#[tokio::test]
+async fn requests_are_counted_per_route() {
+    let file_path = "temp_metrics_01.json";
+    let users = Arc::new(Mutex::new(UserStorage::default()));
+
+    let signing_key = SigningKey::from_bytes(&[5; 32]);
+    let public_key = public_key(&signing_key);
+    let user = json!({ "uid": "1", "username": "Metric", "public_keys": [pub_key(&public_key)] });
+    create_user(users.clone(), file_path, user).await;
+
+    let (status, _) = request(
+        users.clone(),
+        file_path,
+        Method::GET,
+        "search_users/Met",
+        "",
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = request(users.clone(), file_path, Method::GET, "does/not/exist", "").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // a request signed an hour ago has expired
+    let path = format!("prekeys/1/{}", urlencoding::encode(&public_key));
+    let prekeys = build_signed(Method::GET, &path, "", &signing_key, now() - 3600);
+    let reply = send(users.clone(), file_path, prekeys).await;
+    assert_eq!(reply.status, StatusCode::FORBIDDEN);
+
+    // clients without a certificate cannot read the metrics if client certificates are required
+    let mut denied = build(Method::GET, "metrics", "");
+    denied.extensions_mut().insert(AdminAccess::Denied);
+    let reply = send(users.clone(), file_path, denied).await;
+    assert_eq!(reply.status, StatusCode::FORBIDDEN);
+
+    let mut open = build(Method::GET, "metrics", "");
+    open.extensions_mut().insert(AdminAccess::Open);
+    let reply = send(users.clone(), file_path, open).await;
+    assert_eq!(reply.status, StatusCode::OK);
+    let text = reply.text();
+    for line in [
+        r#"jaem_users{service="user_discovery"} 1"#,
+        r#"jaem_search_duration_seconds_count{service="user_discovery"} 1"#,
+        r#"jaem_http_requests_total{method="POST",route="create_user",status="200",service="user_discovery"} 1"#,
+        r#"jaem_http_requests_total{method="GET",route="unknown",status="404",service="user_discovery"} 1"#,
+        r#"jaem_http_requests_total{method="GET",route="metrics",status="403",service="user_discovery"} 1"#,
+        r#"jaem_auth_failures_total{reason="expired",service="user_discovery"} 1"#,
+    ] {
+        assert!(text.contains(line), "{} missing in\n{}", line, text);
+    }
+
+    // Clean up
+    fs::remove_file(file_path).unwrap();
+}
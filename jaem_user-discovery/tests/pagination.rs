@@ -0,0 +1,138 @@
+use std::{fs, sync::Arc};
+
+use http_body_util::BodyExt;
+use hyper::{Method, Request, StatusCode};
+use jaem_user_discovery::{handle_connection::handle_connection, user_data::UserStorage};
+use serde_json::Value;
+use tokio::sync::Mutex;
+
+const BASE_URI: &str = "http://127.0.0.1:8080";
+
+async fn create_user(users: Arc<Mutex<UserStorage>>, file_path: &str, uid: &str, username: &str) {
+    let body = format!(
+        r#"{{"uid":"{}", "username":"{}", "public_keys":[{{"algorithm":"ED25519", "signature_key":"iojj3XQJ8ZX9UtstPLpdcspnCb8dlBIb83SIAbQPb1w=","exchange_key":"CQkJCQkJCQkJCQkJCQkJCQkJCQkJCQkJCQkJCQkJCQk="}}]}}"#,
+        uid, username
+    );
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(format!("{}/create_user", BASE_URI))
+        .body(body)
+        .unwrap();
+    let response = handle_connection(request, users, file_path).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+async fn get(users: Arc<Mutex<UserStorage>>, file_path: &str, path: &str) -> (StatusCode, Value) {
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(format!("{}/{}", BASE_URI, path))
+        .body("".to_string())
+        .unwrap();
+    let response = handle_connection(request, users, file_path).await.unwrap();
+    let status = response.status();
+    let body = response.collect().await.unwrap().to_bytes();
+    (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+}
+
+fn uids(page: &Value) -> Vec<&str> {
+    page["users"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|user| user["uid"].as_str().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn page_through_users_with_cursor() {
+    let file_path = "temp_pagination_01.json";
+    let users = Arc::new(Mutex::new(UserStorage::default()));
+    for uid in ["d", "b", "e", "a", "c"] {
+        create_user(users.clone(), file_path, uid, &format!("User {}", uid)).await;
+    }
+
+    let (status, page) = get(users.clone(), file_path, "users?limit=2").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(uids(&page), ["a", "b"]);
+    assert_eq!(page["total"], 5);
+    assert!(page["users"][0].get("id").is_none());
+
+    // users inserted before the cursor do not shift the following pages
+    create_user(users.clone(), file_path, "a0", "User a0").await;
+
+    let cursor = page["next_cursor"].as_str().unwrap();
+    let (_, page) = get(
+        users.clone(),
+        file_path,
+        &format!("users?limit=2&cursor={}", cursor),
+    )
+    .await;
+    assert_eq!(uids(&page), ["c", "d"]);
+    assert_eq!(page["total"], 6);
+
+    let cursor = page["next_cursor"].as_str().unwrap();
+    let (_, page) = get(
+        users.clone(),
+        file_path,
+        &format!("users?limit=2&cursor={}", cursor),
+    )
+    .await;
+    assert_eq!(uids(&page), ["e"]);
+    assert!(page["next_cursor"].is_null());
+
+    // the legacy path form still returns a plain list
+    let (_, page) = get(users.clone(), file_path, "users/0/2").await;
+    assert_eq!(page.as_array().unwrap().len(), 2);
+    assert_eq!(page[1]["id"], 1);
+
+    // Clean up
+    fs::remove_file(file_path).unwrap();
+}
+
+#[tokio::test]
+async fn page_through_search_results_with_cursor() {
+    let file_path = "temp_pagination_02.json";
+    let users = Arc::new(Mutex::new(UserStorage::default()));
+    for (uid, username) in [
+        ("1", "Anna"),
+        ("2", "Annabelle"),
+        ("3", "Joanna"),
+        ("4", "Annika"),
+    ] {
+        create_user(users.clone(), file_path, uid, username).await;
+    }
+
+    let (_, page) = get(users.clone(), file_path, "search_users/anna?limit=2").await;
+    // exact and prefix matches come first, "Annika" is a fuzzy match
+    assert_eq!(uids(&page), ["1", "2"]);
+    assert_eq!(page["total"], 4);
+
+    let cursor = page["next_cursor"].as_str().unwrap();
+    let (_, page) = get(
+        users.clone(),
+        file_path,
+        &format!("search_users/anna?limit=2&cursor={}", cursor),
+    )
+    .await;
+    assert_eq!(uids(&page), ["3", "4"]);
+    assert!(page["next_cursor"].is_null());
+
+    // Clean up
+    fs::remove_file(file_path).unwrap();
+}
+
+#[tokio::test]
+async fn reject_invalid_pagination_parameters() {
+    let file_path = "temp_pagination_03.json";
+    let users = Arc::new(Mutex::new(UserStorage::default()));
+
+    for path in [
+        "users?limit=0",
+        "users?limit=1000",
+        "users?cursor=not-a-cursor",
+        "search_users/anna?limit=abc",
+    ] {
+        let (status, _) = get(users.clone(), file_path, path).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", path);
+    }
+}
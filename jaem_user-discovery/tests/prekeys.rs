@@ -0,0 +1,316 @@
+mod common;
+
+use std::{fs, sync::Arc};
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use common::{
+    build, build_signed, now, pub_key, public_key, send, signed_request, Reply, EXCHANGE_KEY,
+};
+use ed25519_dalek::{Signer, SigningKey};
+use hyper::{Method, StatusCode};
+use jaem_user_discovery::{
+    handle_connection::RemoteAddr,
+    prekeys::{signed_prekey_message, ONE_TIME_PREKEYS_PER_DEVICE_PER_HOUR},
+    user_data::UserStorage,
+};
+use serde_json::json;
+use tokio::sync::Mutex;
+
+async fn create_user(users: Arc<Mutex<UserStorage>>, file_path: &str, signing_key: &SigningKey) {
+    let user = json!({
+        "uid": "1",
+        "username": "Bob",
+        "public_keys": [pub_key(&public_key(signing_key))],
+    });
+    common::create_user(users, file_path, user).await;
+}
+
+/// Fetches a prekey bundle from the given client address.
+async fn take_bundle(
+    users: Arc<Mutex<UserStorage>>,
+    file_path: &str,
+    path: &str,
+    client: &str,
+) -> Reply {
+    let mut request = build(Method::POST, path, "");
+    request
+        .extensions_mut()
+        .insert(RemoteAddr(client.parse().unwrap()));
+    send(users, file_path, request).await
+}
+
+fn upload_body(signing_key: &SigningKey, one_time_prekeys: &[u32]) -> String {
+    let signature = signing_key.sign(&signed_prekey_message(1, "signed"));
+    let one_time_prekeys: Vec<String> = one_time_prekeys
+        .iter()
+        .map(|id| format!(r#"{{"key_id":{}, "public_key":"otk{}"}}"#, id, id))
+        .collect();
+    format!(
+        r#"{{"signed_prekey":{{"key_id":1, "public_key":"signed", "signature":"{}"}},
+            "one_time_prekeys":[{}]}}"#,
+        STANDARD.encode(signature.to_bytes()),
+        one_time_prekeys.join(",")
+    )
+}
+
+#[tokio::test]
+async fn one_time_prekeys_are_consumed_once() {
+    let file_path = "temp_prekeys_01.json";
+    let users = Arc::new(Mutex::new(UserStorage::default()));
+    let signing_key = SigningKey::from_bytes(&[3; 32]);
+    create_user(users.clone(), file_path, &signing_key).await;
+
+    let encoded_key = urlencoding::encode(&public_key(&signing_key)).to_string();
+    let prekeys = format!("prekeys/1/{}", encoded_key);
+    let body = upload_body(&signing_key, &[1, 2]);
+
+    // only the owner of the device can upload prekeys
+    let other_key = SigningKey::from_bytes(&[4; 32]);
+    let (status, _) = signed_request(
+        users.clone(),
+        file_path,
+        Method::PUT,
+        &prekeys,
+        &body,
+        Some(&other_key),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, count) = signed_request(
+        users.clone(),
+        file_path,
+        Method::PUT,
+        &prekeys,
+        &body,
+        Some(&signing_key),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(count["one_time_prekeys"], 2);
+
+    let bundle_path = format!("prekey_bundle/1/{}", encoded_key);
+    for expected in ["otk1", "otk2"] {
+        let reply = take_bundle(users.clone(), file_path, &bundle_path, "192.0.2.1").await;
+        assert_eq!(reply.status, StatusCode::OK);
+        let bundle = reply.json();
+        assert_eq!(bundle["identity_key"], EXCHANGE_KEY);
+        assert_eq!(bundle["signed_prekey"]["public_key"], "signed");
+        assert_eq!(bundle["one_time_prekey"]["public_key"], expected);
+    }
+
+    // without one-time prekeys the bundle still contains the signed prekey
+    let reply = take_bundle(users.clone(), file_path, &bundle_path, "192.0.2.1").await;
+    assert_eq!(reply.status, StatusCode::OK);
+    assert!(reply.json()["one_time_prekey"].is_null());
+
+    // bundles are only handed out for a given device
+    let reply = take_bundle(users.clone(), file_path, "prekey_bundle/1", "192.0.2.1").await;
+    assert_eq!(reply.status, StatusCode::BAD_REQUEST);
+
+    let (status, _) =
+        signed_request(users.clone(), file_path, Method::GET, &prekeys, "", None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, count) = signed_request(
+        users.clone(),
+        file_path,
+        Method::GET,
+        &prekeys,
+        "",
+        Some(&signing_key),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(count["signed_prekey_id"], 1);
+    assert_eq!(count["one_time_prekeys"], 0);
+
+    // one-time prekeys are not part of the public user data
+    let (_, user) = signed_request(
+        users.clone(),
+        file_path,
+        Method::GET,
+        "user_by_uid/1",
+        "",
+        None,
+    )
+    .await;
+    assert!(user.get("prekeys").is_none());
+
+    // Clean up
+    fs::remove_file(file_path).unwrap();
+}
+
+#[tokio::test]
+async fn invalid_prekey_uploads() {
+    let file_path = "temp_prekeys_02.json";
+    let users = Arc::new(Mutex::new(UserStorage::default()));
+    let signing_key = SigningKey::from_bytes(&[5; 32]);
+    create_user(users.clone(), file_path, &signing_key).await;
+
+    let encoded_key = urlencoding::encode(&public_key(&signing_key)).to_string();
+    let prekeys = format!("prekeys/1/{}", encoded_key);
+
+    // no bundle before anything was uploaded
+    let bundle_path = format!("prekey_bundle/1/{}", encoded_key);
+    let reply = take_bundle(users.clone(), file_path, &bundle_path, "192.0.2.1").await;
+    assert_eq!(reply.status, StatusCode::NOT_FOUND);
+
+    // the signed prekey has to be signed by the device
+    let forged = upload_body(&SigningKey::from_bytes(&[6; 32]), &[1]);
+    let (status, _) = signed_request(
+        users.clone(),
+        file_path,
+        Method::PUT,
+        &prekeys,
+        &forged,
+        Some(&signing_key),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    // the first upload needs a signed prekey
+    let (status, _) = signed_request(
+        users.clone(),
+        file_path,
+        Method::PUT,
+        &prekeys,
+        r#"{"one_time_prekeys":[{"key_id":1, "public_key":"otk1"}]}"#,
+        Some(&signing_key),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let body = upload_body(&signing_key, &[1, 1]);
+    let (status, _) = signed_request(
+        users.clone(),
+        file_path,
+        Method::PUT,
+        &prekeys,
+        &body,
+        Some(&signing_key),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    // prekeys of revoked keys are dropped
+    let body = upload_body(&signing_key, &[1]);
+    let (status, _) = signed_request(
+        users.clone(),
+        file_path,
+        Method::PUT,
+        &prekeys,
+        &body,
+        Some(&signing_key),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let path = format!("user/1/{}", encoded_key);
+    let (status, _) =
+        signed_request(users.clone(), file_path, Method::DELETE, &path, "", None).await;
+    assert_eq!(status, StatusCode::OK);
+    let reply = take_bundle(users.clone(), file_path, &bundle_path, "192.0.2.1").await;
+    assert_eq!(reply.status, StatusCode::CONFLICT);
+
+    // Clean up
+    fs::remove_file(file_path).unwrap();
+}
+
+#[tokio::test]
+async fn signed_requests_cannot_be_altered_or_replayed() {
+    let file_path = "temp_prekeys_03.json";
+    let users = Arc::new(Mutex::new(UserStorage::default()));
+    let signing_key = SigningKey::from_bytes(&[7; 32]);
+    create_user(users.clone(), file_path, &signing_key).await;
+    let prekeys = format!(
+        "prekeys/1/{}",
+        urlencoding::encode(&public_key(&signing_key))
+    );
+
+    // the signature covers the body
+    let timestamp = now();
+    let mut swapped = build_signed(
+        Method::PUT,
+        &prekeys,
+        upload_body(&signing_key, &[1]),
+        &signing_key,
+        timestamp,
+    );
+    let original = build_signed(
+        Method::PUT,
+        &prekeys,
+        upload_body(&signing_key, &[2]),
+        &signing_key,
+        timestamp,
+    );
+    *swapped.headers_mut() = original.headers().clone();
+    let reply = send(users.clone(), file_path, swapped).await;
+    assert_eq!(reply.status, StatusCode::FORBIDDEN);
+
+    let reply = send(users.clone(), file_path, original).await;
+    assert_eq!(reply.status, StatusCode::OK);
+    // the same request cannot be sent again
+    let replayed = build_signed(
+        Method::PUT,
+        &prekeys,
+        upload_body(&signing_key, &[2]),
+        &signing_key,
+        timestamp,
+    );
+    let reply = send(users.clone(), file_path, replayed).await;
+    assert_eq!(reply.status, StatusCode::FORBIDDEN);
+    assert_eq!(
+        reply.json()["message"],
+        "Request signature was already used"
+    );
+
+    // Clean up
+    fs::remove_file(file_path).unwrap();
+}
+
+#[tokio::test]
+async fn prekey_bundles_are_rate_limited() {
+    let file_path = "temp_prekeys_04.json";
+    let users = Arc::new(Mutex::new(UserStorage::default()));
+    let signing_key = SigningKey::from_bytes(&[8; 32]);
+    create_user(users.clone(), file_path, &signing_key).await;
+    let encoded_key = urlencoding::encode(&public_key(&signing_key)).to_string();
+    let key_ids: Vec<u32> = (1..=ONE_TIME_PREKEYS_PER_DEVICE_PER_HOUR + 5).collect();
+    let (status, _) = signed_request(
+        users.clone(),
+        file_path,
+        Method::PUT,
+        &format!("prekeys/1/{}", encoded_key),
+        &upload_body(&signing_key, &key_ids),
+        Some(&signing_key),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    // many clients cannot drain the one-time prekeys of a device, but still get bundles
+    let bundle_path = format!("prekey_bundle/1/{}", encoded_key);
+    let mut one_time_prekeys = 0;
+    for i in 0..ONE_TIME_PREKEYS_PER_DEVICE_PER_HOUR + 5 {
+        let client = format!("192.0.2.{}", i);
+        let reply = take_bundle(users.clone(), file_path, &bundle_path, &client).await;
+        assert_eq!(reply.status, StatusCode::OK);
+        if !reply.json()["one_time_prekey"].is_null() {
+            one_time_prekeys += 1;
+        }
+    }
+    assert_eq!(one_time_prekeys, ONE_TIME_PREKEYS_PER_DEVICE_PER_HOUR);
+
+    // a single client is limited as well
+    let mut status = StatusCode::OK;
+    for _ in 0..100 {
+        status = take_bundle(users.clone(), file_path, &bundle_path, "198.51.100.1")
+            .await
+            .status;
+        if status != StatusCode::OK {
+            break;
+        }
+    }
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+
+    // Clean up
+    fs::remove_file(file_path).unwrap();
+}
@@ -0,0 +1,357 @@
+use std::{
+    fs,
+    io::{self, Cursor},
+    path::PathBuf,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use http_body_util::BodyExt;
+use hyper::{
+    body::{Body, Bytes, Frame},
+    Method, Request, StatusCode,
+};
+use jaem_user_discovery::{
+    handle_connection::handle_connection, profile_picture::MAX_PICTURE_EDGE, user_data::UserStorage,
+};
+use serde_json::Value;
+use tokio::sync::Mutex;
+
+const BASE_URI: &str = "http://127.0.0.1:8080";
+
+// A transparent PNG image of 1x1 pixels
+const PNG_IMAGE: &str =
+    "iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAADUlEQVR42mNkYPhfDwAChwGA60e6kgAAAABJRU5ErkJggg==";
+
+fn create_storage(picture_dir: &str, max_size: usize) -> Arc<Mutex<UserStorage>> {
+    let mut storage = UserStorage::default();
+    storage.set_picture_store(PathBuf::from(picture_dir), max_size);
+    Arc::new(Mutex::new(storage))
+}
+
+async fn create_user(users: Arc<Mutex<UserStorage>>, file_path: &str, uid: &str) {
+    let body = format!(
+        r#"{{"uid":"{}", "username":"Picture User", "public_keys":[{{"algorithm":"ED25519", "signature_key":"iojj3XQJ8ZX9UtstPLpdcspnCb8dlBIb83SIAbQPb1w=","exchange_key":"CQkJCQkJCQkJCQkJCQkJCQkJCQkJCQkJCQkJCQkJCQk="}}]}}"#,
+        uid
+    );
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(format!("{}/create_user", BASE_URI))
+        .body(body)
+        .unwrap();
+    let response = handle_connection(request, users, file_path).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+fn put_picture(uid: &str, picture: Vec<u8>) -> Request<http_body_util::Full<hyper::body::Bytes>> {
+    Request::builder()
+        .method(Method::PUT)
+        .uri(format!("{}/user/{}/picture", BASE_URI, uid))
+        .body(http_body_util::Full::new(picture.into()))
+        .unwrap()
+}
+
+fn get_picture(uid: &str) -> Request<String> {
+    Request::builder()
+        .method(Method::GET)
+        .uri(format!("{}/user/{}/picture", BASE_URI, uid))
+        .body("".to_string())
+        .unwrap()
+}
+
+fn get_thumbnail(uid: &str, size: u32) -> Request<String> {
+    Request::builder()
+        .method(Method::GET)
+        .uri(format!("{}/user/{}/picture/{}", BASE_URI, uid, size))
+        .body("".to_string())
+        .unwrap()
+}
+
+#[tokio::test]
+async fn upload_and_download_picture() {
+    let file_path = "temp_profile_pictures_01.json";
+    let picture_dir = "./temp_profile_pictures_01";
+    let users = create_storage(picture_dir, 1024);
+    create_user(users.clone(), file_path, "1").await;
+
+    let png = STANDARD.decode(PNG_IMAGE).unwrap();
+    let response = handle_connection(put_picture("1", png), users.clone(), file_path)
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = handle_connection(get_picture("1"), users.clone(), file_path)
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["Content-Type"], "image/png");
+    let etag = response.headers()["ETag"].to_str().unwrap().to_string();
+    let body = response.collect().await.unwrap().to_bytes();
+    let image = image::load_from_memory(&body).unwrap();
+    assert_eq!((image.width(), image.height()), (1, 1));
+
+    // listings only reference the picture
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(format!("{}/users", BASE_URI))
+        .body("".to_string())
+        .unwrap();
+    let response = handle_connection(request, users.clone(), file_path)
+        .await
+        .unwrap();
+    let body = response.collect().await.unwrap().to_bytes();
+    let json = serde_json::from_slice::<Value>(&body).unwrap();
+    assert_eq!(json[0]["profile_picture"], "/user/1/picture");
+    assert_eq!(
+        format!("\"{}\"", json[0]["profile_picture_hash"].as_str().unwrap()),
+        etag
+    );
+
+    // Clean up
+    fs::remove_file(file_path).unwrap();
+    fs::remove_dir_all(picture_dir).unwrap();
+}
+
+#[tokio::test]
+async fn reject_invalid_pictures() {
+    let file_path = "temp_profile_pictures_02.json";
+    let picture_dir = "./temp_profile_pictures_02";
+    let users = create_storage(picture_dir, 32);
+    create_user(users.clone(), file_path, "1").await;
+
+    // not an image
+    let response = handle_connection(
+        put_picture("1", b"Im an Image".to_vec()),
+        users.clone(),
+        file_path,
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // larger than the configured 32 bytes
+    let png = STANDARD.decode(PNG_IMAGE).unwrap();
+    let response = handle_connection(put_picture("1", png), users.clone(), file_path)
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    // no picture has been stored
+    let response = handle_connection(get_picture("1"), users.clone(), file_path)
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // Clean up
+    fs::remove_file(file_path).unwrap();
+}
+
+#[tokio::test]
+async fn reject_huge_pictures() {
+    let file_path = "temp_profile_pictures_04.json";
+    let picture_dir = "./temp_profile_pictures_04";
+    let users = create_storage(picture_dir, 1024 * 1024);
+    create_user(users.clone(), file_path, "1").await;
+
+    // a small file that declares a picture larger than allowed
+    let mut png = Cursor::new(Vec::new());
+    image::DynamicImage::new_luma8(MAX_PICTURE_EDGE + 1, 1)
+        .write_to(&mut png, image::ImageFormat::Png)
+        .unwrap();
+    let response = handle_connection(put_picture("1", png.into_inner()), users.clone(), file_path)
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body = response.collect().await.unwrap().to_bytes();
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    assert!(body["message"].as_str().unwrap().contains("at most"));
+
+    // Clean up
+    fs::remove_file(file_path).unwrap();
+}
+
+#[tokio::test]
+async fn invalid_pictures_leave_the_profile_unchanged() {
+    let file_path = "temp_profile_pictures_06.json";
+    let users = create_storage("./temp_profile_pictures_06", 1024);
+    create_user(users.clone(), file_path, "1").await;
+
+    let body = r#"{"uid":"1", "username":"Renamed", "description":"New", "profile_picture":"bm90IGFuIGltYWdl"}"#;
+    let request = Request::builder()
+        .method(Method::PATCH)
+        .uri(format!("{}/profile", BASE_URI))
+        .body(body.to_string())
+        .unwrap();
+    let response = handle_connection(request, users.clone(), file_path)
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let user = users
+        .lock()
+        .await
+        .get_entry_by_uid("1".to_string(), false)
+        .unwrap();
+    assert_eq!(user.username, "Picture User");
+    assert_eq!(user.description, "Hey there! Let`s have a Jaem.");
+    assert_eq!(user.version, 1);
+
+    // Clean up
+    fs::remove_file(file_path).unwrap();
+}
+
+/// A request body whose connection breaks before it was read.
+#[derive(Debug)]
+struct BrokenBody;
+
+impl Body for BrokenBody {
+    type Data = Bytes;
+    type Error = io::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, io::Error>>> {
+        Poll::Ready(Some(Err(io::Error::other("connection reset"))))
+    }
+}
+
+#[tokio::test]
+async fn unreadable_pictures_are_bad_requests() {
+    let file_path = "temp_profile_pictures_05.json";
+    let users = create_storage("./temp_profile_pictures_05", 1024);
+    create_user(users.clone(), file_path, "1").await;
+
+    let request = Request::builder()
+        .method(Method::PUT)
+        .uri(format!("{}/user/1/picture", BASE_URI))
+        .body(BrokenBody)
+        .unwrap();
+    let response = handle_connection(request, users.clone(), file_path)
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // Clean up
+    fs::remove_file(file_path).unwrap();
+}
+
+/// Creates a JPEG image of the given size with an EXIF segment containing a camera model.
+fn jpeg_with_exif(width: u32, height: u32) -> Vec<u8> {
+    let mut jpeg = Cursor::new(Vec::new());
+    image::DynamicImage::new_rgb8(width, height)
+        .write_to(&mut jpeg, image::ImageFormat::Jpeg)
+        .unwrap();
+    let jpeg = jpeg.into_inner();
+
+    // TIFF header with a single IFD entry: Model (0x0110) = "SECRETCAM"
+    let mut tiff = b"II*\0\x08\0\0\0\x01\0\x10\x01\x02\0\x0a\0\0\0\x1a\0\0\0\0\0\0\0".to_vec();
+    tiff.extend_from_slice(b"SECRETCAM\0");
+    let mut exif = b"Exif\0\0".to_vec();
+    exif.extend_from_slice(&tiff);
+
+    let mut result = jpeg[0..2].to_vec();
+    result.extend_from_slice(&[0xFF, 0xE1]);
+    result.extend_from_slice(&((exif.len() + 2) as u16).to_be_bytes());
+    result.extend_from_slice(&exif);
+    result.extend_from_slice(&jpeg[2..]);
+    result
+}
+
+#[tokio::test]
+async fn strip_metadata_and_generate_thumbnails() {
+    let file_path = "temp_profile_pictures_03.json";
+    let picture_dir = "./temp_profile_pictures_03";
+    let users = create_storage(picture_dir, 1024 * 1024);
+    create_user(users.clone(), file_path, "1").await;
+
+    let jpeg = jpeg_with_exif(400, 200);
+    assert!(jpeg.windows(9).any(|window| window == b"SECRETCAM"));
+    let response = handle_connection(put_picture("1", jpeg), users.clone(), file_path)
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = handle_connection(get_picture("1"), users.clone(), file_path)
+        .await
+        .unwrap();
+    assert_eq!(response.headers()["Content-Type"], "image/jpeg");
+    let body = response.collect().await.unwrap().to_bytes();
+    assert!(!body.windows(9).any(|window| window == b"SECRETCAM"));
+
+    for (size, expected) in [(64, (64, 32)), (256, (256, 128))] {
+        let response = handle_connection(get_thumbnail("1", size), users.clone(), file_path)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.collect().await.unwrap().to_bytes();
+        let thumbnail = image::load_from_memory(&body).unwrap();
+        assert_eq!((thumbnail.width(), thumbnail.height()), expected);
+    }
+
+    // only the predefined sizes are available
+    let response = handle_connection(get_thumbnail("1", 100), users.clone(), file_path)
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // Clean up
+    fs::remove_file(file_path).unwrap();
+    fs::remove_dir_all(picture_dir).unwrap();
+}
+
+/// Creates a PNG image of the given size.
+fn png(width: u32, height: u32) -> Vec<u8> {
+    let mut png = Cursor::new(Vec::new());
+    image::DynamicImage::new_rgb8(width, height)
+        .write_to(&mut png, image::ImageFormat::Png)
+        .unwrap();
+    png.into_inner()
+}
+
+#[tokio::test]
+async fn thumbnails_do_not_replace_pictures_of_other_users() {
+    let file_path = "temp_profile_pictures_07.json";
+    let picture_dir = "./temp_profile_pictures_07";
+    let users = create_storage(picture_dir, 1024 * 1024);
+    create_user(users.clone(), file_path, "abc").await;
+    create_user(users.clone(), file_path, "abc_64").await;
+
+    let response = handle_connection(put_picture("abc_64", png(3, 3)), users.clone(), file_path)
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let picture_size = |users: Arc<Mutex<UserStorage>>| async move {
+        let response = handle_connection(get_picture("abc_64"), users, file_path)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.collect().await.unwrap().to_bytes();
+        let image = image::load_from_memory(&body).unwrap();
+        (image.width(), image.height())
+    };
+
+    // the thumbnails of abc are written with a picture of abc_64 next to them
+    let response = handle_connection(put_picture("abc", png(2, 2)), users.clone(), file_path)
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(picture_size(users.clone()).await, (3, 3));
+
+    // changing the format removes the previous picture of abc and its thumbnails only
+    let response = handle_connection(
+        put_picture("abc", jpeg_with_exif(2, 2)),
+        users.clone(),
+        file_path,
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(picture_size(users.clone()).await, (3, 3));
+
+    // Clean up
+    fs::remove_file(file_path).unwrap();
+    fs::remove_dir_all(picture_dir).unwrap();
+}
@@ -0,0 +1,150 @@
+use std::{fs, sync::Arc};
+
+use http_body_util::BodyExt;
+use hyper::{Method, Request, StatusCode};
+use jaem_config::UsernameRules;
+use jaem_user_discovery::{handle_connection::handle_connection, user_data::UserStorage};
+use serde_json::Value;
+use tokio::sync::Mutex;
+
+const BASE_URI: &str = "http://127.0.0.1:8080";
+
+fn create_user_request(uid: &str, username: &str) -> Request<String> {
+    let body = format!(
+        r#"{{"uid":"{}", "username":"{}", "public_keys":[{{"algorithm":"ED25519", "signature_key":"iojj3XQJ8ZX9UtstPLpdcspnCb8dlBIb83SIAbQPb1w=","exchange_key":"CQkJCQkJCQkJCQkJCQkJCQkJCQkJCQkJCQkJCQkJCQk="}}]}}"#,
+        uid, username
+    );
+    Request::builder()
+        .method(Method::POST)
+        .uri(format!("{}/create_user", BASE_URI))
+        .body(body)
+        .unwrap()
+}
+
+#[tokio::test]
+async fn usernames_are_validated() {
+    let file_path = "temp_username_rules_01.json";
+    let users = Arc::new(Mutex::new(UserStorage::default()));
+
+    let too_long = "a".repeat(33);
+    // "аdmin" starts with a cyrillic "а"
+    for username in [too_long.as_str(), "admin!", "\u{0430}dmin"] {
+        let response =
+            handle_connection(create_user_request("1", username), users.clone(), file_path)
+                .await
+                .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    // full width characters are normalized to their ASCII counterparts
+    let response = handle_connection(
+        create_user_request("2", "\u{FF41}\u{FF44}\u{FF4D}\u{FF49}\u{FF4E}"),
+        users.clone(),
+        file_path,
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(users.lock().await.users[0].username, "admin");
+
+    // Clean up
+    fs::remove_file(file_path).unwrap();
+}
+
+#[tokio::test]
+async fn unique_usernames_reject_duplicates_and_confusables() {
+    let file_path = "temp_username_rules_02.json";
+    let mut storage = UserStorage::default();
+    storage.set_username_rules(UsernameRules {
+        unique: true,
+        ..UsernameRules::default()
+    });
+    let users = Arc::new(Mutex::new(storage));
+
+    let response = handle_connection(create_user_request("1", "Alice"), users.clone(), file_path)
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    for username in ["alice", "AIice", "A1ice"] {
+        let response =
+            handle_connection(create_user_request("2", username), users.clone(), file_path)
+                .await
+                .unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+    }
+
+    // the owner of a username may keep it when updating the profile
+    let request = Request::builder()
+        .method(Method::PATCH)
+        .uri(format!("{}/profile", BASE_URI))
+        .body(r#"{"uid":"1", "username":"alice"}"#.to_string())
+        .unwrap();
+    let response = handle_connection(request, users.clone(), file_path)
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // without confusable detection only the case is ignored
+    users.lock().await.set_username_rules(UsernameRules {
+        unique: true,
+        reject_confusables: false,
+        ..UsernameRules::default()
+    });
+    let response = handle_connection(create_user_request("2", "ALICE"), users.clone(), file_path)
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let response = handle_connection(create_user_request("2", "A1ice"), users.clone(), file_path)
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Clean up
+    fs::remove_file(file_path).unwrap();
+}
+
+#[tokio::test]
+async fn lookup_by_username() {
+    let file_path = "temp_username_rules_03.json";
+    let users = Arc::new(Mutex::new(UserStorage::default()));
+    handle_connection(create_user_request("1", "Bob"), users.clone(), file_path)
+        .await
+        .unwrap();
+
+    let lookup = |username: &str| {
+        Request::builder()
+            .method(Method::GET)
+            .uri(format!("{}/user_by_username/{}", BASE_URI, username))
+            .body("".to_string())
+            .unwrap()
+    };
+
+    // lookup is only possible if usernames are unique
+    let response = handle_connection(lookup("bob"), users.clone(), file_path)
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    users.lock().await.set_username_rules(UsernameRules {
+        unique: true,
+        ..UsernameRules::default()
+    });
+
+    let response = handle_connection(lookup("bob"), users.clone(), file_path)
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.collect().await.unwrap().to_bytes();
+    let json = serde_json::from_slice::<Value>(&body).unwrap();
+    assert_eq!(json["uid"].as_str().unwrap(), "1");
+
+    // a confusable username must not resolve to the real user
+    let response = handle_connection(lookup("b0b"), users.clone(), file_path)
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // Clean up
+    fs::remove_file(file_path).unwrap();
+}
@@ -0,0 +1,141 @@
+mod common;
+
+use std::{fs, sync::Arc};
+
+use common::request;
+use hyper::{Method, StatusCode};
+use jaem_config::UsernameRules;
+use jaem_user_discovery::user_data::UserStorage;
+use serde_json::{json, Value};
+use tokio::sync::Mutex;
+
+async fn create_user(users: Arc<Mutex<UserStorage>>, file_path: &str, uid: &str, visibility: &str) {
+    let user = json!({
+        "uid": uid,
+        "username": format!("Anna {}", uid),
+        "public_keys": [],
+        "visibility": visibility,
+    });
+    common::create_user(users, file_path, user).await;
+}
+
+fn uids(users: &Value) -> Vec<&str> {
+    users
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|user| user["uid"].as_str().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn hidden_users_are_not_listed_or_found() {
+    let file_path = "temp_visibility_01.json";
+    let mut storage = UserStorage::default();
+    storage.set_username_rules(UsernameRules {
+        unique: true,
+        ..UsernameRules::default()
+    });
+    let users = Arc::new(Mutex::new(storage));
+    create_user(users.clone(), file_path, "1", "public").await;
+    create_user(users.clone(), file_path, "2", "exact_username").await;
+    create_user(users.clone(), file_path, "3", "uid_only").await;
+
+    let (_, listing) = request(users.clone(), file_path, Method::GET, "users", "").await;
+    assert_eq!(uids(&listing), ["1"]);
+    let (_, page) = request(users.clone(), file_path, Method::GET, "users?limit=5", "").await;
+    assert_eq!(uids(&page["users"]), ["1"]);
+    assert_eq!(page["total"], 1);
+
+    let (_, results) = request(
+        users.clone(),
+        file_path,
+        Method::GET,
+        "search_users/anna?limit=5",
+        "",
+    )
+    .await;
+    assert_eq!(uids(&results["users"]), ["1"]);
+    assert_eq!(results["total"], 1);
+
+    // exact searches and lookups still find users that are only searchable by their username
+    let (_, page) = request(
+        users.clone(),
+        file_path,
+        Method::GET,
+        "search_users/Anna%202?limit=5",
+        "",
+    )
+    .await;
+    assert_eq!(uids(&page["users"])[0], "2");
+    let (_, page) = request(
+        users.clone(),
+        file_path,
+        Method::GET,
+        "search_users/Anna%203?limit=5",
+        "",
+    )
+    .await;
+    assert!(!uids(&page["users"]).contains(&"3"));
+    let (status, _) = request(
+        users.clone(),
+        file_path,
+        Method::GET,
+        "user_by_username/Anna%202",
+        "",
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = request(
+        users.clone(),
+        file_path,
+        Method::GET,
+        "user_by_username/Anna%203",
+        "",
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, user) = request(users.clone(), file_path, Method::GET, "user_by_uid/3", "").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(user["visibility"], "uid_only");
+
+    // users can change their visibility
+    let body = json!({"uid": "3", "visibility": "public"}).to_string();
+    let (status, _) = request(users.clone(), file_path, Method::PATCH, "profile", &body).await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, listing) = request(users.clone(), file_path, Method::GET, "users", "").await;
+    assert_eq!(uids(&listing), ["1", "3"]);
+
+    let body = json!({"uid": "3", "visibility": "hidden"}).to_string();
+    let (status, _) = request(users.clone(), file_path, Method::PATCH, "profile", &body).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // Clean up
+    fs::remove_file(file_path).unwrap();
+}
+
+#[tokio::test]
+async fn listing_can_be_disabled() {
+    let file_path = "temp_visibility_02.json";
+    let users = Arc::new(Mutex::new(UserStorage::default()));
+    create_user(users.clone(), file_path, "1", "public").await;
+    users.lock().await.set_listing_enabled(false);
+
+    for path in ["users", "users?limit=5"] {
+        let (status, body) = request(users.clone(), file_path, Method::GET, path, "").await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["code"], "forbidden");
+    }
+    let (_, results) = request(
+        users.clone(),
+        file_path,
+        Method::GET,
+        "search_users/anna",
+        "",
+    )
+    .await;
+    assert_eq!(uids(&results), ["1"]);
+
+    // Clean up
+    fs::remove_file(file_path).unwrap();
+}
@@ -0,0 +1,98 @@
+use std::{collections::BTreeMap, fmt::Display, future::Future, sync::Arc};
+
+use jaem_config::{reload::LiveConfig, JaemConfig};
+
+/// The services the server can run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Service {
+    MessageDelivery,
+    UserDiscovery,
+}
+
+impl Service {
+    pub const ALL: [Service; 2] = [Service::MessageDelivery, Service::UserDiscovery];
+
+    /// A service runs if its section is present in the configuration.
+    pub fn is_enabled(&self, config: &JaemConfig) -> bool {
+        match self {
+            Service::MessageDelivery => config.message_delivery_config.is_some(),
+            Service::UserDiscovery => config.user_discovery_config.is_some(),
+        }
+    }
+
+    /// Runs the service until `shutdown` completes or the service fails.
+    pub async fn run(
+        self,
+        live_config: Arc<LiveConfig>,
+        shutdown: impl Future<Output = ()>,
+    ) -> Result<(), anyhow::Error> {
+        match self {
+            Service::MessageDelivery => {
+                jaem_message_delivery::server::run(live_config, shutdown).await
+            }
+            Service::UserDiscovery => jaem_user_discovery::server::run(live_config, shutdown).await,
+        }
+    }
+}
+
+impl Display for Service {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Service::MessageDelivery => write!(f, "message delivery"),
+            Service::UserDiscovery => write!(f, "user discovery"),
+        }
+    }
+}
+
+/// The state of a service.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Health {
+    /// The service has no section in the configuration.
+    Disabled,
+    Running,
+    /// The service stopped after a shutdown was requested.
+    Stopped,
+    /// The service stopped on its own, with the reason.
+    Failed(String),
+}
+
+impl Display for Health {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Health::Disabled => write!(f, "disabled"),
+            Health::Running => write!(f, "running"),
+            Health::Stopped => write!(f, "stopped"),
+            Health::Failed(reason) => write!(f, "failed: {}", reason),
+        }
+    }
+}
+
+/// The health of all services. Every change is logged.
+#[derive(Debug, Default)]
+pub struct HealthReport(BTreeMap<Service, Health>);
+
+impl HealthReport {
+    pub fn set(&mut self, service: Service, health: Health) {
+        match &health {
+            Health::Failed(_) => tracing::error!(%service, %health, "Service failed"),
+            _ => tracing::info!(%service, %health, "Service health changed"),
+        }
+        self.0.insert(service, health);
+    }
+
+    pub fn any_failed(&self) -> bool {
+        self.0
+            .values()
+            .any(|health| matches!(health, Health::Failed(_)))
+    }
+}
+
+impl Display for HealthReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Health of the services:")?;
+        for (service, health) in &self.0 {
+            write!(f, "\n  {}: {}", service, health)?;
+        }
+        Ok(())
+    }
+}
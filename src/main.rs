@@ -1,3 +1,110 @@
-fn main() {
-    println!("Hello, world!");
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use health::{Health, HealthReport, Service};
+use jaem_common::{logging, shutdown::shutdown_signal};
+use jaem_config::{
+    loader::{warn_about_ignored, ConfigLoader},
+    reload::{LiveConfig, WATCH_INTERVAL},
+};
+use tokio::{sync::watch, task::JoinSet};
+
+mod health;
+
+/*
+ * Run every service that has a section in the configuration in this process. When one of them
+ * stops or the process is asked to stop, all services are shut down.
+*/
+#[tokio::main]
+async fn main() {
+    let loader = match ConfigLoader::from_env() {
+        Ok(loader) => loader,
+        Err(err) => {
+            eprintln!("Could not load the configuration: {:#}", err);
+            std::process::exit(1);
+        }
+    };
+    let (config, ignored) = match loader.load_reporting_ignored() {
+        Ok(loaded) => loaded,
+        Err(err) => {
+            eprintln!("Could not load the configuration: {:#}", err);
+            std::process::exit(1);
+        }
+    };
+    let log_level = match logging::init(&config.logging) {
+        Ok(log_level) => log_level,
+        Err(err) => {
+            eprintln!("Could not set up logging: {:#}", err);
+            std::process::exit(1);
+        }
+    };
+    warn_about_ignored(&ignored);
+    if !Service::ALL
+        .iter()
+        .any(|service| service.is_enabled(&config))
+    {
+        tracing::error!(
+            "No service is configured. Add a [message_delivery_config] or [user_discovery_config] section to {}.",
+            loader.path().display()
+        );
+        std::process::exit(1);
+    }
+
+    let live_config = Arc::new(LiveConfig::new(loader, config.clone()));
+    log_level.follow(&live_config);
+    live_config.watch(WATCH_INTERVAL);
+
+    let (shutdown_sender, shutdown_receiver) = watch::channel(false);
+    let mut health = HealthReport::default();
+    let mut services = JoinSet::new();
+    for service in Service::ALL {
+        if !service.is_enabled(&config) {
+            health.set(service, Health::Disabled);
+            continue;
+        }
+        let live_config = Arc::clone(&live_config);
+        let mut shutdown_receiver = shutdown_receiver.clone();
+        let shutdown = async move {
+            let _ = shutdown_receiver.wait_for(|shutdown| *shutdown).await;
+        };
+        // the service runs in its own task, so that a panic is reported like an error
+        services.spawn(async move {
+            let result = match tokio::spawn(service.run(live_config, shutdown)).await {
+                Ok(result) => result,
+                Err(err) => Err(anyhow!("{}", err)),
+            };
+            (service, result)
+        });
+        health.set(service, Health::Running);
+    }
+
+    let mut shutting_down = false;
+    loop {
+        let finished = tokio::select! {
+            _ = shutdown_signal(), if !shutting_down => None,
+            finished = services.join_next() => match finished {
+                Some(finished) => Some(finished.expect("Supervised services do not panic.")),
+                None => break,
+            },
+        };
+        match finished {
+            Some((service, Ok(()))) if shutting_down => health.set(service, Health::Stopped),
+            Some((service, Ok(()))) => health.set(
+                service,
+                Health::Failed("stopped without a shutdown".to_string()),
+            ),
+            Some((service, Err(err))) => health.set(service, Health::Failed(format!("{:#}", err))),
+            None => {}
+        }
+        if !shutting_down {
+            tracing::info!("Shutting down all services");
+            shutting_down = true;
+            let _ = shutdown_sender.send(true);
+        }
+    }
+
+    tracing::info!("{}", health);
+    if health.any_failed() {
+        std::process::exit(1);
+    }
 }